@@ -0,0 +1,781 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// NTSC CPU clock, the APU is clocked once per CPU cycle
+pub const CPU_CLOCK: u64 = 1_789_773;
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+const TRIANGLE_TABLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+// Noise and DMC periods in CPU cycles
+const NOISE_TABLE: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+const DMC_TABLE: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+
+// Frame counter steps in CPU cycles
+const STEP1: u32 = 7457;
+const STEP2: u32 = 14913;
+const STEP3: u32 = 22371;
+const STEP4: u32 = 29829;
+const STEP5: u32 = 37281;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Envelope{
+    start: bool,
+    looping: bool,
+    constant: bool,
+    // Constant volume or the envelope period
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope{
+    fn write(&mut self, value: u8){
+        self.looping = value&0x20 != 0;
+        self.constant = value&0x10 != 0;
+        self.volume = value&0x0F;
+    }
+
+    fn clock(&mut self){
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8{
+        if self.constant { self.volume } else { self.decay }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Length{
+    enabled: bool,
+    halt: bool,
+    counter: u8,
+}
+
+impl Length{
+    fn reload(&mut self, index: u8){
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index >> 3) as usize];
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool){
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    fn clock(&mut self){
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Pulse{
+    // Pulse 1 negates its sweep in ones' complement
+    first: bool,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    envelope: Envelope,
+    length: Length,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse{
+    fn write(&mut self, reg: u16, value: u8){
+        match reg&0x03 {
+            0 => {
+                self.duty = value >> 6;
+                self.length.halt = value&0x20 != 0;
+                self.envelope.write(value);
+            },
+            1 => {
+                self.sweep_enabled = value&0x80 != 0;
+                self.sweep_period = (value >> 4)&0x07;
+                self.sweep_negate = value&0x08 != 0;
+                self.sweep_shift = value&0x07;
+                self.sweep_reload = true;
+            },
+            2 => self.period = self.period&0x0700 | value as u16,
+            _ => {
+                self.period = self.period&0x00FF | ((value&0x07) as u16) << 8;
+                self.length.reload(value);
+                self.step = 0;
+                self.envelope.start = true;
+            },
+        }
+    }
+
+    fn target(&self) -> u16{
+        let change = self.period >> self.sweep_shift;
+        if !self.sweep_negate {
+            self.period + change
+        } else if self.first {
+            self.period.saturating_sub(change + 1)
+        } else {
+            self.period.saturating_sub(change)
+        }
+    }
+
+    fn muted(&self) -> bool{
+        self.period < 8 || self.target() > 0x07FF
+    }
+
+    // Every other CPU cycle
+    fn clock_timer(&mut self){
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1)&0x07;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_sweep(&mut self){
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8{
+        if self.length.counter == 0 || self.muted() || DUTY_TABLE[self.duty as usize][self.step as usize] == 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Triangle{
+    step: u8,
+    period: u16,
+    timer: u16,
+    length: Length,
+    // Control flag, also halts the length counter
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+}
+
+impl Triangle{
+    fn write(&mut self, reg: u16, value: u8){
+        match reg&0x03 {
+            0 => {
+                self.control = value&0x80 != 0;
+                self.length.halt = self.control;
+                self.linear_reload_value = value&0x7F;
+            },
+            1 => (),
+            2 => self.period = self.period&0x0700 | value as u16,
+            _ => {
+                self.period = self.period&0x00FF | ((value&0x07) as u16) << 8;
+                self.length.reload(value);
+                self.linear_reload = true;
+            },
+        }
+    }
+
+    // Every CPU cycle
+    fn clock_timer(&mut self){
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length.counter > 0 && self.linear_counter > 0 {
+                self.step = (self.step + 1)&0x1F;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_linear(&mut self){
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    fn output(&self) -> u8{
+        TRIANGLE_TABLE[self.step as usize]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Noise{
+    // Short mode taps bit 6 instead of bit 1
+    short: bool,
+    period: u16,
+    timer: u16,
+    shift: u16,
+    envelope: Envelope,
+    length: Length,
+}
+
+impl Noise{
+    fn write(&mut self, reg: u16, value: u8){
+        match reg&0x03 {
+            0 => {
+                self.length.halt = value&0x20 != 0;
+                self.envelope.write(value);
+            },
+            1 => (),
+            2 => {
+                self.short = value&0x80 != 0;
+                self.period = NOISE_TABLE[(value&0x0F) as usize];
+            },
+            _ => {
+                self.length.reload(value);
+                self.envelope.start = true;
+            },
+        }
+    }
+
+    // Every CPU cycle
+    fn clock_timer(&mut self){
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            let tap = if self.short { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap))&0x01;
+            self.shift = self.shift >> 1 | feedback << 14;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8{
+        if self.length.counter == 0 || self.shift&0x01 != 0 { 0 } else { self.envelope.output() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Dmc{
+    irq_enabled: bool,
+    looping: bool,
+    period: u16,
+    timer: u16,
+    level: u8,
+    sample_addr: u16,
+    sample_length: u16,
+    addr: u16,
+    remaining: u16,
+    buffer: Option<u8>,
+    shift: u8,
+    bits: u8,
+    silence: bool,
+    irq: bool,
+}
+
+impl Dmc{
+    fn write(&mut self, reg: u16, value: u8){
+        match reg&0x03 {
+            0 => {
+                self.irq_enabled = value&0x80 != 0;
+                self.looping = value&0x40 != 0;
+                self.period = DMC_TABLE[(value&0x0F) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            },
+            1 => self.level = value&0x7F,
+            2 => self.sample_addr = 0xC000 | (value as u16) << 6,
+            _ => self.sample_length = (value as u16) << 4 | 1,
+        }
+    }
+
+    fn restart(&mut self){
+        self.addr = self.sample_addr;
+        self.remaining = self.sample_length;
+    }
+
+    fn fill(&mut self, value: u8){
+        self.buffer = Some(value);
+        self.addr = if self.addr == 0xFFFF { 0x8000 } else { self.addr + 1 };
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // Every CPU cycle
+    fn clock_timer(&mut self){
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period.max(1) - 1;
+        if !self.silence {
+            if self.shift&0x01 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits = self.bits.saturating_sub(1);
+        if self.bits == 0 {
+            self.bits = 8;
+            match self.buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift = sample;
+                },
+                None => self.silence = true,
+            }
+        }
+    }
+}
+
+pub struct Apu{
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    // Frame counter
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    frame_cycle: u32,
+    odd: bool,
+    // Output samples at sample_rate, averaged over the CPU cycles of each sample
+    sample_rate: u64,
+    sample_clock: u64,
+    sum: f32,
+    count: u32,
+    samples: Vec<f32>,
+}
+
+impl Apu{
+
+    pub fn new(sample_rate: u32) -> Apu{
+        Apu{
+            pulse1: Pulse{ first: true, ..Pulse::default() },
+            pulse2: Pulse::default(),
+            triangle: Triangle::default(),
+            noise: Noise{ short: false, period: NOISE_TABLE[0], timer: 0, shift: 1, envelope: Envelope::default(), length: Length::default() },
+            dmc: Dmc{ period: DMC_TABLE[0], bits: 8, silence: true, sample_addr: 0xC000, sample_length: 1, ..Dmc::default() },
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            odd: false,
+            sample_rate: sample_rate as u64,
+            sample_clock: 0,
+            sum: 0.0,
+            count: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32{
+        self.sample_rate as u32
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32){
+        self.sample_rate = sample_rate as u64;
+        self.sample_clock = 0;
+    }
+
+    // Samples produced since the last call
+    pub fn take_samples(&mut self) -> Vec<f32>{
+        std::mem::take(&mut self.samples)
+    }
+
+    // Level of the APU IRQ line, frame counter or DMC
+    pub fn irq(&self) -> bool{
+        self.frame_irq || self.dmc.irq
+    }
+
+    // Address the DMC wants to read into its empty sample buffer
+    pub fn dmc_request(&self) -> Option<u16>{
+        (self.dmc.buffer.is_none() && self.dmc.remaining > 0).then_some(self.dmc.addr)
+    }
+
+    pub fn dmc_fill(&mut self, value: u8){
+        self.dmc.fill(value);
+    }
+
+    pub fn peek_status(&self) -> u8{
+        (self.pulse1.length.counter > 0) as u8
+            | ((self.pulse2.length.counter > 0) as u8) << 1
+            | ((self.triangle.length.counter > 0) as u8) << 2
+            | ((self.noise.length.counter > 0) as u8) << 3
+            | ((self.dmc.remaining > 0) as u8) << 4
+            | (self.frame_irq as u8) << 6
+            | (self.dmc.irq as u8) << 7
+    }
+
+    // $4015, reading acknowledges the frame IRQ
+    pub fn read_status(&mut self) -> u8{
+        let status = self.peek_status();
+        self.frame_irq = false;
+        status
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8){
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write(addr, value),
+            0x4004..=0x4007 => self.pulse2.write(addr, value),
+            0x4008..=0x400B => self.triangle.write(addr, value),
+            0x400C..=0x400F => self.noise.write(addr, value),
+            0x4010..=0x4013 => self.dmc.write(addr, value),
+            0x4015 => {
+                self.pulse1.length.set_enabled(value&0x01 != 0);
+                self.pulse2.length.set_enabled(value&0x02 != 0);
+                self.triangle.length.set_enabled(value&0x04 != 0);
+                self.noise.length.set_enabled(value&0x08 != 0);
+                if value&0x10 == 0 {
+                    self.dmc.remaining = 0;
+                } else if self.dmc.remaining == 0 {
+                    self.dmc.restart();
+                }
+                self.dmc.irq = false;
+            },
+            0x4017 => {
+                self.five_step = value&0x80 != 0;
+                self.irq_inhibit = value&0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                self.frame_cycle = 0;
+                // The 5-step mode clocks the units right away
+                if self.five_step {
+                    self.quarter_frame();
+                    self.half_frame();
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn quarter_frame(&mut self){
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear();
+    }
+
+    fn half_frame(&mut self){
+        self.pulse1.length.clock();
+        self.pulse2.length.clock();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+        self.pulse1.clock_sweep();
+        self.pulse2.clock_sweep();
+    }
+
+    fn clock_frame_counter(&mut self){
+        self.frame_cycle += 1;
+        match (self.frame_cycle, self.five_step) {
+            (STEP1, _) | (STEP3, _) => self.quarter_frame(),
+            (STEP2, _) => {
+                self.quarter_frame();
+                self.half_frame();
+            },
+            (STEP4, false) => {
+                self.quarter_frame();
+                self.half_frame();
+                if !self.irq_inhibit {
+                    self.frame_irq = true;
+                }
+                self.frame_cycle = 0;
+            },
+            (STEP5, true) => {
+                self.quarter_frame();
+                self.half_frame();
+                self.frame_cycle = 0;
+            },
+            _ => (),
+        }
+    }
+
+    // Nonlinear DAC mix of the five channels, 0.0 to about 1.0
+    pub fn output(&self) -> f32{
+        let pulse = (self.pulse1.output() + self.pulse2.output()) as f32;
+        let pulse_out = if pulse == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulse + 100.0) };
+        let tnd = self.triangle.output() as f32 / 8227.0 + self.noise.output() as f32 / 12241.0 + self.dmc.level as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 { 0.0 } else { 159.79 / (1.0 / tnd + 100.0) };
+        pulse_out + tnd_out
+    }
+
+    // Advances the APU by one CPU cycle
+    pub fn tick(&mut self){
+        self.clock_frame_counter();
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.odd {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.odd = !self.odd;
+
+        if self.sample_rate == 0 {
+            return;
+        }
+        self.sum += self.output();
+        self.count += 1;
+        self.sample_clock += self.sample_rate;
+        if self.sample_clock >= CPU_CLOCK {
+            self.sample_clock -= CPU_CLOCK;
+            self.samples.push(self.sum / self.count as f32);
+            self.sum = 0.0;
+            self.count = 0;
+        }
+    }
+}
+
+impl Snapshot for Envelope{
+    fn save(&self, w: &mut StateWriter){
+        w.bool(self.start);
+        w.bool(self.looping);
+        w.bool(self.constant);
+        w.u8(self.volume);
+        w.u8(self.divider);
+        w.u8(self.decay);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        self.start = r.bool()?;
+        self.looping = r.bool()?;
+        self.constant = r.bool()?;
+        self.volume = r.u8()?;
+        self.divider = r.u8()?;
+        self.decay = r.u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Length{
+    fn save(&self, w: &mut StateWriter){
+        w.bool(self.enabled);
+        w.bool(self.halt);
+        w.u8(self.counter);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        self.enabled = r.bool()?;
+        self.halt = r.bool()?;
+        self.counter = r.u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Pulse{
+    fn save(&self, w: &mut StateWriter){
+        w.u8(self.duty);
+        w.u8(self.step);
+        w.u16(self.period);
+        w.u16(self.timer);
+        self.envelope.save(w);
+        self.length.save(w);
+        w.bool(self.sweep_enabled);
+        w.u8(self.sweep_period);
+        w.bool(self.sweep_negate);
+        w.u8(self.sweep_shift);
+        w.u8(self.sweep_divider);
+        w.bool(self.sweep_reload);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        self.duty = r.u8()?&0x03;
+        self.step = r.u8()?&0x07;
+        let (period, timer) = (r.u16()?, r.u16()?);
+        // The timers are 11 bits, the sweep target would overflow past that
+        if period > 0x07FF || timer > 0x07FF {
+            return Err(StateError::Invalid("pulse period"));
+        }
+        self.period = period;
+        self.timer = timer;
+        self.envelope.load(r)?;
+        self.length.load(r)?;
+        self.sweep_enabled = r.bool()?;
+        self.sweep_period = r.u8()?;
+        self.sweep_negate = r.bool()?;
+        self.sweep_shift = r.u8()?&0x07;
+        self.sweep_divider = r.u8()?;
+        self.sweep_reload = r.bool()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Triangle{
+    fn save(&self, w: &mut StateWriter){
+        w.u8(self.step);
+        w.u16(self.period);
+        w.u16(self.timer);
+        self.length.save(w);
+        w.bool(self.control);
+        w.u8(self.linear_reload_value);
+        w.u8(self.linear_counter);
+        w.bool(self.linear_reload);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        self.step = r.u8()?&0x1F;
+        let (period, timer) = (r.u16()?, r.u16()?);
+        if period > 0x07FF || timer > 0x07FF {
+            return Err(StateError::Invalid("triangle period"));
+        }
+        self.period = period;
+        self.timer = timer;
+        self.length.load(r)?;
+        self.control = r.bool()?;
+        self.linear_reload_value = r.u8()?;
+        self.linear_counter = r.u8()?;
+        self.linear_reload = r.bool()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Noise{
+    fn save(&self, w: &mut StateWriter){
+        w.bool(self.short);
+        w.u16(self.period);
+        w.u16(self.timer);
+        w.u16(self.shift);
+        self.envelope.save(w);
+        self.length.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        self.short = r.bool()?;
+        self.period = r.u16()?;
+        if self.period == 0 {
+            return Err(StateError::Invalid("noise period"));
+        }
+        self.timer = r.u16()?;
+        self.shift = r.u16()?;
+        self.envelope.load(r)?;
+        self.length.load(r)?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Dmc{
+    fn save(&self, w: &mut StateWriter){
+        w.bool(self.irq_enabled);
+        w.bool(self.looping);
+        w.u16(self.period);
+        w.u16(self.timer);
+        w.u8(self.level);
+        w.u16(self.sample_addr);
+        w.u16(self.sample_length);
+        w.u16(self.addr);
+        w.u16(self.remaining);
+        w.bool(self.buffer.is_some());
+        w.u8(self.buffer.unwrap_or(0));
+        w.u8(self.shift);
+        w.u8(self.bits);
+        w.bool(self.silence);
+        w.bool(self.irq);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        self.irq_enabled = r.bool()?;
+        self.looping = r.bool()?;
+        self.period = r.u16()?;
+        self.timer = r.u16()?;
+        self.level = r.u8()?&0x7F;
+        self.sample_addr = r.u16()?;
+        self.sample_length = r.u16()?;
+        self.addr = r.u16()?;
+        self.remaining = r.u16()?;
+        let full = r.bool()?;
+        let buffer = r.u8()?;
+        self.buffer = full.then_some(buffer);
+        self.shift = r.u8()?;
+        self.bits = r.u8()?;
+        self.silence = r.bool()?;
+        self.irq = r.bool()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Apu{
+    // The output rate and the samples not yet taken belong to the host
+    fn save(&self, w: &mut StateWriter){
+        self.pulse1.save(w);
+        self.pulse2.save(w);
+        self.triangle.save(w);
+        self.noise.save(w);
+        self.dmc.save(w);
+        w.bool(self.five_step);
+        w.bool(self.irq_inhibit);
+        w.bool(self.frame_irq);
+        w.u32(self.frame_cycle);
+        w.bool(self.odd);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        self.pulse1.load(r)?;
+        self.pulse2.load(r)?;
+        self.triangle.load(r)?;
+        self.noise.load(r)?;
+        self.dmc.load(r)?;
+        self.five_step = r.bool()?;
+        self.irq_inhibit = r.bool()?;
+        self.frame_irq = r.bool()?;
+        self.frame_cycle = r.u32()?;
+        if self.frame_cycle >= STEP5 {
+            return Err(StateError::Invalid("frame counter"));
+        }
+        self.odd = r.bool()?;
+        self.sum = 0.0;
+        self.count = 0;
+        Ok(())
+    }
+}
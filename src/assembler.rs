@@ -0,0 +1,812 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::bus::Bus;
+use crate::op::{Legality, Mode, opcodes};
+use crate::processor::Variant;
+
+mod expr;
+
+use expr::{Evaluator, Token, tokenize};
+
+// Passes allowed for labels to stop moving as forward references shrink to zero page
+const MAX_PASSES: usize = 16;
+// Nesting of includes, macro calls and repeats, catches a file including itself
+const MAX_DEPTH: usize = 64;
+const MAX_REPEAT: i64 = 0x10000;
+
+// Other names for the undocumented opcodes
+const ALIASES: [(&str, &str); 13] = [
+    ("ISB", "ISC"), ("INS", "ISC"), ("DCM", "DCP"), ("AXS", "SBX"), ("AHX", "SHA"), ("SHS", "TAS"), ("LAR", "LAS"),
+    ("ASR", "ALR"), ("ANE", "XAA"), ("ASO", "SLO"), ("LSE", "SRE"), ("KIL", "JAM"), ("HLT", "JAM"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError{
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for AsmError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl Error for AsmError{}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly{
+    // Address of bytes[0], the lowest one written
+    pub origin: u16,
+    // From the lowest to the highest address written, gaps left by .org and .res are zero
+    pub bytes: Vec<u8>,
+    // Labels and constants by scoped name, e.g. main, main@loop or sound::init
+    pub symbols: BTreeMap<String, i64>,
+}
+
+impl Assembly{
+
+    // Writes the bytes at their addresses, code assembled at .org $8000 can go to load_rom instead
+    pub fn load<B: Bus>(&self, bus: &mut B){
+        for (i, &byte) in self.bytes.iter().enumerate() {
+            bus.write(self.origin.wrapping_add(i as u16), byte);
+        }
+    }
+
+    // One "name = $value" line per symbol, sorted by name
+    pub fn symbol_map(&self) -> String{
+        self.symbols.iter().map(|(name, &value)| match value {
+            0..=0xFFFF => format!("{} = ${:04X}\n", name, value),
+            _ => format!("{} = {}\n", name, value),
+        }).collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Line{
+    file: Rc<str>,
+    number: usize,
+    tokens: Vec<Token>,
+}
+
+impl Line{
+
+    fn error(&self, message: String) -> AsmError{
+        AsmError{ file: self.file.to_string(), line: self.number, message }
+    }
+
+    // Lower case directive and its arguments once any labels are skipped
+    fn directive(&self) -> Option<(String, &[Token])>{
+        let mut tokens = &self.tokens[..];
+        while let [Token::Ident(_), Token::Colon, rest @ ..] = tokens {
+            tokens = rest;
+        }
+        match tokens {
+            [Token::Ident(word), args @ ..] if word.starts_with('.') => Some((word.to_ascii_lowercase(), args)),
+            _ => None,
+        }
+    }
+}
+
+fn parse(file: &str, text: &str) -> Result<Vec<Line>, AsmError>{
+    let file: Rc<str> = file.into();
+    text.lines().enumerate().map(|(i, text)| {
+        let tokens = tokenize(text).map_err(|message| AsmError{ file: file.to_string(), line: i + 1, message })?;
+        Ok(Line{ file: file.clone(), number: i + 1, tokens })
+    }).collect()
+}
+
+// Splits at the commas outside of parentheses
+fn arguments(tokens: &[Token]) -> Vec<&[Token]>{
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen => depth -= 1,
+            Token::Comma if depth == 0 => {
+                parts.push(&tokens[start..i]);
+                start = i + 1;
+            },
+            _ => (),
+        }
+    }
+    parts.push(&tokens[start..]);
+    parts
+}
+
+// Index of the parenthesis closing the one at open
+fn closing(tokens: &[Token], open: usize) -> Option<usize>{
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            },
+            _ => (),
+        }
+    }
+    None
+}
+
+fn register(token: &Token, name: &str) -> bool{
+    matches!(token, Token::Ident(ident) if ident.eq_ignore_ascii_case(name))
+}
+
+// Operand syntax before the mnemonic decides which addressing mode it is
+enum Operand<'t>{
+    None,
+    Accumulator,
+    Immediate(&'t [Token]),
+    Direct,
+    IndexedX(&'t [Token]),
+    IndexedY(&'t [Token]),
+    Indirect(&'t [Token]),
+    IndirectX(&'t [Token]),
+    IndirectY(&'t [Token]),
+    // BBRn/BBSn zp, target
+    Pair(&'t [Token], &'t [Token]),
+}
+
+fn operand(tokens: &[Token]) -> Operand<'_>{
+    let last = tokens.len().saturating_sub(1);
+    let wrapped = |end: usize| tokens.first() == Some(&Token::LParen) && closing(tokens, 0) == Some(end);
+    match tokens {
+        [] => Operand::None,
+        [a] if register(a, "a") => Operand::Accumulator,
+        [Token::Hash, value @ ..] => Operand::Immediate(value),
+        [_, inner @ .., Token::RParen, Token::Comma, y] if register(y, "y") && wrapped(last - 2) => Operand::IndirectY(inner),
+        [_, inner @ .., Token::Comma, x, Token::RParen] if register(x, "x") && wrapped(last) => Operand::IndirectX(inner),
+        [_, inner @ .., Token::RParen] if wrapped(last) => Operand::Indirect(inner),
+        [value @ .., Token::Comma, x] if register(x, "x") => Operand::IndexedX(value),
+        [value @ .., Token::Comma, y] if register(y, "y") => Operand::IndexedY(value),
+        _ => match arguments(tokens)[..] {
+            [zp, target] => Operand::Pair(zp, target),
+            _ => Operand::Direct,
+        },
+    }
+}
+
+// Opcode for every mnemonic and mode, documented ones win where an undocumented opcode repeats them
+fn mnemonics(variant: Variant) -> HashMap<String, Vec<(Mode, u8)>>{
+    let table = opcodes(variant);
+    let mut mnemonics: HashMap<String, Vec<(Mode, u8)>> = HashMap::new();
+    for (code, op) in table.iter().enumerate() {
+        let modes = mnemonics.entry(op.mnemonic.to_string()).or_default();
+        match modes.iter_mut().find(|(mode, _)| *mode == op.mode) {
+            None => modes.push((op.mode, code as u8)),
+            Some(entry) => if table[entry.1 as usize].legality != Legality::Legal && op.legality == Legality::Legal {
+                entry.1 = code as u8;
+            },
+        }
+    }
+    mnemonics
+}
+
+struct Macro{
+    params: Vec<String>,
+    body: Vec<Line>,
+}
+
+struct Scope{
+    name: String,
+    // Label that @locals attach to outside of the scope
+    global: String,
+    anonymous: bool,
+}
+
+struct Assembler{
+    name: String,
+    variant: Variant,
+    mnemonics: HashMap<String, Vec<(Mode, u8)>>,
+    sources: HashMap<String, Rc<Vec<Line>>>,
+    binaries: HashMap<String, Rc<Vec<u8>>>,
+    // Symbols of the last pass answer forward references
+    previous: HashMap<String, i64>,
+    symbols: HashMap<String, i64>,
+    labels: HashSet<String>,
+    macros: HashMap<String, Rc<Macro>>,
+    scopes: Vec<Scope>,
+    global: String,
+    anonymous: usize,
+    depth: usize,
+    pc: u32,
+    memory: Vec<Option<u8>>,
+    // Undefined symbols and values out of range are only errors once every label is known
+    final_pass: bool,
+    unresolved: bool,
+}
+
+impl Assembler{
+
+    fn new(name: &str) -> Assembler{
+        Assembler{
+            name: name.to_string(),
+            variant: Variant::Nmos,
+            mnemonics: mnemonics(Variant::Nmos),
+            sources: HashMap::new(),
+            binaries: HashMap::new(),
+            previous: HashMap::new(),
+            symbols: HashMap::new(),
+            labels: HashSet::new(),
+            macros: HashMap::new(),
+            scopes: Vec::new(),
+            global: String::new(),
+            anonymous: 0,
+            depth: 0,
+            pc: 0,
+            memory: Vec::new(),
+            final_pass: false,
+            unresolved: false,
+        }
+    }
+
+    fn run(mut self, lines: &[Line]) -> Result<Assembly, AsmError>{
+        for _ in 0..MAX_PASSES {
+            self.pass(lines, false)?;
+            let settled = !self.unresolved && self.symbols == self.previous;
+            self.previous = std::mem::take(&mut self.symbols);
+            if settled {
+                break;
+            }
+        }
+        self.pass(lines, true)?;
+        if self.symbols != self.previous {
+            return Err(AsmError{ file: self.name, line: 0, message: format!("labels still move after {} passes", MAX_PASSES) });
+        }
+
+        let written: Vec<usize> = (0..self.memory.len()).filter(|&addr| self.memory[addr].is_some()).collect();
+        let (origin, bytes) = match (written.first(), written.last()) {
+            (Some(&first), Some(&last)) => (first as u16, self.memory[first..=last].iter().map(|byte| byte.unwrap_or(0)).collect()),
+            _ => (0, Vec::new()),
+        };
+        // Macro and repeat scopes are internal
+        let symbols = self.symbols.into_iter().filter(|(name, _)| !name.split("::").any(|part| part.starts_with("__"))).collect();
+        Ok(Assembly{ origin, bytes, symbols })
+    }
+
+    fn pass(&mut self, lines: &[Line], final_pass: bool) -> Result<(), AsmError>{
+        self.final_pass = final_pass;
+        self.unresolved = false;
+        self.set_variant(Variant::Nmos);
+        self.symbols.clear();
+        self.labels.clear();
+        self.macros.clear();
+        self.scopes.clear();
+        self.global.clear();
+        self.anonymous = 0;
+        self.depth = 0;
+        self.pc = 0;
+        self.memory = vec![None; 0x10000];
+        self.block(lines)?;
+        match self.scopes.last() {
+            Some(scope) => Err(AsmError{ file: self.name.clone(), line: 0, message: format!("scope {} is never closed", scope.name) }),
+            None => Ok(()),
+        }
+    }
+
+    fn set_variant(&mut self, variant: Variant){
+        if variant != self.variant {
+            self.variant = variant;
+            self.mnemonics = mnemonics(variant);
+        }
+    }
+
+    fn block(&mut self, lines: &[Line]) -> Result<(), AsmError>{
+        let mut i = 0;
+        while i < lines.len() {
+            i = self.statement(lines, i)?;
+        }
+        Ok(())
+    }
+
+    // Runs a nested block (include, macro or repeat) inside an optional anonymous scope
+    fn nested(&mut self, line: &Line, scope: Option<&str>, lines: &[Line], constant: Option<(&str, i64)>) -> Result<(), AsmError>{
+        if self.depth == MAX_DEPTH {
+            return Err(line.error("includes, macros or repeats nest too deep".to_string()));
+        }
+        self.depth += 1;
+        let scopes = self.scopes.len();
+        if let Some(kind) = scope {
+            let name = format!("__{}{}", kind, self.anonymous);
+            self.anonymous += 1;
+            self.enter(name, true);
+        }
+        if let Some((name, value)) = constant {
+            self.symbols.insert(self.qualified(name), value);
+        }
+        self.block(lines)?;
+        if scope.is_some() {
+            if self.scopes.len() != scopes + 1 || !self.scopes[scopes].anonymous {
+                return Err(line.error("unbalanced .scope or .proc inside the block".to_string()));
+            }
+            self.leave();
+        }
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn enter(&mut self, name: String, anonymous: bool){
+        let global = std::mem::take(&mut self.global);
+        self.scopes.push(Scope{ name, global, anonymous });
+    }
+
+    fn leave(&mut self){
+        if let Some(scope) = self.scopes.pop() {
+            self.global = scope.global;
+        }
+    }
+
+    fn qualified(&self, name: &str) -> String{
+        self.scopes.iter().map(|scope| scope.name.as_str()).chain([name]).collect::<Vec<_>>().join("::")
+    }
+
+    // A name as seen from the current scope, innermost first
+    fn candidates(&self, name: &str) -> Vec<String>{
+        let name = if name.starts_with('@') { format!("{}{}", self.global, name) } else { name.to_string() };
+        (0..=self.scopes.len()).rev().map(|depth| {
+            self.scopes[..depth].iter().map(|scope| scope.name.as_str()).chain([name.as_str()]).collect::<Vec<_>>().join("::")
+        }).collect()
+    }
+
+    fn lookup(&mut self, name: &str) -> Result<Option<i64>, String>{
+        for candidate in self.candidates(name) {
+            if let Some(&value) = self.symbols.get(&candidate).or_else(|| self.previous.get(&candidate)) {
+                return Ok(Some(value));
+            }
+        }
+        if self.final_pass {
+            return Err(format!("undefined symbol {}", name));
+        }
+        self.unresolved = true;
+        Ok(None)
+    }
+
+    fn defined(&self, name: &str) -> bool{
+        self.candidates(name).iter().any(|candidate| self.symbols.contains_key(candidate))
+    }
+
+    fn eval(&mut self, tokens: &[Token]) -> Result<Option<i64>, String>{
+        let pc = self.pc as i64;
+        Evaluator::new(tokens, pc, |name| self.lookup(name)).evaluate()
+    }
+
+    // A value the layout depends on, unknown ones count as zero until the final pass
+    fn known(&mut self, tokens: &[Token]) -> Result<i64, String>{
+        Ok(self.eval(tokens)?.unwrap_or(0))
+    }
+
+    fn check(&self, ok: bool, message: impl FnOnce() -> String) -> Result<(), String>{
+        if self.final_pass && !ok {
+            return Err(message());
+        }
+        Ok(())
+    }
+
+    fn label(&mut self, name: &str) -> Result<(), String>{
+        if name.contains("::") || name.starts_with('.') {
+            return Err(format!("bad label name {}", name));
+        }
+        let full = if name.starts_with('@') {
+            self.qualified(&format!("{}{}", self.global, name))
+        } else {
+            self.global = name.to_string();
+            self.qualified(name)
+        };
+        if !self.labels.insert(full.clone()) {
+            return Err(format!("label {} is defined twice", name));
+        }
+        self.symbols.insert(full, self.pc as i64);
+        Ok(())
+    }
+
+    // name = expr, may be assigned again unlike a label
+    fn constant(&mut self, name: &str, tokens: &[Token]) -> Result<(), String>{
+        if name.contains("::") || name.starts_with('.') {
+            return Err(format!("bad constant name {}", name));
+        }
+        let full = self.qualified(name);
+        if self.labels.contains(&full) {
+            return Err(format!("{} is already a label", name));
+        }
+        if let Some(value) = self.eval(tokens)? {
+            self.symbols.insert(full, value);
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), String>{
+        if self.pc > 0xFFFF {
+            return Err("output runs past $FFFF".to_string());
+        }
+        if self.final_pass {
+            let slot = &mut self.memory[self.pc as usize];
+            if slot.is_some() {
+                return Err(format!("${:04X} is written twice", self.pc));
+            }
+            *slot = Some(byte);
+        }
+        self.pc += 1;
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, value: Option<i64>) -> Result<(), String>{
+        let value = value.unwrap_or(0);
+        self.check((-128..=0xFF).contains(&value), || format!("{} does not fit in a byte", value))?;
+        self.emit(value as u8)
+    }
+
+    fn emit_word(&mut self, value: Option<i64>) -> Result<(), String>{
+        let value = value.unwrap_or(0);
+        self.check((-0x8000..=0xFFFF).contains(&value), || format!("{} does not fit in a word", value))?;
+        self.emit(value as u8)?;
+        self.emit((value >> 8) as u8)
+    }
+
+    fn statement(&mut self, lines: &[Line], i: usize) -> Result<usize, AsmError>{
+        let line = &lines[i];
+        let fail = |message: String| line.error(message);
+        let mut tokens = &line.tokens[..];
+        while let [Token::Ident(name), Token::Colon, rest @ ..] = tokens {
+            self.label(name).map_err(fail)?;
+            tokens = rest;
+        }
+        let (word, args) = match tokens {
+            [] => return Ok(i + 1),
+            [Token::Ident(word), args @ ..] => (word, args),
+            _ => return Err(fail("expected a label, instruction, directive or macro".to_string())),
+        };
+        if let [Token::Op("="), value @ ..] = args {
+            self.constant(word, value).map_err(fail)?;
+            return Ok(i + 1);
+        }
+        match word.to_ascii_lowercase().as_str() {
+            ".if" | ".ifdef" | ".ifndef" => self.conditional(lines, i),
+            ".macro" => {
+                let (_, end) = block_end(lines, i, &[".macro"], &[], &[".endmacro", ".endm"])?;
+                let (name, params) = match args {
+                    [Token::Ident(name), params @ ..] => (name, params),
+                    _ => return Err(fail(".macro needs a name".to_string())),
+                };
+                let params = arguments(params).into_iter().map(|param| match param {
+                    [Token::Ident(param)] => Ok(param.clone()),
+                    _ => Err(fail("macro parameters are plain names".to_string())),
+                }).collect::<Result<_, _>>()?;
+                self.macros.insert(name.clone(), Rc::new(Macro{ params, body: lines[i + 1..end].to_vec() }));
+                Ok(end + 1)
+            },
+            ".repeat" | ".rept" => {
+                let (_, end) = block_end(lines, i, &[".repeat", ".rept"], &[], &[".endrepeat", ".endrep", ".endr"])?;
+                let (count, var) = match arguments(args)[..] {
+                    [count] => (count, None),
+                    [count, [Token::Ident(var)]] => (count, Some(var.as_str())),
+                    _ => return Err(fail(".repeat takes a count and an optional counter name".to_string())),
+                };
+                let count = self.known(count).map_err(fail)?;
+                if !(0..=MAX_REPEAT).contains(&count) {
+                    return Err(fail(format!("repeat count {} is out of range", count)));
+                }
+                for n in 0..count {
+                    self.nested(line, Some("repeat"), &lines[i + 1..end], var.map(|var| (var, n)))?;
+                }
+                Ok(end + 1)
+            },
+            ".include" => {
+                let [Token::Str(file)] = args else { return Err(fail(".include takes a file name in quotes".to_string())) };
+                let path = Path::new(&*line.file).parent().unwrap_or(Path::new("")).join(file);
+                let path = path.to_string_lossy().into_owned();
+                let source = match self.sources.get(&path) {
+                    Some(source) => source.clone(),
+                    None => {
+                        let text = fs::read_to_string(&path).map_err(|err| fail(format!("cannot read {}: {}", path, err)))?;
+                        let source = Rc::new(parse(&path, &text)?);
+                        self.sources.insert(path, source.clone());
+                        source
+                    },
+                };
+                self.nested(line, None, &source, None)?;
+                Ok(i + 1)
+            },
+            directive if directive.starts_with('.') => {
+                self.directive(line, directive, args).map_err(fail)?;
+                Ok(i + 1)
+            },
+            _ => {
+                match self.macros.get(word).cloned() {
+                    Some(expansion) => self.call(line, &expansion, args)?,
+                    None => self.instruction(word, args).map_err(fail)?,
+                }
+                Ok(i + 1)
+            },
+        }
+    }
+
+    // .if/.ifdef/.ifndef with any .elseif and .else, only the branch taken is assembled
+    fn conditional(&mut self, lines: &[Line], i: usize) -> Result<usize, AsmError>{
+        let (middles, end) = block_end(lines, i, &[".if", ".ifdef", ".ifndef"], &[".elseif", ".else"], &[".endif"])?;
+        let bounds: Vec<usize> = [i].into_iter().chain(middles).chain([end]).collect();
+        for pair in bounds.windows(2) {
+            let line = &lines[pair[0]];
+            let Some((directive, args)) = line.directive() else { unreachable!() };
+            let taken = match (directive.as_str(), args) {
+                (".else", []) => true,
+                (".if" | ".elseif", args) => self.known(args).map_err(|message| line.error(message))? != 0,
+                (".ifdef", [Token::Ident(name)]) => self.defined(name),
+                (".ifndef", [Token::Ident(name)]) => !self.defined(name),
+                (directive, _) => return Err(line.error(format!("bad arguments to {}", directive))),
+            };
+            if taken {
+                self.block(&lines[pair[0] + 1..pair[1]])?;
+                break;
+            }
+        }
+        Ok(end + 1)
+    }
+
+    // Parameters are replaced token for token, missing arguments leave them empty
+    fn call(&mut self, line: &Line, expansion: &Macro, args: &[Token]) -> Result<(), AsmError>{
+        let args = arguments(args);
+        if args.len() > expansion.params.len() {
+            return Err(line.error(format!("macro takes {} arguments but got {}", expansion.params.len(), args.len())));
+        }
+        let body: Vec<Line> = expansion.body.iter().map(|body| {
+            let tokens = body.tokens.iter().flat_map(|token| {
+                let param = match token {
+                    Token::Ident(name) => expansion.params.iter().position(|param| param == name),
+                    _ => None,
+                };
+                match param {
+                    Some(n) => args.get(n).map(|arg| arg.to_vec()).unwrap_or_default(),
+                    None => vec![token.clone()],
+                }
+            }).collect();
+            Line{ tokens, ..body.clone() }
+        }).collect();
+        self.nested(line, Some("macro"), &body, None)
+    }
+
+    fn directive(&mut self, line: &Line, directive: &str, args: &[Token]) -> Result<(), String>{
+        match directive {
+            ".org" => {
+                let [value] = arguments(args)[..] else { return Err(".org takes an address".to_string()) };
+                let addr = self.known(value)?;
+                if !(0..=0xFFFF).contains(&addr) {
+                    return Err(format!("origin {} is outside $0000-$FFFF", addr));
+                }
+                self.pc = addr as u32;
+            },
+            ".byte" | ".db" => {
+                for arg in arguments(args) {
+                    match arg {
+                        [Token::Str(text)] => for c in text.chars() {
+                            let byte = u8::try_from(c).map_err(|_| format!("{:?} is not a single byte", c))?;
+                            self.emit(byte)?;
+                        },
+                        value => {
+                            let value = self.eval(value)?;
+                            self.emit_byte(value)?;
+                        },
+                    }
+                }
+            },
+            ".word" | ".dw" | ".addr" => {
+                for arg in arguments(args) {
+                    let value = self.eval(arg)?;
+                    self.emit_word(value)?;
+                }
+            },
+            ".res" | ".ds" => {
+                let (count, fill) = match arguments(args)[..] {
+                    [count] => (count, None),
+                    [count, fill] => (count, Some(fill)),
+                    _ => return Err(".res takes a count and an optional fill byte".to_string()),
+                };
+                let count = self.known(count)?;
+                if !(0..=0x10000 - self.pc as i64).contains(&count) {
+                    return Err(format!("cannot reserve {} bytes at ${:04X}", count, self.pc));
+                }
+                match fill {
+                    Some(fill) => {
+                        let fill = self.eval(fill)?;
+                        for _ in 0..count {
+                            self.emit_byte(fill)?;
+                        }
+                    },
+                    None => self.pc += count as u32,
+                }
+            },
+            ".incbin" => {
+                let parts = arguments(args);
+                let Some([Token::Str(file)]) = parts.first() else { return Err(".incbin takes a file name in quotes".to_string()) };
+                let path = Path::new(&*line.file).parent().unwrap_or(Path::new("")).join(file);
+                let path = path.to_string_lossy().into_owned();
+                let data = match self.binaries.get(&path) {
+                    Some(data) => data.clone(),
+                    None => {
+                        let data = Rc::new(fs::read(&path).map_err(|err| format!("cannot read {}: {}", path, err))?);
+                        self.binaries.insert(path, data.clone());
+                        data
+                    },
+                };
+                let offset = match parts.get(1) {
+                    Some(offset) => self.known(offset)?,
+                    None => 0,
+                };
+                let len = match parts.get(2) {
+                    Some(len) => self.known(len)?,
+                    None => data.len() as i64 - offset,
+                };
+                if parts.len() > 3 || offset < 0 || len < 0 || offset + len > data.len() as i64 {
+                    return Err(format!("{} has {} bytes, cannot include {} from {}", file, data.len(), len, offset));
+                }
+                for &byte in &data[offset as usize..(offset + len) as usize] {
+                    self.emit(byte)?;
+                }
+            },
+            ".setcpu" => {
+                let variant = match args {
+                    [Token::Str(cpu)] => match cpu.to_ascii_uppercase().as_str() {
+                        "6502" | "6502X" => Variant::Nmos,
+                        "2A03" => Variant::Nes,
+                        "65C02" | "65SC02" => Variant::Cmos,
+                        _ => return Err(format!("unknown cpu {:?}, use \"6502\", \"2A03\" or \"65C02\"", cpu)),
+                    },
+                    _ => return Err(".setcpu takes a cpu name in quotes".to_string()),
+                };
+                self.set_variant(variant);
+            },
+            ".scope" => match args {
+                [] => {
+                    let name = format!("__scope{}", self.anonymous);
+                    self.anonymous += 1;
+                    self.enter(name, false);
+                },
+                [Token::Ident(name)] if !name.contains("::") => self.enter(name.clone(), false),
+                _ => return Err(".scope takes an optional name".to_string()),
+            },
+            ".proc" => match args {
+                [Token::Ident(name)] => {
+                    self.label(name)?;
+                    self.enter(name.clone(), false);
+                },
+                _ => return Err(".proc takes a name".to_string()),
+            },
+            ".endscope" | ".endproc" => match self.scopes.last() {
+                Some(scope) if !scope.anonymous => self.leave(),
+                _ => return Err(format!("{} without a matching opener", directive)),
+            },
+            ".error" => {
+                let message = match args {
+                    [Token::Str(message)] => message.clone(),
+                    _ => "error directive".to_string(),
+                };
+                return Err(message);
+            },
+            ".elseif" | ".else" | ".endif" | ".endmacro" | ".endm" | ".endrepeat" | ".endrep" | ".endr" => {
+                return Err(format!("{} without a matching opener", directive));
+            },
+            _ => return Err(format!("unknown directive {}", directive)),
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, word: &str, args: &[Token]) -> Result<(), String>{
+        let upper = word.to_ascii_uppercase();
+        let name = ALIASES.iter().find(|(alias, _)| *alias == upper).map_or(upper.as_str(), |(_, name)| name);
+        let modes = self.mnemonics.get(name).cloned().ok_or_else(|| format!("unknown instruction or macro {}", word))?;
+        let has = |mode: Mode| modes.iter().find(|(m, _)| *m == mode).map(|&(_, code)| code);
+        let start = self.pc as i64;
+        // Zero page when the value is known to fit, absolute while it could still be a forward reference
+        let sized = |value: Option<i64>, zp: Mode, abs: Mode| match (has(zp), has(abs)) {
+            (Some(_), Some(_)) => if value.is_some_and(|value| (0..0x100).contains(&value)) { zp } else { abs },
+            (Some(_), None) => zp,
+            _ => abs,
+        };
+
+        let (mode, value) = match operand(args) {
+            Operand::None => (if has(Mode::Implied).is_some() { Mode::Implied } else { Mode::Accumulator }, None),
+            Operand::Accumulator => (Mode::Accumulator, None),
+            Operand::Immediate(value) => (Mode::Immediate, self.eval(value)?),
+            Operand::IndexedX(value) => {
+                let value = self.eval(value)?;
+                (sized(value, Mode::ZeroPageX, Mode::AbsoluteX), value)
+            },
+            Operand::IndexedY(value) => {
+                let value = self.eval(value)?;
+                (sized(value, Mode::ZeroPageY, Mode::AbsoluteY), value)
+            },
+            Operand::IndirectX(value) => {
+                let mode = if has(Mode::AbsoluteIndexedIndirect).is_some() { Mode::AbsoluteIndexedIndirect } else { Mode::IndirectX };
+                (mode, self.eval(value)?)
+            },
+            Operand::IndirectY(value) => (Mode::IndirectY, self.eval(value)?),
+            Operand::Indirect(value) if has(Mode::Indirect).is_some() => (Mode::Indirect, self.eval(value)?),
+            Operand::Indirect(value) if has(Mode::ZeroPageIndirect).is_some() => (Mode::ZeroPageIndirect, self.eval(value)?),
+            Operand::Pair(zp, target) if has(Mode::ZeroPageRelative).is_some() => {
+                let zp = self.eval(zp)?;
+                let target = self.eval(target)?;
+                let code = has(Mode::ZeroPageRelative).unwrap_or_default();
+                self.emit(code)?;
+                self.check(zp.is_none_or(|zp| (0..0x100).contains(&zp)), || format!("{} is not a zero page address", zp.unwrap_or(0)))?;
+                self.emit(zp.unwrap_or(0) as u8)?;
+                return self.branch(target, start + 3);
+            },
+            // A parenthesised expression when the instruction has no indirect mode
+            Operand::Indirect(_) | Operand::Direct | Operand::Pair(..) => {
+                let value = self.eval(args)?;
+                let mode = if has(Mode::Relative).is_some() { Mode::Relative } else { sized(value, Mode::ZeroPage, Mode::Absolute) };
+                (mode, value)
+            },
+        };
+
+        let code = has(mode).ok_or_else(|| format!("{} has no {:?} mode", upper, mode))?;
+        self.emit(code)?;
+        match mode {
+            Mode::Implied | Mode::Accumulator => Ok(()),
+            Mode::Relative => self.branch(value, start + 2),
+            Mode::Immediate => self.emit_byte(value),
+            Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY | Mode::Indirect | Mode::AbsoluteIndexedIndirect => {
+                self.check(value.is_none_or(|value| (0..0x10000).contains(&value)), || format!("{} is not an address", value.unwrap_or(0)))?;
+                self.emit_word(value)
+            },
+            _ => {
+                self.check(value.is_none_or(|value| (0..0x100).contains(&value)), || format!("{} is not a zero page address", value.unwrap_or(0)))?;
+                self.emit_byte(value)
+            },
+        }
+    }
+
+    // Branch offset from the end of the instruction
+    fn branch(&mut self, target: Option<i64>, next: i64) -> Result<(), String>{
+        let offset = target.map_or(0, |target| target - next);
+        self.check((-128..=127).contains(&offset), || format!("branch is {} bytes out of range", if offset < 0 { -128 - offset } else { offset - 127 }))?;
+        self.emit(offset as u8)
+    }
+}
+
+// Line closing the block opened at start and the lines splitting it (.elseif and .else), nested blocks skipped
+fn block_end(lines: &[Line], start: usize, open: &[&str], middle: &[&str], close: &[&str]) -> Result<(Vec<usize>, usize), AsmError>{
+    let mut depth = 0;
+    let mut middles = Vec::new();
+    for (i, line) in lines.iter().enumerate().skip(start + 1) {
+        let Some((directive, _)) = line.directive() else { continue };
+        if open.contains(&directive.as_str()) {
+            depth += 1;
+        } else if close.contains(&directive.as_str()) {
+            if depth == 0 {
+                return Ok((middles, i));
+            }
+            depth -= 1;
+        } else if depth == 0 && middle.contains(&directive.as_str()) {
+            middles.push(i);
+        }
+    }
+    Err(lines[start].error(format!("{} without {}", open[0], close[0])))
+}
+
+// Assembles source text, .include and .incbin paths are relative to the working directory
+pub fn assemble(source: &str) -> Result<Assembly, AsmError>{
+    let lines = parse("<source>", source)?;
+    Assembler::new("<source>").run(&lines)
+}
+
+// Assembles a file, .include and .incbin paths are relative to the including file
+pub fn assemble_file(path: &str) -> Result<Assembly, AsmError>{
+    let text = fs::read_to_string(path).map_err(|err| AsmError{ file: path.to_string(), line: 0, message: err.to_string() })?;
+    let lines = parse(path, &text)?;
+    Assembler::new(path).run(&lines)
+}
@@ -0,0 +1,255 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token{
+    Number(i64),
+    // Symbols, mnemonics and directives, including scope::name and @local
+    Ident(String),
+    Str(String),
+    Op(&'static str),
+    // * where a value is expected, the current address
+    Pc,
+    LParen,
+    RParen,
+    Comma,
+    Colon,
+    Hash,
+}
+
+// Longest first so << is not read as <
+const OPERATORS: [&str; 20] = ["<<", ">>", "==", "!=", "<=", ">=", "&&", "||", "+", "-", "*", "/", "%", "&", "|", "^", "~", "!", "<", ">"];
+
+fn ident_start(c: char) -> bool{
+    c.is_ascii_alphabetic() || c == '_' || c == '.' || c == '@'
+}
+
+fn ident_char(c: char) -> bool{
+    c.is_ascii_alphanumeric() || c == '_' || c == '@'
+}
+
+fn digits(text: &str, radix: u32) -> Result<i64, String>{
+    i64::from_str_radix(text, radix).map_err(|_| format!("bad number {:?}", text))
+}
+
+// Splits a source line into tokens, a ; starts a comment
+pub fn tokenize(line: &str) -> Result<Vec<Token>, String>{
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        // $, % and * are numbers where an operand is expected and operators elsewhere
+        let operand = !matches!(tokens.last(), Some(Token::Number(_) | Token::Ident(_) | Token::Str(_) | Token::Pc | Token::RParen));
+        let take = |i: &mut usize, accept: &dyn Fn(char) -> bool| {
+            let start = *i;
+            while *i < chars.len() && accept(chars[*i]) {
+                *i += 1;
+            }
+            chars[start..*i].iter().collect::<String>()
+        };
+        match c {
+            ';' => break,
+            _ if c.is_whitespace() => i += 1,
+            '0'..='9' => tokens.push(Token::Number(digits(&take(&mut i, &|c| c.is_ascii_alphanumeric()), 10)?)),
+            '$' => {
+                i += 1;
+                tokens.push(Token::Number(digits(&take(&mut i, &|c| c.is_ascii_hexdigit()), 16)?));
+            },
+            '%' if operand => {
+                i += 1;
+                tokens.push(Token::Number(digits(&take(&mut i, &|c| c == '0' || c == '1'), 2)?));
+            },
+            '*' if operand => {
+                i += 1;
+                tokens.push(Token::Pc);
+            },
+            '\'' => {
+                let value = *chars.get(i + 1).ok_or("unterminated character")?;
+                if chars.get(i + 2) != Some(&'\'') {
+                    return Err("unterminated character".to_string());
+                }
+                tokens.push(Token::Number(value as i64));
+                i += 3;
+            },
+            '"' => {
+                i += 1;
+                let mut text = String::new();
+                loop {
+                    match chars.get(i) {
+                        None => return Err("unterminated string".to_string()),
+                        Some('"') => break,
+                        Some('\\') => {
+                            text.push(match chars.get(i + 1) {
+                                Some('n') => '\n',
+                                Some('r') => '\r',
+                                Some('t') => '\t',
+                                Some('0') => '\0',
+                                Some(&other) => other,
+                                None => return Err("unterminated string".to_string()),
+                            });
+                            i += 2;
+                        },
+                        Some(&other) => {
+                            text.push(other);
+                            i += 1;
+                        },
+                    }
+                }
+                i += 1;
+                tokens.push(Token::Str(text));
+            },
+            '(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            },
+            ')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            },
+            ',' => {
+                tokens.push(Token::Comma);
+                i += 1;
+            },
+            '#' => {
+                tokens.push(Token::Hash);
+                i += 1;
+            },
+            ':' => {
+                tokens.push(Token::Colon);
+                i += 1;
+            },
+            '=' if chars.get(i + 1) != Some(&'=') => {
+                tokens.push(Token::Op("="));
+                i += 1;
+            },
+            _ if ident_start(c) => {
+                let mut name = String::from(c);
+                i += 1;
+                name.push_str(&take(&mut i, &ident_char));
+                // scope::name
+                while chars.get(i) == Some(&':') && chars.get(i + 1) == Some(&':') && chars.get(i + 2).is_some_and(|&c| ident_start(c)) {
+                    i += 2;
+                    name.push_str("::");
+                    name.push_str(&take(&mut i, &ident_char));
+                }
+                tokens.push(Token::Ident(name));
+            },
+            _ => {
+                let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+                let op = OPERATORS.iter().find(|op| rest.starts_with(**op)).ok_or_else(|| format!("unexpected character {:?}", c))?;
+                tokens.push(Token::Op(op));
+                i += op.len();
+            },
+        }
+    }
+    Ok(tokens)
+}
+
+// Binding strength of the binary operators, higher binds tighter
+fn precedence(op: &str) -> Option<u8>{
+    Some(match op {
+        "||" => 1,
+        "&&" => 2,
+        // = compares inside an expression, only name = value assigns
+        "=" | "==" | "!=" | "<" | ">" | "<=" | ">=" => 3,
+        "|" => 4,
+        "^" => 5,
+        "&" => 6,
+        "<<" | ">>" => 7,
+        "+" | "-" => 8,
+        "*" | "/" | "%" => 9,
+        _ => return None,
+    })
+}
+
+// Evaluates while parsing, None is a value that is not known yet (a forward reference)
+pub struct Evaluator<'a, F: FnMut(&str) -> Result<Option<i64>, String>>{
+    tokens: &'a [Token],
+    pos: usize,
+    pc: i64,
+    lookup: F,
+}
+
+impl<'a, F: FnMut(&str) -> Result<Option<i64>, String>> Evaluator<'a, F>{
+
+    pub fn new(tokens: &'a [Token], pc: i64, lookup: F) -> Evaluator<'a, F>{
+        Evaluator{ tokens, pos: 0, pc, lookup }
+    }
+
+    // The whole token list as one expression
+    pub fn evaluate(mut self) -> Result<Option<i64>, String>{
+        if self.tokens.is_empty() {
+            return Err("missing expression".to_string());
+        }
+        let value = self.binary(1)?;
+        match self.tokens.get(self.pos) {
+            None => Ok(value),
+            Some(token) => Err(format!("unexpected {:?} in expression", token)),
+        }
+    }
+
+    fn binary(&mut self, min: u8) -> Result<Option<i64>, String>{
+        let mut left = self.unary()?;
+        while let Some(Token::Op(op)) = self.tokens.get(self.pos) {
+            let Some(strength) = precedence(op).filter(|&strength| strength >= min) else { break };
+            self.pos += 1;
+            let right = self.binary(strength + 1)?;
+            left = match (left, right) {
+                (Some(a), Some(b)) => Some(apply(op, a, b)?),
+                _ => None,
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Option<i64>, String>{
+        let token = self.tokens.get(self.pos).cloned().ok_or("expression ends early")?;
+        self.pos += 1;
+        let value = match token {
+            Token::Number(value) => Some(value),
+            Token::Pc => Some(self.pc),
+            Token::Ident(name) => (self.lookup)(&name)?,
+            Token::LParen => {
+                let value = self.binary(1)?;
+                if self.tokens.get(self.pos) != Some(&Token::RParen) {
+                    return Err("missing )".to_string());
+                }
+                self.pos += 1;
+                value
+            },
+            Token::Op(op @ ("-" | "+" | "~" | "!" | "<" | ">")) => self.unary()?.map(|value| match op {
+                "-" => value.wrapping_neg(),
+                "~" => !value,
+                "!" => (value == 0) as i64,
+                // Low and high byte
+                "<" => value&0xFF,
+                ">" => (value >> 8)&0xFF,
+                _ => value,
+            }),
+            other => return Err(format!("unexpected {:?} in expression", other)),
+        };
+        Ok(value)
+    }
+}
+
+fn apply(op: &str, a: i64, b: i64) -> Result<i64, String>{
+    Ok(match op {
+        "||" => (a != 0 || b != 0) as i64,
+        "&&" => (a != 0 && b != 0) as i64,
+        "=" | "==" => (a == b) as i64,
+        "!=" => (a != b) as i64,
+        "<" => (a < b) as i64,
+        ">" => (a > b) as i64,
+        "<=" => (a <= b) as i64,
+        ">=" => (a >= b) as i64,
+        "|" => a | b,
+        "^" => a ^ b,
+        "&" => a&b,
+        "<<" => a.wrapping_shl(b as u32),
+        ">>" => a.wrapping_shr(b as u32),
+        "+" => a.wrapping_add(b),
+        "-" => a.wrapping_sub(b),
+        "*" => a.wrapping_mul(b),
+        "/" | "%" if b == 0 => return Err("division by zero".to_string()),
+        "/" => a.wrapping_div(b),
+        _ => a.wrapping_rem(b),
+    })
+}
@@ -0,0 +1,196 @@
+use emulator_6502::{bus::Bus, memory::Memory, processor::{Event, Processor, Variant}};
+
+const BRK_HANDLER: u16 = 0x9000;
+const NMI_HANDLER: u16 = 0xA000;
+
+// BRK with its padding byte at $8000 followed by CLI and NOPs, both handlers are a lone RTI
+fn setup() -> (Processor, Memory) {
+    let mut mem = Memory::new();
+    mem.write(0x8000, 0x00);
+    mem.write(0x8001, 0xFF);
+    mem.write(0x8002, 0x58);
+    mem.write(0x8003, 0xEA);
+    mem.write(0x8004, 0xEA);
+    mem.write(BRK_HANDLER, 0x40);
+    mem.write(NMI_HANDLER, 0x40);
+    mem.write(0xFFFA, NMI_HANDLER as u8);
+    mem.write(0xFFFB, (NMI_HANDLER >> 8) as u8);
+    mem.write(0xFFFE, BRK_HANDLER as u8);
+    mem.write(0xFFFF, (BRK_HANDLER >> 8) as u8);
+    (Processor::new(), mem)
+}
+
+fn brk_jumps_through_vector() {
+    let (mut cpu, mut mem) = setup();
+    cpu.step(&mut mem).unwrap();
+    assert_eq!(cpu.pc, BRK_HANDLER, "BRK: PC");
+    assert_eq!(cpu.cycles, 7, "BRK: cycles");
+    assert_eq!(cpu.s, 0xFC, "BRK: SP");
+    assert_eq!(mem.read(0x01FF), 0x80, "BRK: pushed PCH");
+    assert_eq!(mem.read(0x01FE), 0x02, "BRK: pushed PCL");
+    assert_eq!(mem.read(0x01FD), 0x30, "BRK: pushed P");
+    assert_eq!(cpu.p & 0x04, 0x04, "BRK: I flag");
+
+    cpu.step(&mut mem).unwrap();
+    assert_eq!(cpu.pc, 0x8002, "RTI after BRK: PC");
+    assert_eq!(cpu.s, 0xFF, "RTI after BRK: SP");
+    assert_eq!(cpu.p, 0x20, "RTI after BRK: P");
+}
+
+fn brk_ignores_i_flag() {
+    let (mut cpu, mut mem) = setup();
+    cpu.p = 0x04;
+    cpu.step(&mut mem).unwrap();
+    assert_eq!(cpu.pc, BRK_HANDLER, "BRK with I set: PC");
+    assert_eq!(mem.read(0x01FD), 0x34, "BRK with I set: pushed P");
+}
+
+fn nmi_before_brk() {
+    let (mut cpu, mut mem) = setup();
+    cpu.nmi();
+    cpu.step(&mut mem).unwrap();
+    assert_eq!(cpu.pc, NMI_HANDLER, "NMI: PC");
+    assert_eq!(cpu.cycles, 7, "NMI: cycles");
+    assert_eq!(mem.read(0x01FD), 0x20, "NMI: pushed P");
+    assert!(!cpu.nmi_pending(), "NMI: still pending");
+
+    cpu.step(&mut mem).unwrap();
+    assert_eq!(cpu.pc, 0x8000, "RTI after NMI: PC");
+    cpu.step(&mut mem).unwrap();
+    assert_eq!(cpu.pc, BRK_HANDLER, "BRK after NMI: PC");
+}
+
+fn irq_respects_i_flag() {
+    let (mut cpu, mut mem) = setup();
+    cpu.pc = 0x8002;
+    cpu.p = 0x04;
+    cpu.step(&mut mem).unwrap();
+    assert_eq!(cpu.pc, 0x8003, "CLI: PC");
+    cpu.irq(true);
+    cpu.step(&mut mem).unwrap();
+    assert_eq!(cpu.pc, 0x8004, "IRQ delayed one instruction after CLI: PC");
+    cpu.step(&mut mem).unwrap();
+    assert_eq!(cpu.pc, BRK_HANDLER, "IRQ: PC");
+    assert_eq!(mem.read(0x01FD), 0x20, "IRQ: pushed P");
+}
+
+// Ticks until the instruction or interrupt sequence in progress finishes
+fn finish(cpu: &mut Processor, mem: &mut Memory) -> Event {
+    loop {
+        if let Some(info) = cpu.tick(mem).unwrap() {
+            return info.event;
+        }
+    }
+}
+
+fn nmi_hijacks_brk() {
+    let (mut cpu, mut mem) = setup();
+    for _ in 0..4 {
+        cpu.tick(&mut mem).unwrap();
+    }
+    cpu.nmi();
+    finish(&mut cpu, &mut mem);
+    assert_eq!(cpu.pc, NMI_HANDLER, "Hijacked BRK: PC");
+    assert_eq!(mem.read(0x01FD), 0x30, "Hijacked BRK: pushed P");
+    assert!(!cpu.nmi_pending(), "Hijacked BRK: NMI still pending");
+}
+
+fn nmi_after_brk_vector_fetch() {
+    let (mut cpu, mut mem) = setup();
+    for _ in 0..6 {
+        cpu.tick(&mut mem).unwrap();
+    }
+    cpu.nmi();
+    finish(&mut cpu, &mut mem);
+    assert_eq!(cpu.pc, BRK_HANDLER, "Late NMI: BRK PC");
+    assert_eq!(finish(&mut cpu, &mut mem), Event::Instruction(0x40), "Late NMI: handler runs first");
+    assert_eq!(finish(&mut cpu, &mut mem), Event::Nmi, "Late NMI: taken after RTI");
+}
+
+// BNE taken from $8000 to $8004 without a page cross, IRQ raised during the offset fetch
+fn irq_delayed_by_taken_branch() {
+    let (mut cpu, mut mem) = setup();
+    mem.write(0x8000, 0xD0);
+    mem.write(0x8001, 0x02);
+    mem.write(0x8004, 0xEA);
+    cpu.tick(&mut mem).unwrap();
+    cpu.irq(true);
+    assert_eq!(finish(&mut cpu, &mut mem), Event::Instruction(0xD0), "Taken branch: event");
+    assert_eq!(cpu.cycles, 3, "Taken branch: cycles");
+    assert_eq!(finish(&mut cpu, &mut mem), Event::Instruction(0xEA), "Taken branch: next instruction runs first");
+    assert_eq!(cpu.pc, 0x8005, "Taken branch: PC");
+    assert_eq!(finish(&mut cpu, &mut mem), Event::Irq, "Taken branch: IRQ after the next instruction");
+}
+
+// BNE taken from $80FD across the page, the PCL cycle samples the IRQ again
+fn irq_after_page_crossing_branch() {
+    let (mut cpu, mut mem) = setup();
+    cpu.pc = 0x80FD;
+    mem.write(0x80FD, 0xD0);
+    mem.write(0x80FE, 0x01);
+    mem.write(0x8100, 0xEA);
+    cpu.tick(&mut mem).unwrap();
+    cpu.irq(true);
+    assert_eq!(finish(&mut cpu, &mut mem), Event::Instruction(0xD0), "Page crossing branch: event");
+    assert_eq!(cpu.cycles, 4, "Page crossing branch: cycles");
+    assert_eq!(cpu.pc, 0x8100, "Page crossing branch: PC");
+    assert_eq!(finish(&mut cpu, &mut mem), Event::Irq, "Page crossing branch: IRQ taken right away");
+}
+
+// Flat memory logging every bus access as (address, value, write) in cycle order
+struct Logged{
+    mem: Memory,
+    log: Vec<(u16, u8, bool)>,
+}
+
+impl Bus for Logged{
+    fn read(&mut self, addr: u16) -> u8{
+        let value = self.mem.read(addr);
+        self.log.push((addr, value, false));
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8){
+        self.log.push((addr, value, true));
+        self.mem.write(addr, value);
+    }
+
+    fn peek(&self, addr: u16) -> u8{
+        self.mem.read(addr)
+    }
+}
+
+// INC $2000 on the given core, returns the bus log
+fn inc_absolute(variant: Variant) -> Vec<(u16, u8, bool)> {
+    let (_, mut mem) = setup();
+    mem.write(0x8000, 0xEE);
+    mem.write(0x8001, 0x00);
+    mem.write(0x8002, 0x20);
+    mem.write(0x2000, 0x41);
+    let mut bus = Logged{ mem, log: Vec::new() };
+    let mut cpu = Processor::with_variant(variant);
+    cpu.step(&mut bus).unwrap();
+    assert_eq!(cpu.cycles, 6, "INC abs: cycles");
+    assert_eq!(bus.mem.read(0x2000), 0x42, "INC abs: result");
+    bus.log
+}
+
+fn rmw_double_write() {
+    let log = inc_absolute(Variant::Nmos);
+    assert_eq!(log[3..], [(0x2000, 0x41, false), (0x2000, 0x41, true), (0x2000, 0x42, true)], "NMOS INC abs: bus cycles 4-6");
+    let log = inc_absolute(Variant::Cmos);
+    assert_eq!(log[3..], [(0x2000, 0x41, false), (0x2000, 0x41, false), (0x2000, 0x42, true)], "65C02 INC abs: bus cycles 4-6");
+}
+
+pub fn brktest() {
+    brk_jumps_through_vector();
+    brk_ignores_i_flag();
+    nmi_before_brk();
+    irq_respects_i_flag();
+    nmi_hijacks_brk();
+    nmi_after_brk_vector_fetch();
+    irq_delayed_by_taken_branch();
+    irq_after_page_crossing_branch();
+    rmw_double_write();
+    println!("brktest passed");
+}
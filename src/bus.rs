@@ -0,0 +1,33 @@
+// Address space seen by the CPU, read and write may have side effects while peek must not
+pub trait Bus{
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, value: u8);
+    fn peek(&self, addr: u16) -> u8;
+
+    fn peek_u16(&self, addr: u16) -> u16{
+        ((self.peek(addr.wrapping_add(1)) as u16) << 8) | self.peek(addr) as u16
+    }
+}
+
+// Passes every access through and reports it as (address, value, write), for the debugger's watchpoints
+pub struct Observed<'a, B: Bus, F: FnMut(u16, u8, bool)>{
+    pub bus: &'a mut B,
+    pub access: F,
+}
+
+impl<B: Bus, F: FnMut(u16, u8, bool)> Bus for Observed<'_, B, F>{
+    fn read(&mut self, addr: u16) -> u8{
+        let value = self.bus.read(addr);
+        (self.access)(addr, value, false);
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8){
+        self.bus.write(addr, value);
+        (self.access)(addr, value, true);
+    }
+
+    fn peek(&self, addr: u16) -> u8{
+        self.bus.peek(addr)
+    }
+}
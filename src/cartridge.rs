@@ -0,0 +1,184 @@
+use crate::RomError;
+use crate::crc::crc32;
+
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_BANK: usize = 16 * 1024;
+const CHR_BANK: usize = 8 * 1024;
+const PRG_RAM_BANK: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format{
+    INes,
+    Nes2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring{
+    Horizontal,
+    Vertical,
+    FourScreen,
+    // Every nametable maps to the first or second 1KB of VRAM, set by some mappers
+    SingleLower,
+    SingleUpper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console{
+    Nes,
+    VsSystem,
+    Playchoice10,
+    // NES 2.0 extended console type from byte 13
+    Extended(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing{
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cartridge{
+    pub format: Format,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    // PRG-RAM or CHR-RAM is kept alive by a battery
+    pub battery: bool,
+    pub console: Console,
+    pub timing: Timing,
+    pub trainer: Option<Vec<u8>>,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    // Volatile and battery backed RAM sizes in bytes
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+}
+
+// NES 2.0 ROM sizes with the high nibble $F are 2^E * (MM*2+1)
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb&0x03) as usize * 2 + 1;
+        1usize.checked_shl(exponent).map_or(usize::MAX, |size| size.saturating_mul(multiplier))
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * unit
+    }
+}
+
+// NES 2.0 RAM sizes are 64 << shift, a shift of zero means none
+fn ram_size(shift: u8) -> usize {
+    if shift == 0 { 0 } else { 64 << shift }
+}
+
+impl Cartridge{
+
+    pub fn from_bytes(rom: &[u8]) -> Result<Cartridge, RomError>{
+        if rom.len() < HEADER_SIZE {
+            return Err(RomError::TooShort{ len: rom.len() });
+        }
+        let header = &rom[0..HEADER_SIZE];
+        if header[0..4] != *b"NES\x1A" {
+            return Err(RomError::BadMagic([header[0], header[1], header[2], header[3]]));
+        }
+        let format = if header[7]&0x0C == 0x08 { Format::Nes2 } else { Format::INes };
+
+        let mirroring = if header[6]&0x08 != 0 {
+            Mirroring::FourScreen
+        } else if header[6]&0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let battery = header[6]&0x02 != 0;
+        let has_trainer = header[6]&0x04 != 0;
+
+        let (prg_size, chr_size, mapper, submapper, console, timing);
+        let (prg_ram_size, prg_nvram_size, chr_ram_size, chr_nvram_size);
+        match format {
+            Format::Nes2 => {
+                prg_size = rom_size(header[4], header[9]&0x0F, PRG_BANK);
+                chr_size = rom_size(header[5], header[9] >> 4, CHR_BANK);
+                mapper = ((header[8]&0x0F) as u16) << 8 | (header[7]&0xF0) as u16 | (header[6] >> 4) as u16;
+                submapper = header[8] >> 4;
+                prg_ram_size = ram_size(header[10]&0x0F);
+                prg_nvram_size = ram_size(header[10] >> 4);
+                chr_ram_size = ram_size(header[11]&0x0F);
+                chr_nvram_size = ram_size(header[11] >> 4);
+                console = match header[7]&0x03 {
+                    0 => Console::Nes,
+                    1 => Console::VsSystem,
+                    2 => Console::Playchoice10,
+                    _ => Console::Extended(header[13]&0x0F),
+                };
+                timing = match header[12]&0x03 {
+                    0 => Timing::Ntsc,
+                    1 => Timing::Pal,
+                    2 => Timing::MultiRegion,
+                    _ => Timing::Dendy,
+                };
+            },
+            Format::INes => {
+                prg_size = header[4] as usize * PRG_BANK;
+                chr_size = header[5] as usize * CHR_BANK;
+                // Headers dirtied by old tools ("DiskDude!") carry garbage from byte 7 on
+                let high = if header[12..16].iter().any(|&byte| byte != 0) { 0 } else { header[7]&0xF0 };
+                mapper = (high | header[6] >> 4) as u16;
+                submapper = 0;
+                // A zero PRG-RAM size means 8KB for compatibility
+                let prg_ram = header[8].max(1) as usize * PRG_RAM_BANK;
+                (prg_ram_size, prg_nvram_size) = if battery { (0, prg_ram) } else { (prg_ram, 0) };
+                chr_ram_size = if chr_size == 0 { CHR_BANK } else { 0 };
+                chr_nvram_size = 0;
+                console = match header[7]&0x03 {
+                    1 => Console::VsSystem,
+                    2 => Console::Playchoice10,
+                    _ => Console::Nes,
+                };
+                timing = if header[9]&0x01 != 0 { Timing::Pal } else { Timing::Ntsc };
+            },
+        }
+        if prg_size == 0 {
+            return Err(RomError::NoPrgRom);
+        }
+
+        let trainer_size = if has_trainer { TRAINER_SIZE } else { 0 };
+        let prg_start = HEADER_SIZE + trainer_size;
+        let chr_start = prg_start.saturating_add(prg_size);
+        let end = chr_start.saturating_add(chr_size);
+        if rom.len() < end {
+            return Err(RomError::Truncated{ expected: end, actual: rom.len() });
+        }
+
+        Ok(Cartridge{
+            format,
+            mapper,
+            submapper,
+            mirroring,
+            battery,
+            console,
+            timing,
+            trainer: has_trainer.then(|| rom[HEADER_SIZE..prg_start].to_vec()),
+            prg_rom: rom[prg_start..chr_start].to_vec(),
+            chr_rom: rom[chr_start..end].to_vec(),
+            prg_ram_size,
+            prg_nvram_size,
+            chr_ram_size,
+            chr_nvram_size,
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Cartridge, RomError>{
+        Cartridge::from_bytes(&crate::read_rom(path)?)
+    }
+
+    // CRC-32 of PRG and CHR ROM, identifies the game regardless of header revisions
+    pub fn hash(&self) -> u32{
+        crc32(&[self.prg_rom.as_slice(), self.chr_rom.as_slice()].concat())
+    }
+}
@@ -0,0 +1,262 @@
+use std::error::Error;
+use std::path::Path;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::klaus::klaus;
+use crate::monitor::monitor;
+use crate::singlestep::singlestep;
+use emulator_6502::assembler;
+use emulator_6502::bus::Bus;
+use emulator_6502::disasm::{self, Instruction};
+use emulator_6502::memory::Memory;
+use emulator_6502::cartridge::Cartridge;
+use emulator_6502::nes::{Nes, NesBus};
+use emulator_6502::processor::Variant;
+use emulator_6502::trace::TraceFormat;
+use emulator_6502::headless::{self, RunLength, record_audio, render_frames};
+
+const USAGE: &str = "usage:
+  emulator-6502                      run the CPU test suites
+  emulator-6502 frames <rom> <count> [--capture n,n,...] [--input script] [--out dir]
+  emulator-6502 audio <rom> (--frames n | --seconds s) [--rate hz] [--input script] [--out file]
+  emulator-6502 debug <rom> [--org addr] [--pc addr]
+  emulator-6502 disasm <rom> [--org addr] [--entry addr,...] [--range start-end] [--cmos]
+  emulator-6502 trace <rom> [--pc addr] [--count n] [--format nintendulator|nestest] [--out file]
+  emulator-6502 asm <source> [--out file] [--symbols file]
+  emulator-6502 klaus [dir]          run Klaus Dormann's test binaries found in dir (default test)
+  emulator-6502 singlestep <dir> [--cpu nmos|nes|cmos] [--cycles] [--opcode xx]";
+
+// Value following a --flag, consumed with it
+fn option(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, Box<dyn Error>>{
+    let Some(index) = args.iter().position(|arg| arg == flag) else { return Ok(None) };
+    if index + 1 >= args.len() {
+        return Err(format!("{} needs a value", flag).into());
+    }
+    let value = args.remove(index + 1);
+    args.remove(index);
+    Ok(Some(value))
+}
+
+fn frames(mut args: Vec<String>) -> Result<(), Box<dyn Error>>{
+    let captures = match option(&mut args, "--capture")? {
+        Some(list) => list.split(',').map(|n| n.trim().parse()).collect::<Result<Vec<u32>, _>>()?,
+        None => Vec::new(),
+    };
+    let script = match option(&mut args, "--input")? {
+        Some(path) => headless::load_script(path)?,
+        None => Vec::new(),
+    };
+    let out = option(&mut args, "--out")?.unwrap_or_else(|| ".".to_string());
+    let [rom, count] = &args[..] else { return Err(USAGE.into()) };
+    for path in render_frames(rom, count.parse()?, script, &captures, Path::new(&out))? {
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+fn audio(mut args: Vec<String>) -> Result<(), Box<dyn Error>>{
+    let length = match (option(&mut args, "--frames")?, option(&mut args, "--seconds")?) {
+        (Some(frames), None) => RunLength::Frames(frames.parse()?),
+        (None, Some(seconds)) => RunLength::Seconds(seconds.parse()?),
+        _ => return Err("give one of --frames or --seconds".into()),
+    };
+    let rate = option(&mut args, "--rate")?.map(|rate| rate.parse()).transpose()?.unwrap_or(44100);
+    let script = match option(&mut args, "--input")? {
+        Some(path) => headless::load_script(path)?,
+        None => Vec::new(),
+    };
+    let out = option(&mut args, "--out")?.unwrap_or_else(|| "audio.wav".to_string());
+    let [rom] = &args[..] else { return Err(USAGE.into()) };
+    let count = record_audio(rom, length, script, rate, Path::new(&out))?;
+    println!("{}: {} samples at {} Hz", out, count, rate);
+    Ok(())
+}
+
+// Addresses are hex with an optional $
+fn address(text: &str) -> Result<u16, Box<dyn Error>>{
+    Ok(u16::from_str_radix(text.trim_start_matches('$'), 16)?)
+}
+
+fn debug(mut args: Vec<String>) -> Result<(), Box<dyn Error>>{
+    let org = option(&mut args, "--org")?.map(|org| address(&org)).transpose()?.unwrap_or(0x8000);
+    let pc = option(&mut args, "--pc")?.map(|pc| address(&pc)).transpose()?;
+    let [rom] = &args[..] else { return Err(USAGE.into()) };
+    monitor(rom, org, pc)
+}
+
+fn hex_bytes(bytes: &[u8]) -> String{
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+fn print_instruction(instruction: &Instruction, labels: &BTreeMap<u16, String>, nes: bool){
+    let label = |addr: u16| labels.get(&addr).cloned().or_else(|| nes.then(|| disasm::nes_register(addr).map(str::to_string)).flatten());
+    let text = format!("{} {}", instruction.mnemonic, instruction.operand_with(label));
+    println!("{:04X}  {:<8}  {}", instruction.addr, hex_bytes(instruction.bytes()), text.trim_end());
+}
+
+// Flow-following listing with labels, .nes files show the banks their mapper has in at power on and name the NES registers
+fn disassembly(mut args: Vec<String>) -> Result<(), Box<dyn Error>>{
+    let org = option(&mut args, "--org")?.map(|org| address(&org)).transpose()?.unwrap_or(0x8000);
+    let entries = option(&mut args, "--entry")?;
+    let range = option(&mut args, "--range")?;
+    let cmos = match args.iter().position(|arg| arg == "--cmos") {
+        Some(index) => {
+            args.remove(index);
+            true
+        },
+        None => false,
+    };
+    let [path] = &args[..] else { return Err(USAGE.into()) };
+
+    let rom = emulator_6502::read_rom(path)?;
+    let mut mem = Memory::new();
+    let nes = path.to_ascii_lowercase().ends_with(".nes");
+    let variant = match (cmos, nes) {
+        (true, _) => Variant::Cmos,
+        (false, true) => Variant::Nes,
+        (false, false) => Variant::Nmos,
+    };
+    let (start, end) = if nes {
+        let bus = NesBus::from_cartridge(Cartridge::from_bytes(&rom)?)?;
+        for addr in 0x8000..=0xFFFF {
+            mem.write(addr, bus.peek(addr));
+        }
+        (0x8000, 0xFFFF)
+    } else {
+        for (i, &byte) in rom.iter().enumerate() {
+            mem.write(org.wrapping_add(i as u16), byte);
+        }
+        (org, org.wrapping_add(rom.len().saturating_sub(1) as u16))
+    };
+
+    if let Some(range) = range {
+        let (first, last) = range.split_once('-').ok_or("--range takes start-end")?;
+        for instruction in disasm::disassemble_range(&mem, address(first)?, address(last)?, variant) {
+            print_instruction(&instruction, &BTreeMap::new(), nes);
+        }
+        return Ok(());
+    }
+
+    let mut labels = BTreeMap::new();
+    let entries = match entries {
+        Some(list) => list.split(',').map(address).collect::<Result<Vec<u16>, _>>()?,
+        None if nes => disasm::vectors(&mem).into_iter().map(|(name, addr)| {
+            labels.insert(addr, name.to_string());
+            addr
+        }).collect(),
+        None => vec![org],
+    };
+    let code = disasm::trace_code(&mem, &entries, variant, true);
+    for instruction in code.values() {
+        if let Some(target) = instruction.jump_target() {
+            labels.entry(target).or_insert_with(|| format!("L{:04X}", target));
+        }
+    }
+
+    // Bytes no traced path reached are listed as data
+    let mut addr = start as u32;
+    while addr <= end as u32 {
+        if let Some(label) = labels.get(&(addr as u16)) {
+            println!("{}:", label);
+        }
+        if let Some(instruction) = code.get(&(addr as u16)) {
+            print_instruction(instruction, &labels, nes);
+            addr += instruction.len as u32;
+            continue;
+        }
+        let data: Vec<u8> = (addr..=end as u32).take(8)
+            .take_while(|&next| next == addr || (!code.contains_key(&(next as u16)) && !labels.contains_key(&(next as u16))))
+            .map(|next| mem.peek(next as u16)).collect();
+        let list: Vec<String> = data.iter().map(|byte| format!("${:02X}", byte)).collect();
+        println!("{:04X}  {:<8}  .byte {}", addr, "", list.join(","));
+        addr += data.len() as u32;
+    }
+    Ok(())
+}
+
+// Trace log of a .nes run, --pc C000 starts nestest in automation mode
+fn trace(mut args: Vec<String>) -> Result<(), Box<dyn Error>>{
+    let pc = option(&mut args, "--pc")?.map(|pc| address(&pc)).transpose()?;
+    let count: u64 = option(&mut args, "--count")?.map(|count| count.parse()).transpose()?.unwrap_or(10000);
+    let format = match option(&mut args, "--format")?.as_deref() {
+        None | Some("nintendulator") => TraceFormat::Nintendulator,
+        Some("nestest") => TraceFormat::Nestest,
+        Some(other) => return Err(format!("unknown trace format {:?}", other).into()),
+    };
+    let out: Box<dyn Write> = match option(&mut args, "--out")? {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut out = BufWriter::new(out);
+    let [rom] = &args[..] else { return Err(USAGE.into()) };
+
+    let mut nes = Nes::new(Cartridge::load(rom)?)?;
+    if let Some(pc) = pc {
+        nes.cpu.pc = pc;
+    }
+    for _ in 0..count {
+        writeln!(out, "{}", nes.trace_line(format))?;
+        if let Err(err) = nes.step() {
+            writeln!(out, "{}", err)?;
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Assembles to a binary starting at the lowest address written, next to the source unless --out
+fn asm(mut args: Vec<String>) -> Result<(), Box<dyn Error>>{
+    let out = option(&mut args, "--out")?;
+    let symbols = option(&mut args, "--symbols")?;
+    let [source] = &args[..] else { return Err(USAGE.into()) };
+    let assembly = assembler::assemble_file(source)?;
+    let out = out.unwrap_or_else(|| Path::new(source).with_extension("bin").to_string_lossy().into_owned());
+    std::fs::write(&out, &assembly.bytes)?;
+    println!("{}: {} bytes at ${:04X}", out, assembly.bytes.len(), assembly.origin);
+    if let Some(path) = symbols {
+        std::fs::write(path, assembly.symbol_map())?;
+    }
+    Ok(())
+}
+
+// Per-opcode JSON test vectors, --cycles also compares every bus access
+fn single_step(mut args: Vec<String>) -> Result<(), Box<dyn Error>>{
+    let variant = match option(&mut args, "--cpu")?.as_deref() {
+        None | Some("nmos") => Variant::Nmos,
+        Some("nes") => Variant::Nes,
+        Some("cmos") => Variant::Cmos,
+        Some(other) => return Err(format!("unknown cpu {:?}", other).into()),
+    };
+    let only = option(&mut args, "--opcode")?.map(|opcode| u8::from_str_radix(opcode.trim_start_matches('$'), 16)).transpose()?;
+    let cycles = match args.iter().position(|arg| arg == "--cycles") {
+        Some(index) => {
+            args.remove(index);
+            true
+        },
+        None => false,
+    };
+    let [dir] = &args[..] else { return Err(USAGE.into()) };
+    singlestep(Path::new(dir), variant, cycles, only)
+}
+
+pub fn run(mut args: Vec<String>) -> Result<(), Box<dyn Error>>{
+    let command = args.remove(0);
+    match command.as_str() {
+        "frames" => frames(args),
+        "audio" => audio(args),
+        "debug" => debug(args),
+        "disasm" => disassembly(args),
+        "trace" => trace(args),
+        "asm" => asm(args),
+        "singlestep" => single_step(args),
+        "klaus" => match &args[..] {
+            [] => klaus(Path::new("test")),
+            [dir] => klaus(Path::new(dir)),
+            _ => Err(USAGE.into()),
+        },
+        _ => Err(USAGE.into()),
+    }
+}
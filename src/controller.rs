@@ -0,0 +1,113 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// Buttons in the order the joypad shifts them out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button{
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button{
+    pub const ALL: [Button; 8] = [Button::A, Button::B, Button::Select, Button::Start, Button::Up, Button::Down, Button::Left, Button::Right];
+
+    pub fn mask(self) -> u8{
+        1 << self as u8
+    }
+
+    pub fn name(self) -> &'static str{
+        match self {
+            Button::A => "A",
+            Button::B => "B",
+            Button::Select => "Select",
+            Button::Start => "Start",
+            Button::Up => "Up",
+            Button::Down => "Down",
+            Button::Left => "Left",
+            Button::Right => "Right",
+        }
+    }
+
+    // Case insensitive, for input scripts
+    pub fn from_name(name: &str) -> Option<Button>{
+        Button::ALL.into_iter().find(|button| button.name().eq_ignore_ascii_case(name))
+    }
+}
+
+// Standard joypad, a parallel-in shift register latched by the strobe bit of $4016
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Controller{
+    // Held buttons, bit 0 is A
+    buttons: u8,
+    shift: u8,
+    strobe: bool,
+}
+
+impl Controller{
+
+    pub fn new() -> Controller{
+        Controller::default()
+    }
+
+    pub fn buttons(&self) -> u8{
+        self.buttons
+    }
+
+    // Whole state for a frame, from a frontend or a recorded movie
+    pub fn set_buttons(&mut self, buttons: u8){
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift = buttons;
+        }
+    }
+
+    pub fn set(&mut self, button: Button, pressed: bool){
+        let buttons = if pressed { self.buttons | button.mask() } else { self.buttons&!button.mask() };
+        self.set_buttons(buttons);
+    }
+
+    pub fn pressed(&self, button: Button) -> bool{
+        self.buttons&button.mask() != 0
+    }
+
+    pub fn write(&mut self, value: u8){
+        self.strobe = value&0x01 != 0;
+        if self.strobe {
+            self.shift = self.buttons;
+        }
+    }
+
+    // While strobe is high the register keeps reloading and A is read back
+    pub fn peek(&self) -> u8{
+        if self.strobe { self.buttons&0x01 } else { self.shift&0x01 }
+    }
+
+    // Official joypads shift in ones, so reads after the eighth return 1
+    pub fn read(&mut self) -> u8{
+        let bit = self.peek();
+        if !self.strobe {
+            self.shift = self.shift >> 1 | 0x80;
+        }
+        bit
+    }
+}
+
+impl Snapshot for Controller{
+    fn save(&self, w: &mut StateWriter){
+        w.u8(self.buttons);
+        w.u8(self.shift);
+        w.bool(self.strobe);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        self.buttons = r.u8()?;
+        self.shift = r.u8()?;
+        self.strobe = r.bool()?;
+        Ok(())
+    }
+}
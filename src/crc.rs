@@ -0,0 +1,11 @@
+// CRC-32 as used by PNG, zlib and the save states, reflected polynomial 0xEDB88320
+pub fn crc32(data: &[u8]) -> u32{
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc&0x01 != 0 { crc >> 1 ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
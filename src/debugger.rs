@@ -0,0 +1,445 @@
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+
+use crate::bus::{Bus, Observed};
+use crate::nes::{Nes, NesBus};
+use crate::op::opcodes;
+use crate::processor::{CpuError, Event, Processor, StepInfo};
+
+const JSR: u8 = 0x20;
+const RTI: u8 = 0x40;
+const RTS: u8 = 0x60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register{
+    A,
+    X,
+    Y,
+    S,
+    P,
+    Pc,
+}
+
+impl Register{
+    pub fn from_name(name: &str) -> Option<Register>{
+        match name.to_ascii_uppercase().as_str() {
+            "A" => Some(Register::A),
+            "X" => Some(Register::X),
+            "Y" => Some(Register::Y),
+            "S" | "SP" => Some(Register::S),
+            "P" => Some(Register::P),
+            "PC" => Some(Register::Pc),
+            _ => None,
+        }
+    }
+
+    pub fn get(self, cpu: &Processor) -> u16{
+        match self {
+            Register::A => cpu.a as u16,
+            Register::X => cpu.x as u16,
+            Register::Y => cpu.y as u16,
+            Register::S => cpu.s as u16,
+            Register::P => cpu.p as u16,
+            Register::Pc => cpu.pc,
+        }
+    }
+
+    // 8-bit registers take the low byte
+    pub fn set(self, cpu: &mut Processor, value: u16){
+        match self {
+            Register::A => cpu.a = value as u8,
+            Register::X => cpu.x = value as u8,
+            Register::Y => cpu.y = value as u8,
+            Register::S => cpu.s = value as u8,
+            Register::P => cpu.p = value as u8,
+            Register::Pc => cpu.pc = value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compare{
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Compare{
+    pub fn from_symbol(symbol: &str) -> Option<Compare>{
+        match symbol {
+            "==" | "=" => Some(Compare::Eq),
+            "!=" => Some(Compare::Ne),
+            "<" => Some(Compare::Lt),
+            "<=" => Some(Compare::Le),
+            ">" => Some(Compare::Gt),
+            ">=" => Some(Compare::Ge),
+            _ => None,
+        }
+    }
+
+    pub fn test(self, left: u16, right: u16) -> bool{
+        match self {
+            Compare::Eq => left == right,
+            Compare::Ne => left != right,
+            Compare::Lt => left < right,
+            Compare::Le => left <= right,
+            Compare::Gt => left > right,
+            Compare::Ge => left >= right,
+        }
+    }
+}
+
+// Register comparison a breakpoint must satisfy to fire, e.g. X == $10
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition{
+    pub register: Register,
+    pub compare: Compare,
+    pub value: u16,
+}
+
+impl Condition{
+    pub fn holds(&self, cpu: &Processor) -> bool{
+        self.compare.test(self.register.get(cpu), self.value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint{
+    pub pc: u16,
+    pub condition: Option<Condition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access{
+    Read,
+    Write,
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint{
+    pub range: RangeInclusive<u16>,
+    pub access: Access,
+}
+
+impl Watchpoint{
+    fn matches(&self, addr: u16, write: bool) -> bool{
+        let access = match self.access {
+            Access::Read => !write,
+            Access::Write => write,
+            Access::ReadWrite => true,
+        };
+        access && self.range.contains(&addr)
+    }
+}
+
+// Bus access that hit a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit{
+    pub addr: u16,
+    pub value: u8,
+    pub write: bool,
+    // PC of the instruction making the access
+    pub pc: u16,
+}
+
+// What the debugger drives: a CPU, the bus it is wired to and a way to run one instruction
+pub trait Machine{
+    type Bus: Bus;
+    fn cpu(&self) -> &Processor;
+    fn cpu_mut(&mut self) -> &mut Processor;
+    fn bus(&self) -> &Self::Bus;
+    fn bus_mut(&mut self) -> &mut Self::Bus;
+    // One instruction, `access` sees every read and write the CPU makes as (address, value, write)
+    fn step<F: FnMut(u16, u8, bool)>(&mut self, access: F) -> Result<StepInfo, CpuError>;
+}
+
+// A CPU alone on a bus, nothing else is clocked
+pub struct System<B: Bus>{
+    pub cpu: Processor,
+    pub bus: B,
+}
+
+impl<B: Bus> Machine for System<B>{
+    type Bus = B;
+
+    fn cpu(&self) -> &Processor{
+        &self.cpu
+    }
+
+    fn cpu_mut(&mut self) -> &mut Processor{
+        &mut self.cpu
+    }
+
+    fn bus(&self) -> &B{
+        &self.bus
+    }
+
+    fn bus_mut(&mut self) -> &mut B{
+        &mut self.bus
+    }
+
+    fn step<F: FnMut(u16, u8, bool)>(&mut self, access: F) -> Result<StepInfo, CpuError>{
+        self.cpu.step(&mut Observed{ bus: &mut self.bus, access })
+    }
+}
+
+// The whole console, the PPU, APU and DMA run along with every instruction
+impl Machine for Nes{
+    type Bus = NesBus;
+
+    fn cpu(&self) -> &Processor{
+        &self.cpu
+    }
+
+    fn cpu_mut(&mut self) -> &mut Processor{
+        &mut self.cpu
+    }
+
+    fn bus(&self) -> &NesBus{
+        &self.bus
+    }
+
+    fn bus_mut(&mut self) -> &mut NesBus{
+        &mut self.bus
+    }
+
+    fn step<F: FnMut(u16, u8, bool)>(&mut self, access: F) -> Result<StepInfo, CpuError>{
+        self.step_with(access)
+    }
+}
+
+// CPU state before an executed instruction, with its bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryEntry{
+    pub pc: u16,
+    pub bytes: [u8; 3],
+    pub len: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub s: u8,
+    pub p: u8,
+    // Debugger cycle count when the instruction started
+    pub cycle: u64,
+    pub event: Event,
+}
+
+// Why a debugger command gave control back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop{
+    // The requested steps are done
+    Stepped,
+    Breakpoint{ pc: u16 },
+    Watchpoint(WatchHit),
+    // Returned from the subroutine being stepped out of
+    Returned,
+    CycleReached{ cycle: u64 },
+}
+
+pub struct Debugger{
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    history: VecDeque<HistoryEntry>,
+    history_len: usize,
+    // Cycles of every step run through the debugger
+    cycles: u64,
+}
+
+impl Default for Debugger{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger{
+
+    pub fn new() -> Debugger{
+        Debugger::with_history(64)
+    }
+
+    pub fn with_history(history_len: usize) -> Debugger{
+        Debugger{
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            history: VecDeque::with_capacity(history_len),
+            history_len,
+            cycles: 0,
+        }
+    }
+
+    pub fn cycles(&self) -> u64{
+        self.cycles
+    }
+
+    pub fn set_cycles(&mut self, cycles: u64){
+        self.cycles = cycles;
+    }
+
+    pub fn add_breakpoint(&mut self, pc: u16, condition: Option<Condition>){
+        let breakpoint = Breakpoint{ pc, condition };
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    // Removes every breakpoint on the address
+    pub fn remove_breakpoint(&mut self, pc: u16){
+        self.breakpoints.retain(|breakpoint| breakpoint.pc != pc);
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint]{
+        &self.breakpoints
+    }
+
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, access: Access){
+        let watchpoint = Watchpoint{ range, access };
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    // Removes the watchpoints starting at the address
+    pub fn remove_watchpoint(&mut self, start: u16){
+        self.watchpoints.retain(|watch| *watch.range.start() != start);
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint]{
+        &self.watchpoints
+    }
+
+    // Oldest first
+    pub fn history(&self) -> impl Iterator<Item = &HistoryEntry>{
+        self.history.iter()
+    }
+
+    pub fn clear_history(&mut self){
+        self.history.clear();
+    }
+
+    fn breakpoint_hit(&self, cpu: &Processor) -> bool{
+        self.breakpoints.iter().any(|breakpoint| {
+            breakpoint.pc == cpu.pc && breakpoint.condition.is_none_or(|condition| condition.holds(cpu))
+        })
+    }
+
+    fn record<B: Bus>(&mut self, cpu: &Processor, bus: &B){
+        if self.history_len == 0 {
+            return;
+        }
+        let len = opcodes(cpu.variant)[bus.peek(cpu.pc) as usize].bytes.max(1);
+        let mut bytes = [0; 3];
+        for (i, byte) in bytes.iter_mut().enumerate().take(len as usize) {
+            *byte = bus.peek(cpu.pc.wrapping_add(i as u16));
+        }
+        if self.history.len() == self.history_len {
+            self.history.pop_front();
+        }
+        self.history.push_back(HistoryEntry{
+            pc: cpu.pc,
+            bytes,
+            len,
+            a: cpu.a,
+            x: cpu.x,
+            y: cpu.y,
+            s: cpu.s,
+            p: cpu.p,
+            cycle: self.cycles,
+            event: Event::Idle,
+        });
+    }
+
+    // One instruction with watchpoints armed
+    fn execute<M: Machine>(&mut self, machine: &mut M) -> Result<(StepInfo, Option<WatchHit>), CpuError>{
+        self.record(machine.cpu(), machine.bus());
+        let (pc, watchpoints) = (machine.cpu().pc, &self.watchpoints);
+        let mut hit = None;
+        let result = machine.step(|addr, value, write| {
+            if hit.is_none() && watchpoints.iter().any(|watch| watch.matches(addr, write)) {
+                hit = Some(WatchHit{ addr, value, write, pc });
+            }
+        });
+        let info = match result {
+            Ok(info) => info,
+            Err(err) => {
+                // Nothing ran, the entry would only repeat the next one
+                if let CpuError::UnknownOpcode{ .. } = err {
+                    self.history.pop_back();
+                }
+                return Err(err);
+            },
+        };
+        self.cycles += info.cycles as u64;
+        if let Some(entry) = self.history.back_mut() {
+            entry.event = info.event;
+        }
+        Ok((info, hit))
+    }
+
+    // Runs instructions until `done` says so or a breakpoint or watchpoint stops it.
+    // The instruction under PC always runs so a command can leave a breakpoint.
+    fn run_until<M: Machine, F>(&mut self, machine: &mut M, mut done: F) -> Result<Stop, CpuError>
+    where F: FnMut(&Processor, &StepInfo, u64) -> Option<Stop>{
+        let mut first = true;
+        loop {
+            if !first && self.breakpoint_hit(machine.cpu()) {
+                return Ok(Stop::Breakpoint{ pc: machine.cpu().pc });
+            }
+            first = false;
+            let (info, hit) = self.execute(machine)?;
+            if let Some(hit) = hit {
+                return Ok(Stop::Watchpoint(hit));
+            }
+            if let Some(stop) = done(machine.cpu(), &info, self.cycles) {
+                return Ok(stop);
+            }
+        }
+    }
+
+    pub fn step<M: Machine>(&mut self, machine: &mut M) -> Result<Stop, CpuError>{
+        self.step_n(machine, 1)
+    }
+
+    pub fn step_n<M: Machine>(&mut self, machine: &mut M, count: u64) -> Result<Stop, CpuError>{
+        let mut left = count;
+        self.run_until(machine, |_, _, _| {
+            left = left.saturating_sub(1);
+            (left == 0).then_some(Stop::Stepped)
+        })
+    }
+
+    // Runs a JSR and the whole subroutine as one step, anything else steps into
+    pub fn step_over<M: Machine>(&mut self, machine: &mut M) -> Result<Stop, CpuError>{
+        let cpu = machine.cpu();
+        if machine.bus().peek(cpu.pc) != JSR {
+            return self.step(machine);
+        }
+        let (ret, s) = (cpu.pc.wrapping_add(3), cpu.s);
+        // Recursion passes the return address with a deeper stack
+        self.run_until(machine, |cpu, _, _| (cpu.pc == ret && cpu.s >= s).then_some(Stop::Stepped))
+    }
+
+    // Runs until an RTS or RTI leaves the current subroutine, nested calls return to a deeper stack
+    pub fn step_out<M: Machine>(&mut self, machine: &mut M) -> Result<Stop, CpuError>{
+        let s = machine.cpu().s;
+        self.run_until(machine, |cpu, info, _| {
+            let returned = matches!(info.event, Event::Instruction(RTS | RTI)) && cpu.s > s;
+            returned.then_some(Stop::Returned)
+        })
+    }
+
+    pub fn run<M: Machine>(&mut self, machine: &mut M) -> Result<Stop, CpuError>{
+        self.run_until(machine, |_, _, _| None)
+    }
+
+    // Stops at the first instruction boundary at or after the cycle count
+    pub fn run_until_cycle<M: Machine>(&mut self, machine: &mut M, cycle: u64) -> Result<Stop, CpuError>{
+        if self.cycles >= cycle {
+            return Ok(Stop::CycleReached{ cycle: self.cycles });
+        }
+        self.run_until(machine, |_, _, cycles| (cycles >= cycle).then_some(Stop::CycleReached{ cycle: cycles }))
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+
+use crate::bus::Bus;
+use crate::op::{Legality, Mnemonic, Mode, opcodes};
+use crate::processor::{Processor, Variant};
+
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction{
+    pub addr: u16,
+    pub opcode: u8,
+    // Opcode and operand bytes, len of them are used
+    pub bytes: [u8; 3],
+    pub len: u8,
+    pub mnemonic: Mnemonic,
+    pub mode: Mode,
+    pub legality: Legality,
+}
+
+impl Instruction{
+
+    pub fn bytes(&self) -> &[u8]{
+        &self.bytes[..self.len as usize]
+    }
+
+    fn byte(&self) -> u8{
+        self.bytes[1]
+    }
+
+    fn word(&self) -> u16{
+        u16::from_le_bytes([self.bytes[1], self.bytes[2]])
+    }
+
+    fn branch(&self, offset: u8) -> u16{
+        self.addr.wrapping_add(self.len as u16).wrapping_add(offset as i8 as u16)
+    }
+
+    // Address the operand names without any register, branch and jump targets included
+    pub fn target(&self) -> Option<u16>{
+        match self.mode {
+            Mode::ZeroPage | Mode::ZeroPageX | Mode::ZeroPageY | Mode::IndirectX
+            | Mode::IndirectY | Mode::ZeroPageIndirect => Some(self.byte() as u16),
+            Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY | Mode::Indirect
+            | Mode::AbsoluteIndexedIndirect => Some(self.word()),
+            Mode::Relative => Some(self.branch(self.byte())),
+            Mode::ZeroPageRelative => Some(self.branch(self.bytes[2])),
+            Mode::Implied | Mode::Accumulator | Mode::Immediate => None,
+        }
+    }
+
+    // Address the instruction would access with the current registers, pointers read through peek
+    pub fn effective<B: Bus>(&self, cpu: &Processor, bus: &B) -> Option<u16>{
+        let zp_word = |ptr: u8| u16::from_le_bytes([bus.peek(ptr as u16), bus.peek(ptr.wrapping_add(1) as u16)]);
+        match self.mode {
+            Mode::ZeroPage | Mode::ZeroPageRelative => Some(self.byte() as u16),
+            Mode::ZeroPageX => Some(self.byte().wrapping_add(cpu.x) as u16),
+            Mode::ZeroPageY => Some(self.byte().wrapping_add(cpu.y) as u16),
+            Mode::Absolute => Some(self.word()),
+            Mode::AbsoluteX => Some(self.word().wrapping_add(cpu.x as u16)),
+            Mode::AbsoluteY => Some(self.word().wrapping_add(cpu.y as u16)),
+            Mode::IndirectX => Some(zp_word(self.byte().wrapping_add(cpu.x))),
+            Mode::IndirectY => Some(zp_word(self.byte()).wrapping_add(cpu.y as u16)),
+            Mode::ZeroPageIndirect => Some(zp_word(self.byte())),
+            Mode::Indirect => {
+                let ptr = self.word();
+                // The NMOS pointer high byte does not carry into the next page
+                let high = if cpu.variant == Variant::Cmos { ptr.wrapping_add(1) } else { ptr&0xFF00 | (ptr as u8).wrapping_add(1) as u16 };
+                Some(u16::from_le_bytes([bus.peek(ptr), bus.peek(high)]))
+            },
+            Mode::AbsoluteIndexedIndirect => {
+                let ptr = self.word().wrapping_add(cpu.x as u16);
+                Some(bus.peek_u16(ptr))
+            },
+            Mode::Relative => Some(self.branch(self.byte())),
+            Mode::Implied | Mode::Accumulator | Mode::Immediate => None,
+        }
+    }
+
+    // Execution continues at the next instruction
+    pub fn falls_through(&self) -> bool{
+        !matches!(self.mnemonic, Mnemonic::Jmp | Mnemonic::Rts | Mnemonic::Rti | Mnemonic::Brk
+            | Mnemonic::Jam | Mnemonic::Stp | Mnemonic::Bra)
+    }
+
+    // Code the instruction can transfer to besides the next one
+    pub fn jump_target(&self) -> Option<u16>{
+        match (self.mnemonic, self.mode) {
+            (Mnemonic::Jmp | Mnemonic::Jsr, Mode::Absolute) => Some(self.word()),
+            (_, Mode::Relative | Mode::ZeroPageRelative) => self.target(),
+            _ => None,
+        }
+    }
+
+    // Operand text, the label function can name addresses
+    pub fn operand_with<F: Fn(u16) -> Option<String>>(&self, label: F) -> String{
+        let zp = |addr: u8| label(addr as u16).unwrap_or_else(|| format!("${:02X}", addr));
+        let abs = |addr: u16| label(addr).unwrap_or_else(|| format!("${:04X}", addr));
+        match self.mode {
+            Mode::Implied => String::new(),
+            Mode::Accumulator => "A".to_string(),
+            Mode::Immediate => format!("#${:02X}", self.byte()),
+            Mode::ZeroPage => zp(self.byte()),
+            Mode::ZeroPageX => format!("{},X", zp(self.byte())),
+            Mode::ZeroPageY => format!("{},Y", zp(self.byte())),
+            Mode::Absolute => abs(self.word()),
+            Mode::AbsoluteX => format!("{},X", abs(self.word())),
+            Mode::AbsoluteY => format!("{},Y", abs(self.word())),
+            Mode::Indirect => format!("({})", abs(self.word())),
+            Mode::IndirectX => format!("({},X)", zp(self.byte())),
+            Mode::IndirectY => format!("({}),Y", zp(self.byte())),
+            Mode::ZeroPageIndirect => format!("({})", zp(self.byte())),
+            Mode::AbsoluteIndexedIndirect => format!("({},X)", abs(self.word())),
+            Mode::Relative => abs(self.branch(self.byte())),
+            Mode::ZeroPageRelative => format!("{},{}", zp(self.byte()), abs(self.branch(self.bytes[2]))),
+        }
+    }
+
+    pub fn operand(&self) -> String{
+        self.operand_with(|_| None)
+    }
+}
+
+impl Display for Instruction{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.mode {
+            Mode::Implied => write!(f, "{}", self.mnemonic),
+            _ => write!(f, "{} {}", self.mnemonic, self.operand()),
+        }
+    }
+}
+
+// Instruction from bytes already fetched, operand bytes past its length are ignored
+pub fn decode(addr: u16, bytes: [u8; 3], variant: Variant) -> Instruction{
+    let op = opcodes(variant)[bytes[0] as usize];
+    let mut bytes = bytes;
+    bytes[op.bytes as usize..].fill(0);
+    Instruction{ addr, opcode: bytes[0], bytes, len: op.bytes, mnemonic: op.mnemonic, mode: op.mode, legality: op.legality }
+}
+
+pub fn disassemble_variant<B: Bus>(bus: &B, addr: u16, variant: Variant) -> Instruction{
+    let bytes = [0, 1, 2].map(|i| bus.peek(addr.wrapping_add(i)));
+    decode(addr, bytes, variant)
+}
+
+// Decodes the NMOS instruction at addr without side effects
+pub fn disassemble<B: Bus>(bus: &B, addr: u16) -> Instruction{
+    disassemble_variant(bus, addr, Variant::Nmos)
+}
+
+// Linear sweep from start up to and including end
+pub fn disassemble_range<B: Bus>(bus: &B, start: u16, end: u16, variant: Variant) -> Vec<Instruction>{
+    let mut listing = Vec::new();
+    let mut addr = start as u32;
+    while addr <= end as u32 {
+        let instruction = disassemble_variant(bus, addr as u16, variant);
+        addr += instruction.len as u32;
+        listing.push(instruction);
+    }
+    listing
+}
+
+// Entry points stored in the NMI, reset and IRQ vectors
+pub fn vectors<B: Bus>(bus: &B) -> [(&'static str, u16); 3]{
+    [("nmi", bus.peek_u16(NMI_VECTOR)), ("reset", bus.peek_u16(RESET_VECTOR)), ("irq", bus.peek_u16(IRQ_VECTOR))]
+}
+
+// Follows code from the entry points through branches, jumps and calls. Indirect jumps end a
+// path since their target is only known at run time, undocumented opcodes too unless allowed.
+pub fn trace_code<B: Bus>(bus: &B, entries: &[u16], variant: Variant, undocumented: bool) -> BTreeMap<u16, Instruction>{
+    let mut code = BTreeMap::new();
+    let mut pending: Vec<u16> = entries.to_vec();
+    let mut seen = BTreeSet::new();
+    while let Some(start) = pending.pop() {
+        let mut addr = start;
+        while seen.insert(addr) {
+            let instruction = disassemble_variant(bus, addr, variant);
+            if !undocumented && instruction.legality != Legality::Legal {
+                break;
+            }
+            code.insert(addr, instruction);
+            if let Some(target) = instruction.jump_target() {
+                pending.push(target);
+            }
+            if !instruction.falls_through() {
+                break;
+            }
+            addr = addr.wrapping_add(instruction.len as u16);
+        }
+    }
+    code
+}
+
+// Names of the PPU, APU and I/O registers of the NES
+pub fn nes_register(addr: u16) -> Option<&'static str>{
+    let name = match addr {
+        0x2000 => "PPUCTRL",
+        0x2001 => "PPUMASK",
+        0x2002 => "PPUSTATUS",
+        0x2003 => "OAMADDR",
+        0x2004 => "OAMDATA",
+        0x2005 => "PPUSCROLL",
+        0x2006 => "PPUADDR",
+        0x2007 => "PPUDATA",
+        0x4000 => "SQ1_VOL",
+        0x4001 => "SQ1_SWEEP",
+        0x4002 => "SQ1_LO",
+        0x4003 => "SQ1_HI",
+        0x4004 => "SQ2_VOL",
+        0x4005 => "SQ2_SWEEP",
+        0x4006 => "SQ2_LO",
+        0x4007 => "SQ2_HI",
+        0x4008 => "TRI_LINEAR",
+        0x400A => "TRI_LO",
+        0x400B => "TRI_HI",
+        0x400C => "NOISE_VOL",
+        0x400E => "NOISE_LO",
+        0x400F => "NOISE_HI",
+        0x4010 => "DMC_FREQ",
+        0x4011 => "DMC_RAW",
+        0x4012 => "DMC_START",
+        0x4013 => "DMC_LEN",
+        0x4014 => "OAMDMA",
+        0x4015 => "SND_CHN",
+        0x4016 => "JOY1",
+        0x4017 => "JOY2",
+        _ => return None,
+    };
+    Some(name)
+}
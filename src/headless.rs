@@ -0,0 +1,190 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::RomError;
+use crate::apu::CPU_CLOCK;
+use crate::cartridge::Cartridge;
+use crate::controller::Button;
+use crate::nes::Nes;
+use crate::png;
+use crate::ppu::{HEIGHT, WIDTH};
+use crate::processor::CpuError;
+use crate::wav;
+
+#[derive(Debug)]
+pub enum RunError{
+    Rom(RomError),
+    Cpu(CpuError),
+    Io(io::Error),
+    Script{ line: usize, text: String },
+    // Zero, or more samples a second than the CPU has cycles
+    SampleRate(u32),
+}
+
+impl Display for RunError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::Rom(err) => write!(f, "{}", err),
+            RunError::Cpu(err) => write!(f, "{}", err),
+            RunError::Io(err) => write!(f, "{}", err),
+            RunError::Script{ line, text } => write!(f, "bad input script line {}: {:?}", line, text),
+            RunError::SampleRate(rate) => write!(f, "sample rate of {} Hz is outside 1 to {} Hz", rate, CPU_CLOCK),
+        }
+    }
+}
+
+impl Error for RunError{
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RunError::Rom(err) => Some(err),
+            RunError::Cpu(err) => Some(err),
+            RunError::Io(err) => Some(err),
+            RunError::Script{ .. } | RunError::SampleRate(_) => None,
+        }
+    }
+}
+
+impl From<RomError> for RunError{
+    fn from(err: RomError) -> Self {
+        RunError::Rom(err)
+    }
+}
+
+impl From<CpuError> for RunError{
+    fn from(err: CpuError) -> Self {
+        RunError::Cpu(err)
+    }
+}
+
+impl From<io::Error> for RunError{
+    fn from(err: io::Error) -> Self {
+        RunError::Io(err)
+    }
+}
+
+// Buttons held on a port from the start of a frame until the next event for that port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent{
+    pub frame: u32,
+    pub port: usize,
+    pub buttons: u8,
+}
+
+// One event per line: frame, port and buttons joined by '+' or '-' for none, e.g. "60 0 Start+A"
+pub fn parse_script(text: &str) -> Result<Vec<InputEvent>, RunError>{
+    let mut events = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let content = line.split('#').next().unwrap_or("").trim();
+        if content.is_empty() {
+            continue;
+        }
+        let bad = || RunError::Script{ line: index + 1, text: line.to_string() };
+        let fields: Vec<&str> = content.split_whitespace().collect();
+        let [frame, port, buttons] = fields[..] else { return Err(bad()) };
+        let frame = frame.parse().map_err(|_| bad())?;
+        let port = port.parse().ok().filter(|&port: &usize| port < 2).ok_or_else(bad)?;
+        let mut mask = 0;
+        if buttons != "-" {
+            for name in buttons.split('+') {
+                mask |= Button::from_name(name).ok_or_else(bad)?.mask();
+            }
+        }
+        events.push(InputEvent{ frame, port, buttons: mask });
+    }
+    Ok(events)
+}
+
+pub fn load_script<P: AsRef<Path>>(path: P) -> Result<Vec<InputEvent>, RunError>{
+    parse_script(&std::fs::read_to_string(path)?)
+}
+
+// Runs a console frame by frame with no display, feeding scripted input
+pub struct Runner{
+    pub nes: Nes,
+    script: Vec<InputEvent>,
+    next: usize,
+    // Frames completed so far
+    frame: u32,
+}
+
+impl Runner{
+
+    pub fn new(nes: Nes, mut script: Vec<InputEvent>) -> Runner{
+        script.sort_by_key(|event| event.frame);
+        Runner{ nes, script, next: 0, frame: 0 }
+    }
+
+    pub fn load(rom: &str, script: Vec<InputEvent>) -> Result<Runner, RunError>{
+        Ok(Runner::new(Nes::new(Cartridge::load(rom)?)?, script))
+    }
+
+    pub fn frame(&self) -> u32{
+        self.frame
+    }
+
+    // Frames are numbered from 1, input for a frame is set before it runs
+    pub fn run_frame(&mut self) -> Result<(), CpuError>{
+        self.frame += 1;
+        while let Some(event) = self.script.get(self.next).filter(|event| event.frame <= self.frame) {
+            self.nes.bus.controllers[event.port].set_buttons(event.buttons);
+            self.next += 1;
+        }
+        self.nes.run_frame()
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()>{
+        png::write(path, WIDTH as u32, HEIGHT as u32, &self.nes.bus.ppu.frame_rgb())
+    }
+}
+
+// Runs frames of a ROM and writes the captured ones as frame_<n>.png, the last frame if none are given
+pub fn render_frames(rom: &str, frames: u32, script: Vec<InputEvent>, captures: &[u32], dir: &Path) -> Result<Vec<PathBuf>, RunError>{
+    let mut runner = Runner::load(rom, script)?;
+    std::fs::create_dir_all(dir)?;
+    let mut written = Vec::new();
+    while runner.frame() < frames {
+        runner.run_frame()?;
+        let frame = runner.frame();
+        if captures.contains(&frame) || (captures.is_empty() && frame == frames) {
+            let path = dir.join(format!("frame_{}.png", frame));
+            runner.save_png(&path)?;
+            written.push(path);
+        }
+    }
+    Ok(written)
+}
+
+// How long to record, a number of frames or of seconds of audio
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunLength{
+    Frames(u32),
+    Seconds(f64),
+}
+
+// Runs a ROM and writes the mixed APU output as a WAV file, returns the number of samples
+pub fn record_audio(rom: &str, length: RunLength, script: Vec<InputEvent>, sample_rate: u32, path: &Path) -> Result<usize, RunError>{
+    if sample_rate == 0 || sample_rate as u64 > CPU_CLOCK {
+        return Err(RunError::SampleRate(sample_rate));
+    }
+    let mut runner = Runner::load(rom, script)?;
+    runner.nes.bus.apu.set_sample_rate(sample_rate);
+    let mut samples = Vec::new();
+    match length {
+        RunLength::Frames(frames) => while runner.frame() < frames {
+            runner.run_frame()?;
+            samples.extend(runner.nes.bus.apu.take_samples());
+        },
+        RunLength::Seconds(seconds) => {
+            let count = (seconds * sample_rate as f64).round() as usize;
+            while samples.len() < count {
+                runner.run_frame()?;
+                samples.extend(runner.nes.bus.apu.take_samples());
+            }
+            samples.truncate(count);
+        },
+    }
+    wav::write(path, sample_rate, &samples)?;
+    Ok(samples.len())
+}
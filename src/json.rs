@@ -0,0 +1,226 @@
+use std::error::Error;
+use std::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json{
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    // Members in file order
+    Object(Vec<(String, Json)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonError{
+    // Byte offset into the text
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+impl Display for JsonError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bad JSON at byte {}: {}", self.offset, self.message)
+    }
+}
+
+impl Error for JsonError{}
+
+impl Json{
+
+    pub fn get(&self, key: &str) -> Option<&Json>{
+        match self {
+            Json::Object(members) => members.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]>{
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str>{
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    // Whole non-negative numbers only
+    pub fn as_u64(&self) -> Option<u64>{
+        match *self {
+            Json::Number(value) if value >= 0.0 && value.fract() == 0.0 && value <= u64::MAX as f64 => Some(value as u64),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a>{
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_>{
+
+    fn error<T>(&self, message: &'static str) -> Result<T, JsonError>{
+        Err(JsonError{ offset: self.pos, message })
+    }
+
+    fn skip_space(&mut self){
+        while self.text.get(self.pos).is_some_and(|byte| byte.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, word: &str, value: Json) -> Result<Json, JsonError>{
+        if !self.text[self.pos..].starts_with(word.as_bytes()) {
+            return self.error("unknown literal");
+        }
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError>{
+        self.skip_space();
+        match self.text.get(self.pos) {
+            None => self.error("unexpected end"),
+            Some(b'n') => self.expect("null", Json::Null),
+            Some(b't') => self.expect("true", Json::Bool(true)),
+            Some(b'f') => self.expect("false", Json::Bool(false)),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_space();
+                if self.text.get(self.pos) == Some(&b']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_space();
+                    match self.text.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Json::Array(items));
+                        },
+                        _ => return self.error("expected , or ]"),
+                    }
+                }
+            },
+            Some(b'{') => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                self.skip_space();
+                if self.text.get(self.pos) == Some(&b'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.skip_space();
+                    if self.text.get(self.pos) != Some(&b'"') {
+                        return self.error("expected a member name");
+                    }
+                    let name = self.string()?;
+                    self.skip_space();
+                    if self.text.get(self.pos) != Some(&b':') {
+                        return self.error("expected :");
+                    }
+                    self.pos += 1;
+                    members.push((name, self.value()?));
+                    self.skip_space();
+                    match self.text.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Json::Object(members));
+                        },
+                        _ => return self.error("expected , or }"),
+                    }
+                }
+            },
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => self.error("unexpected character"),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, JsonError>{
+        let start = self.pos;
+        while self.text.get(self.pos).is_some_and(|&byte| matches!(byte, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        // The slice is ASCII so it is valid UTF-8
+        let text = std::str::from_utf8(&self.text[start..self.pos]).unwrap_or_default();
+        match text.parse() {
+            Ok(value) => Ok(Json::Number(value)),
+            Err(_) => Err(JsonError{ offset: start, message: "bad number" }),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError>{
+        let digits = self.text.get(self.pos..self.pos + 4).and_then(|digits| std::str::from_utf8(digits).ok());
+        match digits.and_then(|digits| u32::from_str_radix(digits, 16).ok()) {
+            Some(value) => {
+                self.pos += 4;
+                Ok(value)
+            },
+            None => self.error("bad \\u escape"),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError>{
+        // Skip the opening quote
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            let Some(&byte) = self.text.get(self.pos) else { return self.error("unterminated string") };
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&escape) = self.text.get(self.pos) else { return self.error("unterminated string") };
+                    self.pos += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            // A surrogate pair spells a character outside the basic plane
+                            if (0xD800..0xDC00).contains(&code) && self.text[self.pos..].starts_with(b"\\u") {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xD800) << 10) + low.wrapping_sub(0xDC00);
+                            }
+                            char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        },
+                        _ => return self.error("bad escape"),
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                },
+                _ => bytes.push(byte),
+            }
+        }
+        String::from_utf8(bytes).or_else(|_| self.error("string is not UTF-8"))
+    }
+}
+
+pub fn parse(text: &str) -> Result<Json, JsonError>{
+    let mut parser = Parser{ text: text.as_bytes(), pos: 0 };
+    let value = parser.value()?;
+    parser.skip_space();
+    if parser.pos != parser.text.len() {
+        return parser.error("trailing characters");
+    }
+    Ok(value)
+}
@@ -0,0 +1,139 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use emulator_6502::bus::Bus;
+use emulator_6502::memory::Memory;
+use emulator_6502::processor::{CpuError, Event, Processor, RunState};
+
+// The functional test runs about 30 million instructions
+const MAX_INSTRUCTIONS: u64 = 100_000_000;
+// Current test number in the functional and interrupt tests
+const TEST_CASE: u16 = 0x0200;
+// Operands and result of the decimal test, ERROR is 0 when every ADC and SBC matched
+const DECIMAL_N1: u16 = 0x0000;
+const DECIMAL_N2: u16 = 0x0001;
+const DECIMAL_ERROR: u16 = 0x000B;
+// Feedback register of the interrupt test, bit 0 drives IRQ and bit 1 NMI
+const INTERRUPT_PORT: u16 = 0xBFFC;
+
+#[derive(Clone, Copy)]
+enum Success{
+    // The suite ends in a loop at this address, any other loop is a failed test
+    Trap(u16),
+    // The suite always stops in the same place and leaves a zero here when it passed
+    Result(u16),
+}
+
+struct Suite{
+    name: &'static str,
+    // Where an image shorter than 64KB is loaded, full images start at $0000
+    org: u16,
+    start: u16,
+    success: Success,
+    port: Option<u16>,
+}
+
+// Klaus Dormann's suites as assembled with their default options
+const SUITES: [Suite; 3] = [
+    Suite{ name: "6502_functional_test", org: 0x0000, start: 0x0400, success: Success::Trap(0x3469), port: None },
+    // Bruce Clark's decimal test ends in a STP ($DB), which the NMOS core rejects as undocumented
+    Suite{ name: "6502_decimal_test", org: 0x0200, start: 0x0200, success: Success::Result(DECIMAL_ERROR), port: None },
+    Suite{ name: "6502_interrupt_test", org: 0x0000, start: 0x0400, success: Success::Trap(0x06F5), port: Some(INTERRUPT_PORT) },
+];
+
+// Flat memory with the interrupt test's feedback port, an NMI is raised on the rising edge of bit 1
+struct Feedback{
+    mem: Memory,
+    port: Option<u16>,
+    value: u8,
+    nmi: bool,
+}
+
+impl Bus for Feedback{
+    fn read(&mut self, addr: u16) -> u8{
+        self.mem.read(addr)
+    }
+
+    fn write(&mut self, addr: u16, value: u8){
+        if Some(addr) == self.port {
+            self.nmi |= value&0x02 != 0 && self.value&0x02 == 0;
+            self.value = value;
+        }
+        self.mem.write(addr, value);
+    }
+
+    fn peek(&self, addr: u16) -> u8{
+        self.mem.read(addr)
+    }
+}
+
+// Runs until PC is stuck in a loop on itself or the CPU stops, returns where
+fn run(cpu: &mut Processor, bus: &mut Feedback) -> Result<(u16, u64), Box<dyn Error>>{
+    let mut instructions = 0;
+    loop {
+        let finished = match cpu.tick(bus) {
+            Ok(finished) => finished,
+            Err(CpuError::UnknownOpcode{ pc, .. } | CpuError::Jammed{ pc }) => return Ok((pc, instructions)),
+        };
+        if bus.port.is_some() {
+            cpu.irq(bus.value&0x01 != 0);
+            if std::mem::take(&mut bus.nmi) {
+                cpu.nmi();
+            }
+        }
+        let Some(info) = finished else { continue };
+        if cpu.state() == RunState::Stopped {
+            return Ok((info.pc, instructions));
+        }
+        if matches!(info.event, Event::Instruction(_)) {
+            if cpu.pc == info.pc {
+                return Ok((info.pc, instructions));
+            }
+            instructions += 1;
+            if instructions == MAX_INSTRUCTIONS {
+                return Err(format!("no trap after {} instructions, PC at ${:04X}", instructions, cpu.pc).into());
+            }
+        }
+    }
+}
+
+fn suite(suite: &Suite, image: &[u8]) -> Result<(), Box<dyn Error>>{
+    let mut mem = Memory::new();
+    let org = if image.len() == 0x10000 { 0 } else { suite.org };
+    if org as usize + image.len() > 0x10000 {
+        return Err(format!("{}: {} bytes do not fit at ${:04X}", suite.name, image.len(), org).into());
+    }
+    for (i, &byte) in image.iter().enumerate() {
+        mem.write(org + i as u16, byte);
+    }
+    let mut bus = Feedback{ mem, port: suite.port, value: 0, nmi: false };
+    let mut cpu = Processor::new();
+    // The suites only use documented opcodes, anything else means the CPU ran off
+    cpu.undocumented = false;
+    cpu.pc = suite.start;
+
+    let (trap, instructions) = run(&mut cpu, &mut bus).map_err(|err| format!("{}: {}", suite.name, err))?;
+    match suite.success {
+        Success::Trap(addr) if trap == addr => (),
+        Success::Trap(_) => return Err(format!("{}: failed test ${:02X}, trapped at ${:04X}",
+            suite.name, bus.mem.read(TEST_CASE), trap).into()),
+        Success::Result(addr) if bus.mem.read(addr) == 0 => (),
+        Success::Result(_) => return Err(format!("{}: failed with operands ${:02X} and ${:02X}, stopped at ${:04X}",
+            suite.name, bus.mem.read(DECIMAL_N1), bus.mem.read(DECIMAL_N2), trap).into()),
+    }
+    println!("{} passed, trapped at ${:04X} after {} instructions", suite.name, trap, instructions);
+    Ok(())
+}
+
+// Runs the suites found in dir as <name>.bin, the binaries are not shipped so missing ones are skipped
+pub fn klaus(dir: &Path) -> Result<(), Box<dyn Error>>{
+    for suite in &SUITES {
+        let path = dir.join(format!("{}.bin", suite.name));
+        match fs::read(&path) {
+            Ok(image) => self::suite(suite, &image)?,
+            Err(_) => println!("{} skipped, {} not found", suite.name, path.display()),
+        }
+    }
+    Ok(())
+}
@@ -1,24 +1,88 @@
-use core::panic;
-use std::fs;
+use std::error::Error;
+use std::fmt::Display;
+use std::{fs, io};
 
-use crate::memory::Memory;
+use crate::bus::Bus;
+use crate::cartridge::Cartridge;
 
+pub mod apu;
+pub mod assembler;
+pub mod bus;
+pub mod cartridge;
+pub mod controller;
+pub mod crc;
+pub mod debugger;
+pub mod disasm;
+pub mod headless;
+pub mod json;
+pub mod mapper;
+pub mod nes;
+pub mod png;
+pub mod ppu;
+pub mod state;
+pub mod trace;
 pub mod processor;
+pub mod wav;
 pub mod memory;
 pub mod op;
 
-pub fn read_rom(path: &str) -> Vec<u8> {
-    fs::read(path).expect("Reading ROM failed")
+#[derive(Debug)]
+pub enum RomError{
+    Io(io::Error),
+    // File is shorter than the 16 byte iNES header
+    TooShort{ len: usize },
+    // Header does not start with "NES\x1A"
+    BadMagic([u8; 4]),
+    // Header declares no PRG ROM at all
+    NoPrgRom,
+    // Only 16KB and 32KB of PRG ROM fit the flat address space
+    UnsupportedPrgSize(usize),
+    UnsupportedMapper(u16),
+    // Header promises more ROM than the file holds
+    Truncated{ expected: usize, actual: usize },
 }
 
-pub fn load_rom(bus: &mut Memory, rom: &[u8]){
+impl Display for RomError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RomError::Io(err) => write!(f, "reading ROM failed: {err}"),
+            RomError::TooShort{ len } => write!(f, "ROM is {len} bytes, shorter than the 16 byte header"),
+            RomError::BadMagic(magic) => write!(f, "bad iNES magic {magic:02X?}"),
+            RomError::NoPrgRom => write!(f, "ROM has no PRG ROM"),
+            RomError::UnsupportedPrgSize(size) => write!(f, "unsupported PRG size of {size} bytes"),
+            RomError::UnsupportedMapper(mapper) => write!(f, "unsupported mapper {mapper}"),
+            RomError::Truncated{ expected, actual } => write!(f, "ROM truncated, expected {expected} bytes but found {actual}"),
+        }
+    }
+}
+
+impl Error for RomError{
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RomError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RomError{
+    fn from(err: io::Error) -> Self {
+        RomError::Io(err)
+    }
+}
+
+pub fn read_rom(path: &str) -> Result<Vec<u8>, RomError> {
+    Ok(fs::read(path)?)
+}
+
+pub fn load_rom<B: Bus>(bus: &mut B, rom: &[u8]){
     rom.iter().enumerate().for_each(|(i, &byte)|{
         let addr = 0x8000_u16.wrapping_add(i as u16);
         bus.write(addr, byte);
     });
 }
 
-pub fn load_rom_16kb(bus: &mut Memory, rom: &[u8]){
+pub fn load_rom_16kb<B: Bus>(bus: &mut B, rom: &[u8]){
     let buf: Vec<u8> = rom.to_vec().iter().chain(rom.to_vec().iter().rev()).copied().collect();
     buf.iter().enumerate().for_each(|(i, &byte)|{
         let addr = 0x8000_u16.wrapping_add(i as u16);
@@ -26,13 +90,12 @@ pub fn load_rom_16kb(bus: &mut Memory, rom: &[u8]){
     });
 }
 
-pub fn load_nes(bus: &mut Memory, rom: &[u8]){
-    let header = &rom[0..16];
-    let prg_banks = header[4] as usize;
-    let prg_size = prg_banks * 16 * 1024;
-    let prg_start = 16;
-    let prg_end = prg_start + prg_size;
-    let prg = &rom[prg_start..prg_end];
+pub fn load_nes<B: Bus>(bus: &mut B, rom: &[u8]) -> Result<(), RomError>{
+    let cartridge = Cartridge::from_bytes(rom)?;
+    if cartridge.mapper != 0 {
+        return Err(RomError::UnsupportedMapper(cartridge.mapper));
+    }
+    let prg = &cartridge.prg_rom;
     match prg.len() {
         0x4000 => {
             (0x8000u16..=0xFFFFu16).zip(prg.iter().cycle()).for_each(|(addr, &byte)|{bus.write(addr, byte);});
@@ -40,6 +103,7 @@ pub fn load_nes(bus: &mut Memory, rom: &[u8]){
         0x8000 => {
             (0x8000u16..=0xFFFFu16).zip(prg.iter()).for_each(|(addr, &byte)|{bus.write(addr, byte);});
         },
-        _ => panic!("Unsupported PRG size")
+        size => return Err(RomError::UnsupportedPrgSize(size))
     }
-}
\ No newline at end of file
+    Ok(())
+}
@@ -1,27 +1,28 @@
 mod nestest;
-mod ppu;
+mod brktest;
+mod klaus;
+mod singlestep;
+mod cli;
+mod monitor;
 use nestest::nestest;
-// fn prompt(cpu: &mut Processor, mem: &mut Memory) -> u8{
-//     let mut buf = String::new();
-//     io::stdin().read_line(&mut buf).unwrap();
-//     let cmd = buf.trim();
-
-//     match cmd{
-//         "e" => return 2,
-//         "s" => {
-//             println!("{:X}",mem.read(((cpu.s  as u16) + 1) | 0x100));
-//             return 1    
-//         },
-//         _ => match cmd.parse::<u8>() {
-//                 Ok(value) => {
-//                     mem.display_pg(value);
-//                     return 1
-//                 },
-//                 Err(_) => return 0
-//         }
-//     }
-// }
+use brktest::brktest;
+use klaus::klaus;
+use singlestep::singlestep;
 
 fn main(){
-    nestest().unwrap();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() {
+        nestest().unwrap();
+        brktest();
+        klaus(std::path::Path::new("test")).unwrap();
+        // Failures are listed per opcode, the run still ends normally
+        if let Err(err) = singlestep(std::path::Path::new("test/6502/v1"), emulator_6502::processor::Variant::Nmos, true, None) {
+            println!("{}", err);
+        }
+        return;
+    }
+    if let Err(err) = cli::run(args) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,127 @@
+use crate::RomError;
+use crate::cartridge::{Cartridge, Mirroring};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+pub mod nrom;
+pub mod mmc1;
+pub mod uxrom;
+pub mod cnrom;
+pub mod mmc3;
+pub mod axrom;
+
+// Cartridge board as seen from the CPU ($4020-$FFFF) and the PPU pattern tables ($0000-$1FFF),
+// save states cover its registers and RAM
+pub trait Mapper: Snapshot{
+    // None leaves the CPU data bus floating
+    fn cpu_read(&mut self, addr: u16) -> Option<u8>;
+    fn cpu_write(&mut self, addr: u16, value: u8);
+    fn cpu_peek(&self, addr: u16) -> Option<u8>;
+    fn ppu_read(&mut self, addr: u16) -> u8;
+    fn ppu_write(&mut self, addr: u16, value: u8);
+    fn mirroring(&self) -> Mirroring;
+    // Level of the cartridge IRQ line
+    fn irq(&self) -> bool{
+        false
+    }
+    // Clocked by the PPU once per rendering scanline, stands in for watching PPU A12
+    fn scanline(&mut self){}
+    // Clocked once per CPU cycle, after the cycle's bus access
+    fn cpu_clock(&mut self){}
+}
+
+// ROM and RAM every board carries, the mappers only decide which bank is where
+pub struct Banks{
+    pub prg_rom: Vec<u8>,
+    pub chr: Vec<u8>,
+    // Boards without CHR ROM have writable CHR RAM instead
+    pub chr_ram: bool,
+    pub prg_ram: Vec<u8>,
+    pub mirroring: Mirroring,
+}
+
+impl Banks{
+
+    pub fn new(cartridge: Cartridge) -> Banks{
+        let chr_ram = cartridge.chr_rom.is_empty();
+        let chr = if chr_ram {
+            vec![0; (cartridge.chr_ram_size + cartridge.chr_nvram_size).max(0x2000)]
+        } else {
+            cartridge.chr_rom
+        };
+        Banks{
+            prg_rom: cartridge.prg_rom,
+            chr,
+            chr_ram,
+            prg_ram: vec![0; cartridge.prg_ram_size + cartridge.prg_nvram_size],
+            mirroring: cartridge.mirroring,
+        }
+    }
+
+    // Number of banks of the given size, bank numbers wrap around it
+    pub fn prg_banks(&self, size: usize) -> usize{
+        (self.prg_rom.len() / size).max(1)
+    }
+
+    pub fn prg(&self, bank: usize, size: usize, addr: u16) -> u8{
+        let offset = (bank * size + (addr as usize & (size - 1))) % self.prg_rom.len();
+        self.prg_rom[offset]
+    }
+
+    pub fn chr(&self, bank: usize, size: usize, addr: u16) -> u8{
+        let offset = (bank * size + (addr as usize & (size - 1))) % self.chr.len();
+        self.chr[offset]
+    }
+
+    pub fn write_chr(&mut self, bank: usize, size: usize, addr: u16, value: u8){
+        if self.chr_ram {
+            let offset = (bank * size + (addr as usize & (size - 1))) % self.chr.len();
+            self.chr[offset] = value;
+        }
+    }
+
+    // PRG RAM at $6000-$7FFF, absent on most discrete boards
+    pub fn ram(&self, addr: u16) -> Option<u8>{
+        if self.prg_ram.is_empty() {
+            return None;
+        }
+        Some(self.prg_ram[(addr as usize - 0x6000) % self.prg_ram.len()])
+    }
+
+    pub fn write_ram(&mut self, addr: u16, value: u8){
+        if !self.prg_ram.is_empty() {
+            let len = self.prg_ram.len();
+            self.prg_ram[(addr as usize - 0x6000) % len] = value;
+        }
+    }
+}
+
+// ROM is not saved, the state only loads into the same cartridge
+impl Snapshot for Banks{
+    fn save(&self, w: &mut StateWriter){
+        w.vec(&self.prg_ram);
+        if self.chr_ram {
+            w.vec(&self.chr);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        r.vec(&mut self.prg_ram)?;
+        if self.chr_ram {
+            r.vec(&mut self.chr)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn new(cartridge: Cartridge) -> Result<Box<dyn Mapper>, RomError>{
+    let mapper: Box<dyn Mapper> = match cartridge.mapper {
+        0 => Box::new(nrom::Nrom::new(cartridge)),
+        1 => Box::new(mmc1::Mmc1::new(cartridge)),
+        2 => Box::new(uxrom::Uxrom::new(cartridge)),
+        3 => Box::new(cnrom::Cnrom::new(cartridge)),
+        4 => Box::new(mmc3::Mmc3::new(cartridge)),
+        7 => Box::new(axrom::Axrom::new(cartridge)),
+        mapper => return Err(RomError::UnsupportedMapper(mapper)),
+    };
+    Ok(mapper)
+}
@@ -0,0 +1,62 @@
+use crate::cartridge::{Cartridge, Mirroring};
+use crate::mapper::{Banks, Mapper};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// Mapper 7, switchable 32KB PRG and a register bit picking the single-screen nametable
+pub struct Axrom{
+    banks: Banks,
+    bank: u8,
+}
+
+impl Axrom{
+    pub fn new(cartridge: Cartridge) -> Axrom{
+        Axrom{ banks: Banks::new(cartridge), bank: 0 }
+    }
+}
+
+impl Mapper for Axrom{
+    fn cpu_read(&mut self, addr: u16) -> Option<u8>{
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8){
+        match addr {
+            0x6000..=0x7FFF => self.banks.write_ram(addr, value),
+            0x8000..=0xFFFF => self.bank = value,
+            _ => (),
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8>{
+        match addr {
+            0x6000..=0x7FFF => self.banks.ram(addr),
+            0x8000..=0xFFFF => Some(self.banks.prg((self.bank&0x07) as usize, 0x8000, addr)),
+            _ => None,
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8{
+        self.banks.chr(0, 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8){
+        self.banks.write_chr(0, 0x2000, addr, value);
+    }
+
+    fn mirroring(&self) -> Mirroring{
+        if self.bank&0x10 != 0 { Mirroring::SingleUpper } else { Mirroring::SingleLower }
+    }
+}
+
+impl Snapshot for Axrom{
+    fn save(&self, w: &mut StateWriter){
+        self.banks.save(w);
+        w.u8(self.bank);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        self.banks.load(r)?;
+        self.bank = r.u8()?;
+        Ok(())
+    }
+}
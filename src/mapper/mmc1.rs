@@ -0,0 +1,170 @@
+use crate::cartridge::{Cartridge, Mirroring};
+use crate::mapper::{Banks, Mapper};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// Mapper 1, registers are loaded one bit at a time through a 5 bit shift register
+pub struct Mmc1{
+    banks: Banks,
+    shift: u8,
+    // Count of bits in the shift register
+    count: u8,
+    control: u8,
+    chr0: u8,
+    chr1: u8,
+    prg: u8,
+    // CPU cycles seen and the one of the last serial write, the second write of a
+    // read-modify-write comes on the very next cycle and the board ignores it
+    cycle: u64,
+    last_write: Option<u64>,
+}
+
+impl Mmc1{
+    pub fn new(cartridge: Cartridge) -> Mmc1{
+        Mmc1{
+            banks: Banks::new(cartridge),
+            shift: 0,
+            count: 0,
+            // Power up in the mode with the last bank fixed at $C000
+            control: 0x0C,
+            chr0: 0,
+            chr1: 0,
+            prg: 0,
+            cycle: 0,
+            last_write: None,
+        }
+    }
+
+    // SUROM and friends use CHR bit 4 to pick the 256KB half of a 512KB PRG ROM
+    fn prg_outer(&self) -> usize{
+        if self.banks.prg_rom.len() > 0x40000 { (self.chr0&0x10) as usize } else { 0 }
+    }
+
+    fn prg_bank(&self, addr: u16) -> usize{
+        let bank = (self.prg&0x0F) as usize;
+        let last = (self.banks.prg_banks(0x4000) - 1).min(0x0F);
+        let bank = match (self.control >> 2)&0x03 {
+            0 | 1 => (bank&!1) + (addr >= 0xC000) as usize,
+            2 => if addr < 0xC000 { 0 } else { bank },
+            _ => if addr < 0xC000 { bank } else { last },
+        };
+        self.prg_outer() | bank
+    }
+
+    fn chr_bank(&self, addr: u16) -> usize{
+        if self.control&0x10 == 0 {
+            ((self.chr0&0x1E) as usize) + (addr >= 0x1000) as usize
+        } else if addr < 0x1000 {
+            self.chr0 as usize
+        } else {
+            self.chr1 as usize
+        }
+    }
+
+    fn ram_enabled(&self) -> bool{
+        self.prg&0x10 == 0
+    }
+
+    fn load(&mut self, addr: u16, value: u8){
+        let consecutive = self.last_write.is_some_and(|cycle| cycle + 1 == self.cycle);
+        self.last_write = Some(self.cycle);
+        if consecutive {
+            return;
+        }
+        // Writing with bit 7 set clears the shift register and locks PRG mode 3
+        if value&0x80 != 0 {
+            self.shift = 0;
+            self.count = 0;
+            self.control |= 0x0C;
+            return;
+        }
+        self.shift |= (value&0x01) << self.count;
+        self.count += 1;
+        if self.count < 5 {
+            return;
+        }
+        match addr {
+            0x8000..=0x9FFF => self.control = self.shift,
+            0xA000..=0xBFFF => self.chr0 = self.shift,
+            0xC000..=0xDFFF => self.chr1 = self.shift,
+            _ => self.prg = self.shift,
+        }
+        self.shift = 0;
+        self.count = 0;
+    }
+}
+
+impl Mapper for Mmc1{
+    fn cpu_read(&mut self, addr: u16) -> Option<u8>{
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8){
+        match addr {
+            0x6000..=0x7FFF if self.ram_enabled() => self.banks.write_ram(addr, value),
+            0x8000..=0xFFFF => self.load(addr, value),
+            _ => (),
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8>{
+        match addr {
+            0x6000..=0x7FFF => if self.ram_enabled() { self.banks.ram(addr) } else { None },
+            0x8000..=0xFFFF => Some(self.banks.prg(self.prg_bank(addr), 0x4000, addr)),
+            _ => None,
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8{
+        self.banks.chr(self.chr_bank(addr), 0x1000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8){
+        self.banks.write_chr(self.chr_bank(addr), 0x1000, addr, value);
+    }
+
+    fn mirroring(&self) -> Mirroring{
+        match self.control&0x03 {
+            0 => Mirroring::SingleLower,
+            1 => Mirroring::SingleUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn cpu_clock(&mut self){
+        self.cycle += 1;
+    }
+}
+
+impl Snapshot for Mmc1{
+    fn save(&self, w: &mut StateWriter){
+        self.banks.save(w);
+        w.u8(self.shift);
+        w.u8(self.count);
+        w.u8(self.control);
+        w.u8(self.chr0);
+        w.u8(self.chr1);
+        w.u8(self.prg);
+        w.u64(self.cycle);
+        w.bool(self.last_write.is_some());
+        w.u64(self.last_write.unwrap_or(0));
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        self.banks.load(r)?;
+        self.shift = r.u8()?;
+        self.count = r.u8()?;
+        if self.count >= 5 {
+            return Err(StateError::Invalid("MMC1 shift count"));
+        }
+        self.control = r.u8()?;
+        self.chr0 = r.u8()?;
+        self.chr1 = r.u8()?;
+        self.prg = r.u8()?;
+        self.cycle = r.u64()?;
+        let written = r.bool()?;
+        let last_write = r.u64()?;
+        self.last_write = written.then_some(last_write);
+        Ok(())
+    }
+}
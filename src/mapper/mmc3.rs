@@ -0,0 +1,166 @@
+use crate::cartridge::{Cartridge, Mirroring};
+use crate::mapper::{Banks, Mapper};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// Mapper 4, eight bank registers behind a select register and a scanline counter driving IRQ
+pub struct Mmc3{
+    banks: Banks,
+    select: u8,
+    registers: [u8; 8],
+    mirroring: Mirroring,
+    ram_enabled: bool,
+    ram_protected: bool,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl Mmc3{
+    pub fn new(cartridge: Cartridge) -> Mmc3{
+        let banks = Banks::new(cartridge);
+        let mirroring = banks.mirroring;
+        Mmc3{
+            banks,
+            select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring,
+            ram_enabled: true,
+            ram_protected: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    fn prg_bank(&self, addr: u16) -> usize{
+        let second_last = self.banks.prg_banks(0x2000).saturating_sub(2);
+        let r6 = (self.registers[6]&0x3F) as usize;
+        let r7 = (self.registers[7]&0x3F) as usize;
+        // PRG mode 1 swaps $8000 and $C000
+        match (addr >> 13)&0x03 {
+            0 => if self.select&0x40 == 0 { r6 } else { second_last },
+            1 => r7,
+            2 => if self.select&0x40 == 0 { second_last } else { r6 },
+            _ => second_last + 1,
+        }
+    }
+
+    // CHR is addressed in 1KB units, R0 and R1 are 2KB banks
+    fn chr_bank(&self, addr: u16) -> usize{
+        // CHR inversion swaps the 2KB and 1KB halves of the pattern tables
+        let addr = if self.select&0x80 != 0 { addr ^ 0x1000 } else { addr };
+        let slot = (addr >> 10)&0x07;
+        match slot {
+            0 | 1 => (self.registers[0]&0xFE) as usize + slot as usize,
+            2 | 3 => (self.registers[1]&0xFE) as usize + slot as usize - 2,
+            _ => self.registers[slot as usize - 2] as usize,
+        }
+    }
+}
+
+impl Mapper for Mmc3{
+    fn cpu_read(&mut self, addr: u16) -> Option<u8>{
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8){
+        match (addr, addr&0x01) {
+            (0x6000..=0x7FFF, _) if self.ram_enabled && !self.ram_protected => self.banks.write_ram(addr, value),
+            (0x8000..=0x9FFF, 0) => self.select = value,
+            (0x8000..=0x9FFF, _) => self.registers[(self.select&0x07) as usize] = value,
+            (0xA000..=0xBFFF, 0) if self.banks.mirroring != Mirroring::FourScreen => {
+                self.mirroring = if value&0x01 == 0 { Mirroring::Vertical } else { Mirroring::Horizontal };
+            },
+            (0xA000..=0xBFFF, 1) => {
+                self.ram_enabled = value&0x80 != 0;
+                self.ram_protected = value&0x40 != 0;
+            },
+            (0xC000..=0xDFFF, 0) => self.irq_latch = value,
+            (0xC000..=0xDFFF, _) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            },
+            (0xE000..=0xFFFF, 0) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            },
+            (0xE000..=0xFFFF, _) => self.irq_enabled = true,
+            _ => (),
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8>{
+        match addr {
+            0x6000..=0x7FFF => if self.ram_enabled { self.banks.ram(addr) } else { None },
+            0x8000..=0xFFFF => Some(self.banks.prg(self.prg_bank(addr), 0x2000, addr)),
+            _ => None,
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8{
+        self.banks.chr(self.chr_bank(addr), 0x0400, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8){
+        self.banks.write_chr(self.chr_bank(addr), 0x0400, addr, value);
+    }
+
+    fn mirroring(&self) -> Mirroring{
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool{
+        self.irq_pending
+    }
+
+    fn scanline(&mut self){
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Snapshot for Mmc3{
+    fn save(&self, w: &mut StateWriter){
+        self.banks.save(w);
+        w.u8(self.select);
+        w.bytes(&self.registers);
+        // Four-screen boards never leave their wiring, the others are vertical or horizontal
+        w.bool(self.mirroring == Mirroring::Horizontal);
+        w.bool(self.ram_enabled);
+        w.bool(self.ram_protected);
+        w.u8(self.irq_latch);
+        w.u8(self.irq_counter);
+        w.bool(self.irq_reload);
+        w.bool(self.irq_enabled);
+        w.bool(self.irq_pending);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        self.banks.load(r)?;
+        self.select = r.u8()?;
+        r.bytes(&mut self.registers)?;
+        let horizontal = r.bool()?;
+        if self.banks.mirroring != Mirroring::FourScreen {
+            self.mirroring = if horizontal { Mirroring::Horizontal } else { Mirroring::Vertical };
+        }
+        self.ram_enabled = r.bool()?;
+        self.ram_protected = r.bool()?;
+        self.irq_latch = r.u8()?;
+        self.irq_counter = r.u8()?;
+        self.irq_reload = r.bool()?;
+        self.irq_enabled = r.bool()?;
+        self.irq_pending = r.bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+use crate::cartridge::{Cartridge, Mirroring};
+use crate::mapper::{Banks, Mapper};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// Mapper 0, 16KB PRG is mirrored into both halves of $8000-$FFFF
+pub struct Nrom{
+    banks: Banks,
+}
+
+impl Nrom{
+    pub fn new(cartridge: Cartridge) -> Nrom{
+        Nrom{ banks: Banks::new(cartridge) }
+    }
+}
+
+impl Mapper for Nrom{
+    fn cpu_read(&mut self, addr: u16) -> Option<u8>{
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8){
+        if (0x6000..0x8000).contains(&addr) {
+            self.banks.write_ram(addr, value);
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8>{
+        match addr {
+            0x6000..=0x7FFF => self.banks.ram(addr),
+            0x8000..=0xFFFF => Some(self.banks.prg(0, 0x8000, addr)),
+            _ => None,
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8{
+        self.banks.chr(0, 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8){
+        self.banks.write_chr(0, 0x2000, addr, value);
+    }
+
+    fn mirroring(&self) -> Mirroring{
+        self.banks.mirroring
+    }
+}
+
+impl Snapshot for Nrom{
+    fn save(&self, w: &mut StateWriter){
+        self.banks.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        self.banks.load(r)
+    }
+}
@@ -0,0 +1,63 @@
+use crate::cartridge::{Cartridge, Mirroring};
+use crate::mapper::{Banks, Mapper};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+// Mapper 2, switchable 16KB at $8000 and the last 16KB fixed at $C000
+pub struct Uxrom{
+    banks: Banks,
+    bank: u8,
+}
+
+impl Uxrom{
+    pub fn new(cartridge: Cartridge) -> Uxrom{
+        Uxrom{ banks: Banks::new(cartridge), bank: 0 }
+    }
+}
+
+impl Mapper for Uxrom{
+    fn cpu_read(&mut self, addr: u16) -> Option<u8>{
+        self.cpu_peek(addr)
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8){
+        match addr {
+            0x6000..=0x7FFF => self.banks.write_ram(addr, value),
+            0x8000..=0xFFFF => self.bank = value,
+            _ => (),
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8>{
+        match addr {
+            0x6000..=0x7FFF => self.banks.ram(addr),
+            0x8000..=0xBFFF => Some(self.banks.prg(self.bank as usize, 0x4000, addr)),
+            0xC000..=0xFFFF => Some(self.banks.prg(self.banks.prg_banks(0x4000) - 1, 0x4000, addr)),
+            _ => None,
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8{
+        self.banks.chr(0, 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, value: u8){
+        self.banks.write_chr(0, 0x2000, addr, value);
+    }
+
+    fn mirroring(&self) -> Mirroring{
+        self.banks.mirroring
+    }
+}
+
+impl Snapshot for Uxrom{
+    fn save(&self, w: &mut StateWriter){
+        self.banks.save(w);
+        w.u8(self.bank);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        self.banks.load(r)?;
+        self.bank = r.u8()?;
+        Ok(())
+    }
+}
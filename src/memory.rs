@@ -1,3 +1,6 @@
+use crate::bus::Bus;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 #[derive(Debug)]
 pub struct Memory{
     data: [u8; 65536]
@@ -7,6 +10,12 @@ fn get_pgaddr(page: u8) -> u16 {
     (page as u16)<<8
 }
 
+impl Default for Memory{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Memory{
     pub fn new() -> Memory{
         Memory{data: [0;65536]}
@@ -33,4 +42,28 @@ impl Memory{
         let end = (get_pgaddr(page) | 0xFF) as usize;
         println!("{:x?}", &self.data[start..end]);
     }
-}
\ No newline at end of file
+}
+
+impl Bus for Memory{
+    fn read(&mut self, addr: u16) -> u8 {
+        self.data[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.data[addr as usize] = value;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.data[addr as usize]
+    }
+}
+
+impl Snapshot for Memory{
+    fn save(&self, w: &mut StateWriter){
+        w.bytes(&self.data);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        r.bytes(&mut self.data)
+    }
+}
@@ -0,0 +1,179 @@
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+
+use emulator_6502::bus::Bus;
+use emulator_6502::cartridge::Cartridge;
+use emulator_6502::disasm::{Instruction, decode, disassemble_variant};
+use emulator_6502::debugger::{Access, Compare, Condition, Debugger, HistoryEntry, Machine, Register, Stop, System};
+use emulator_6502::memory::Memory;
+use emulator_6502::nes::Nes;
+use emulator_6502::processor::{CpuError, Processor};
+
+const HELP: &str = "commands (numbers are hex, $ is optional):
+  s [n]                 step n instructions
+  n                     step over a JSR
+  f                     finish, run until the subroutine returns
+  c                     continue until a breakpoint or watchpoint
+  u <cycle>             run until the cycle count (decimal)
+  b <addr> [reg op n]   breakpoint, optionally when e.g. x == 10
+  bd <addr>             delete breakpoints at addr
+  w <addr>[-end] [r|w]  watchpoint on reads, writes or both
+  wd <addr>             delete watchpoints starting at addr
+  l                     list breakpoints and watchpoints
+  r [reg value]         show registers or set one
+  m <addr> [len]        dump memory
+  e <addr> <bytes..>    write bytes to memory
+  h [n]                 last n executed instructions
+  q                     quit";
+
+fn number(text: &str) -> Result<u16, Box<dyn Error>>{
+    let digits = text.trim_start_matches('$');
+    Ok(u16::from_str_radix(digits, 16).map_err(|_| format!("bad number {:?}", text))?)
+}
+
+fn instruction(instruction: Instruction) -> String{
+    let hex: Vec<String> = instruction.bytes().iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!("{:04X}  {:<8}  {:<14}", instruction.addr, hex.join(" "), instruction.to_string())
+}
+
+fn registers<M: Machine>(machine: &M, debugger: &Debugger) -> String{
+    let cpu = machine.cpu();
+    format!("{}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+        instruction(disassemble_variant(machine.bus(), cpu.pc, cpu.variant)), cpu.a, cpu.x, cpu.y, cpu.p, cpu.s, debugger.cycles())
+}
+
+fn history(entry: &HistoryEntry, cpu: &Processor) -> String{
+    format!("{}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+        instruction(decode(entry.pc, entry.bytes, cpu.variant)), entry.a, entry.x, entry.y, entry.p, entry.s, entry.cycle)
+}
+
+fn report(stop: Result<Stop, CpuError>){
+    match stop {
+        Ok(Stop::Breakpoint{ pc }) => println!("breakpoint at ${:04X}", pc),
+        Ok(Stop::Watchpoint(hit)) => println!("{} ${:04X} = ${:02X} by instruction at ${:04X}",
+            if hit.write { "write" } else { "read" }, hit.addr, hit.value, hit.pc),
+        Ok(Stop::Returned) => println!("returned"),
+        Ok(Stop::CycleReached{ cycle }) => println!("stopped at cycle {}", cycle),
+        Ok(Stop::Stepped) => (),
+        // The CPU stays usable for inspection after a jam or a rejected opcode
+        Err(err) => println!("{}", err),
+    }
+}
+
+fn command<M: Machine>(line: &str, machine: &mut M, debugger: &mut Debugger) -> Result<bool, Box<dyn Error>>{
+    let args: Vec<&str> = line.split_whitespace().collect();
+    let Some((&name, args)) = args.split_first() else { return Ok(true) };
+    match (name, args) {
+        ("q", _) => return Ok(false),
+        ("s", []) => report(debugger.step(machine)),
+        ("s", [count]) => report(debugger.step_n(machine, count.parse()?)),
+        ("n", []) => report(debugger.step_over(machine)),
+        ("f", []) => report(debugger.step_out(machine)),
+        ("c", []) => report(debugger.run(machine)),
+        ("u", [cycle]) => report(debugger.run_until_cycle(machine, cycle.parse()?)),
+        ("b", [addr]) => debugger.add_breakpoint(number(addr)?, None),
+        ("b", [addr, register, compare, value]) => {
+            let register = Register::from_name(register).ok_or("registers are a, x, y, s, p and pc")?;
+            let compare = Compare::from_symbol(compare).ok_or("comparisons are == != < <= > >=")?;
+            debugger.add_breakpoint(number(addr)?, Some(Condition{ register, compare, value: number(value)? }));
+        },
+        ("bd", [addr]) => debugger.remove_breakpoint(number(addr)?),
+        ("w", [range, access @ ..]) if access.len() <= 1 => {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let access = match access.first().copied() {
+                None | Some("rw") => Access::ReadWrite,
+                Some("r") => Access::Read,
+                Some("w") => Access::Write,
+                Some(other) => return Err(format!("bad access {:?}, use r, w or rw", other).into()),
+            };
+            debugger.add_watchpoint(number(start)?..=number(end)?, access);
+        },
+        ("wd", [addr]) => debugger.remove_watchpoint(number(addr)?),
+        ("l", []) => {
+            for breakpoint in debugger.breakpoints() {
+                match breakpoint.condition {
+                    Some(condition) => println!("break ${:04X} if {:?} {:?} ${:X}", breakpoint.pc, condition.register, condition.compare, condition.value),
+                    None => println!("break ${:04X}", breakpoint.pc),
+                }
+            }
+            for watch in debugger.watchpoints() {
+                println!("watch ${:04X}-${:04X} {:?}", watch.range.start(), watch.range.end(), watch.access);
+            }
+        },
+        ("r", []) => (),
+        ("r", [register, value]) => Register::from_name(register).ok_or("registers are a, x, y, s, p and pc")?.set(machine.cpu_mut(), number(value)?),
+        ("m", [addr, len @ ..]) if len.len() <= 1 => {
+            let start = number(addr)?;
+            let len = len.first().map(|len| number(len)).transpose()?.unwrap_or(0x40);
+            for row in (0..len).step_by(16) {
+                let bytes: Vec<String> = (row..len.min(row + 16)).map(|i| format!("{:02X}", machine.bus().peek(start.wrapping_add(i)))).collect();
+                println!("{:04X}  {}", start.wrapping_add(row), bytes.join(" "));
+            }
+            return Ok(true);
+        },
+        ("e", [addr, bytes @ ..]) if !bytes.is_empty() => {
+            let start = number(addr)?;
+            for (i, byte) in bytes.iter().enumerate() {
+                machine.bus_mut().write(start.wrapping_add(i as u16), number(byte)? as u8);
+            }
+            return Ok(true);
+        },
+        ("h", count) if count.len() <= 1 => {
+            let count = count.first().map(|count| count.parse()).transpose()?.unwrap_or(16);
+            let skip = debugger.history().count().saturating_sub(count);
+            for entry in debugger.history().skip(skip) {
+                println!("{}", history(entry, machine.cpu()));
+            }
+            return Ok(true);
+        },
+        _ => {
+            println!("{}", HELP);
+            return Ok(true);
+        },
+    }
+    println!("{}", registers(machine, debugger));
+    Ok(true)
+}
+
+fn session<M: Machine>(machine: &mut M, debugger: &mut Debugger) -> Result<(), Box<dyn Error>>{
+    println!("{}", registers(machine, debugger));
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        match command(&line, machine, debugger) {
+            Ok(true) => (),
+            Ok(false) => return Ok(()),
+            Err(err) => println!("{}", err),
+        }
+    }
+}
+
+// Interactive monitor, .nes files run on the whole console and raw binaries load at `org` into a flat 64KB memory
+pub fn monitor(path: &str, org: u16, pc: Option<u16>) -> Result<(), Box<dyn Error>>{
+    let rom = emulator_6502::read_rom(path)?;
+    let mut debugger = Debugger::new();
+    if path.to_ascii_lowercase().ends_with(".nes") {
+        let mut nes = Nes::new(Cartridge::from_bytes(&rom)?)?;
+        if let Some(pc) = pc {
+            nes.cpu.pc = pc;
+        }
+        debugger.set_cycles(nes.cycles());
+        return session(&mut nes, &mut debugger);
+    }
+    let mut mem = Memory::new();
+    for (i, &byte) in rom.iter().enumerate() {
+        mem.write(org.wrapping_add(i as u16), byte);
+    }
+    let mut cpu = Processor::new();
+    cpu.reset(&mut mem);
+    if let Some(pc) = pc {
+        cpu.pc = pc;
+    }
+    debugger.set_cycles(cpu.cycles as u64);
+    session(&mut System{ cpu, bus: mem }, &mut debugger)
+}
@@ -0,0 +1,391 @@
+use crate::RomError;
+use crate::apu::Apu;
+use crate::bus::{Bus, Observed};
+use crate::cartridge::Cartridge;
+use crate::controller::Controller;
+use crate::mapper::{self, Mapper};
+use crate::ppu::Ppu;
+use crate::processor::{CpuError, Processor, StepInfo};
+use crate::state::{self, Snapshot, StateError, StateReader, StateWriter};
+use crate::trace::{self, TraceClock, TraceFormat};
+
+// CPU address space of the NES: 2KB of RAM, PPU and APU/IO registers and the cartridge
+pub struct NesBus{
+    ram: [u8; 0x800],
+    pub mapper: Box<dyn Mapper>,
+    pub ppu: Ppu,
+    pub apu: Apu,
+    // Joypads on $4016 and $4017
+    pub controllers: [Controller; 2],
+    // Last value on the CPU data bus, reads that nothing answers see it again
+    open_bus: u8,
+    // Last values written to $4000-$4017
+    io: [u8; 0x18],
+    // Page written to $4014, picked up by the DMA unit after the write cycle
+    dma_page: Option<u8>,
+}
+
+impl NesBus{
+
+    pub fn new(mapper: Box<dyn Mapper>) -> NesBus{
+        NesBus{
+            ram: [0; 0x800],
+            mapper,
+            ppu: Ppu::new(),
+            apu: Apu::new(44100),
+            controllers: [Controller::new(); 2],
+            open_bus: 0,
+            io: [0; 0x18],
+            dma_page: None,
+        }
+    }
+
+    pub fn from_cartridge(cartridge: Cartridge) -> Result<NesBus, RomError>{
+        Ok(NesBus::new(mapper::new(cartridge)?))
+    }
+
+    pub fn open_bus(&self) -> u8{
+        self.open_bus
+    }
+
+    pub fn io(&self, addr: u16) -> u8{
+        self.io[(addr - 0x4000) as usize]
+    }
+
+    pub fn take_dma(&mut self) -> Option<u8>{
+        self.dma_page.take()
+    }
+
+    // Runs the PPU for the three dots of one CPU cycle and the APU for one
+    pub fn clock(&mut self){
+        for _ in 0..3 {
+            self.ppu.tick(self.mapper.as_mut());
+        }
+        self.apu.tick();
+        self.mapper.cpu_clock();
+    }
+
+    // Only the APU status and the controller ports drive the bus, and only some of their bits
+    fn io_read(&mut self, addr: u16) -> Option<u8>{
+        match addr {
+            0x4015 => Some(self.apu.read_status()&0xDF | self.open_bus&0x20),
+            0x4016 => Some(self.controllers[0].read() | self.open_bus&0xE0),
+            0x4017 => Some(self.controllers[1].read() | self.open_bus&0xE0),
+            _ => None,
+        }
+    }
+
+    fn io_peek(&self, addr: u16) -> Option<u8>{
+        match addr {
+            0x4015 => Some(self.apu.peek_status()&0xDF | self.open_bus&0x20),
+            0x4016 => Some(self.controllers[0].peek() | self.open_bus&0xE0),
+            0x4017 => Some(self.controllers[1].peek() | self.open_bus&0xE0),
+            _ => None,
+        }
+    }
+
+    fn io_write(&mut self, addr: u16, value: u8){
+        self.io[(addr - 0x4000) as usize] = value;
+        match addr {
+            0x4014 => self.dma_page = Some(value),
+            // The strobe line goes to both ports
+            0x4016 => self.controllers.iter_mut().for_each(|pad| pad.write(value)),
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.write(addr, value),
+            _ => (),
+        }
+    }
+}
+
+impl Bus for NesBus{
+    fn read(&mut self, addr: u16) -> u8{
+        let value = match addr {
+            0x0000..=0x1FFF => Some(self.ram[(addr&0x07FF) as usize]),
+            0x2000..=0x3FFF => Some(self.ppu.read_register(addr, self.mapper.as_mut())),
+            0x4000..=0x4017 => self.io_read(addr),
+            // CPU test mode registers, disabled on retail consoles
+            0x4018..=0x401F => None,
+            _ => self.mapper.cpu_read(addr),
+        };
+        self.open_bus = value.unwrap_or(self.open_bus);
+        self.open_bus
+    }
+
+    fn write(&mut self, addr: u16, value: u8){
+        self.open_bus = value;
+        match addr {
+            0x0000..=0x1FFF => self.ram[(addr&0x07FF) as usize] = value,
+            0x2000..=0x3FFF => self.ppu.write_register(addr, value, self.mapper.as_mut()),
+            0x4000..=0x4017 => self.io_write(addr, value),
+            0x4018..=0x401F => (),
+            _ => self.mapper.cpu_write(addr, value),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8{
+        let value = match addr {
+            0x0000..=0x1FFF => Some(self.ram[(addr&0x07FF) as usize]),
+            0x2000..=0x3FFF => Some(self.ppu.peek_register(addr)),
+            0x4000..=0x4017 => self.io_peek(addr),
+            0x4018..=0x401F => None,
+            _ => self.mapper.cpu_peek(addr),
+        };
+        value.unwrap_or(self.open_bus)
+    }
+}
+
+impl Snapshot for NesBus{
+    fn save(&self, w: &mut StateWriter){
+        w.bytes(&self.ram);
+        w.u8(self.open_bus);
+        w.bytes(&self.io);
+        w.bool(self.dma_page.is_some());
+        w.u8(self.dma_page.unwrap_or(0));
+        self.ppu.save(w);
+        self.apu.save(w);
+        for controller in &self.controllers {
+            controller.save(w);
+        }
+        self.mapper.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        r.bytes(&mut self.ram)?;
+        self.open_bus = r.u8()?;
+        r.bytes(&mut self.io)?;
+        let pending = r.bool()?;
+        let page = r.u8()?;
+        self.dma_page = pending.then_some(page);
+        self.ppu.load(r)?;
+        self.apu.load(r)?;
+        for controller in &mut self.controllers {
+            controller.load(r)?;
+        }
+        self.mapper.load(r)
+    }
+}
+
+// OAM DMA in progress, the CPU is halted while it copies a page to OAMDATA
+#[derive(Debug, Clone, Copy)]
+struct Dma{
+    page: u8,
+    // Cycles before the first read: the halt cycle plus one to align to a read cycle
+    wait: u16,
+    cycle: u16,
+    data: u8,
+}
+
+// DMC sample fetch, the CPU is halted for the halt, dummy and alignment cycles before the read
+#[derive(Debug, Clone, Copy)]
+struct DmcFetch{
+    addr: u16,
+    cycle: u8,
+}
+
+// The console: CPU and bus clocked together, the PPU and cartridge drive the interrupt lines
+pub struct Nes{
+    pub cpu: Processor,
+    pub bus: NesBus,
+    dma: Option<Dma>,
+    dmc: Option<DmcFetch>,
+    // CPU cycles since power on
+    cycles: u64,
+    // Cartridge::hash of the loaded game, save states only load into the same one
+    rom_hash: u32,
+}
+
+impl Nes{
+
+    pub fn new(cartridge: Cartridge) -> Result<Nes, RomError>{
+        let rom_hash = cartridge.hash();
+        let mut bus = NesBus::from_cartridge(cartridge)?;
+        let cpu = Processor::nes(&mut bus);
+        let cycles = cpu.cycles as u64;
+        Ok(Nes{ cpu, bus, dma: None, dmc: None, cycles, rom_hash })
+    }
+
+    pub fn rom_hash(&self) -> u32{
+        self.rom_hash
+    }
+
+    pub fn save_state(&self) -> Vec<u8>{
+        let mut w = StateWriter::new();
+        self.save(&mut w);
+        state::encode(self.rom_hash, &w.into_bytes())
+    }
+
+    // A state that fails to load leaves the machine as it was
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError>{
+        let payload = state::decode(data, self.rom_hash)?;
+        let backup = self.save_state();
+        let mut r = StateReader::new(payload);
+        let mut result = self.load(&mut r);
+        if result.is_ok() && !r.is_empty() {
+            result = Err(StateError::Invalid("payload length"));
+        }
+        if result.is_err() {
+            let mut r = StateReader::new(state::decode(&backup, self.rom_hash)?);
+            self.load(&mut r)?;
+        }
+        result
+    }
+
+    pub fn cycles(&self) -> u64{
+        self.cycles
+    }
+
+    pub fn dma_active(&self) -> bool{
+        self.dma.is_some() || self.dmc.is_some()
+    }
+
+    // Rest of the machine for one CPU cycle: three PPU dots and the interrupt lines
+    fn clock(&mut self){
+        self.cycles += 1;
+        self.bus.clock();
+        if self.bus.ppu.take_nmi() {
+            self.cpu.nmi();
+        }
+        self.cpu.irq(self.bus.mapper.irq() || self.bus.apu.irq());
+        if self.dmc.is_none() {
+            self.dmc = self.bus.apu.dmc_request().map(|addr| DmcFetch{ addr, cycle: 0 });
+        }
+    }
+
+    // One cycle of a DMC fetch, the sample is read on the fourth
+    fn dmc_cycle(&mut self, mut fetch: DmcFetch){
+        fetch.cycle += 1;
+        if fetch.cycle == 4 {
+            let value = self.bus.read(fetch.addr);
+            self.bus.apu.dmc_fill(value);
+            self.dmc = None;
+        } else {
+            self.dmc = Some(fetch);
+        }
+        self.cpu.cycles += 1;
+        self.clock();
+    }
+
+    // One cycle of OAM DMA, the CPU is halted and only counts the cycle
+    fn dma_cycle(&mut self, mut dma: Dma){
+        if dma.cycle >= dma.wait {
+            let n = dma.cycle - dma.wait;
+            if n&0x01 == 0 {
+                let offset = n >> 1;
+                dma.data = self.bus.read((dma.page as u16) << 8 | offset);
+            } else {
+                self.bus.ppu.write_oam(dma.data);
+            }
+        }
+        dma.cycle += 1;
+        self.dma = (dma.cycle < dma.wait + 512).then_some(dma);
+        self.cpu.cycles += 1;
+        self.clock();
+    }
+
+    // One CPU cycle and three PPU dots
+    pub fn tick(&mut self) -> Result<Option<StepInfo>, CpuError>{
+        self.tick_with(|_, _, _| ())
+    }
+
+    // `access` sees the CPU's own reads and writes, DMA cycles go past it
+    fn tick_with<F: FnMut(u16, u8, bool)>(&mut self, access: F) -> Result<Option<StepInfo>, CpuError>{
+        if let Some(fetch) = self.dmc {
+            self.dmc_cycle(fetch);
+            return Ok(None);
+        }
+        if let Some(dma) = self.dma {
+            self.dma_cycle(dma);
+            return Ok(None);
+        }
+        let result = self.cpu.tick(&mut Observed{ bus: &mut self.bus, access });
+        // Rejected opcodes stop before the cycle starts
+        if let Err(CpuError::UnknownOpcode{ .. }) = result {
+            return result;
+        }
+        self.clock();
+        // DMA halts the CPU from the cycle after the write, taking 513 cycles or 514 from an odd one
+        if let Some(page) = self.bus.take_dma() {
+            let wait = 1 + (self.cycles % 2) as u16;
+            self.dma = Some(Dma{ page, wait, cycle: 0, data: 0 });
+        }
+        result
+    }
+
+    // Runs an instruction, the DMA stalls during and after it are counted in its cycles
+    pub fn step(&mut self) -> Result<StepInfo, CpuError>{
+        self.step_with(|_, _, _| ())
+    }
+
+    // Same with the CPU's reads and writes passed to `access`, for the debugger's watchpoints
+    pub(crate) fn step_with<F: FnMut(u16, u8, bool)>(&mut self, mut access: F) -> Result<StepInfo, CpuError>{
+        let start = self.cycles;
+        let mut info = loop {
+            if let Some(info) = self.tick_with(&mut access)? {
+                break info;
+            }
+        };
+        while self.dma_active() {
+            self.tick()?;
+        }
+        info.cycles = (self.cycles - start) as u32;
+        self.cpu.cycles = info.cycles;
+        Ok(info)
+    }
+
+    // Trace line for the instruction about to run
+    pub fn trace_line(&self, format: TraceFormat) -> String{
+        let clock = TraceClock{ scanline: self.bus.ppu.scanline(), dot: self.bus.ppu.dot(), cycles: self.cycles };
+        trace::trace_line(&self.cpu, &self.bus, clock, format)
+    }
+
+    // Runs until the PPU enters vblank with a finished frame
+    pub fn run_frame(&mut self) -> Result<(), CpuError>{
+        while !self.bus.ppu.take_frame() {
+            self.tick()?;
+        }
+        Ok(())
+    }
+}
+
+impl Snapshot for Nes{
+    fn save(&self, w: &mut StateWriter){
+        self.cpu.save(w);
+        self.bus.save(w);
+        w.bool(self.dma.is_some());
+        if let Some(dma) = self.dma {
+            w.u8(dma.page);
+            w.u16(dma.wait);
+            w.u16(dma.cycle);
+            w.u8(dma.data);
+        }
+        w.bool(self.dmc.is_some());
+        if let Some(fetch) = self.dmc {
+            w.u16(fetch.addr);
+            w.u8(fetch.cycle);
+        }
+        w.u64(self.cycles);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        self.cpu.load(r)?;
+        self.bus.load(r)?;
+        self.dma = None;
+        if r.bool()? {
+            let dma = Dma{ page: r.u8()?, wait: r.u16()?, cycle: r.u16()?, data: r.u8()? };
+            if !(1..=2).contains(&dma.wait) || dma.cycle >= dma.wait + 512 {
+                return Err(StateError::Invalid("OAM DMA cycle"));
+            }
+            self.dma = Some(dma);
+        }
+        self.dmc = None;
+        if r.bool()? {
+            let fetch = DmcFetch{ addr: r.u16()?, cycle: r.u8()? };
+            // A fetch only starts for an empty buffer with sample bytes left, and reads on its fourth cycle
+            if fetch.cycle >= 4 || self.bus.apu.dmc_request().is_none() {
+                return Err(StateError::Invalid("DMC fetch"));
+            }
+            self.dmc = Some(fetch);
+        }
+        self.cycles = r.u64()?;
+        Ok(())
+    }
+}
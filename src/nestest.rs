@@ -1,6 +1,5 @@
-use std::{fs::File, io::{self, BufRead, BufReader}};
-use emulator_6502::{load_nes, memory::Memory, processor::Processor, read_rom};
-use crate::ppu::*;
+use std::{error::Error, fs::File, io::{BufRead, BufReader}};
+use emulator_6502::{cartridge::Cartridge, nes::Nes, trace::TraceFormat};
 
 macro_rules! assert_hex_eq8 {
     ($line:expr, $left:expr, $right:expr, $name:expr) => {
@@ -29,13 +28,12 @@ macro_rules! assert_hex_eq16 {
     };
 }
 
-pub fn nestest() -> io::Result<()>{
-    let mut ppu = ClockPPU::new();
-
-    let rom = read_rom("test/nestest.nes");
-    let mut mem = Memory::new();
-    load_nes(&mut mem, &rom);
-    let mut cpu = Processor::nes();
+pub fn nestest() -> Result<(), Box<dyn Error>>{
+    let mut nes = Nes::new(Cartridge::load("test/nestest.nes")?)?;
+    // Automation mode starts at $C000 instead of the reset vector
+    nes.cpu.pc = 0xC000;
+    // The reference run powered the PPU up at the start of vblank
+    nes.bus.ppu.set_position(241, 0);
 
     let file = File::open("test/nestest.log")?;
     let reader = BufReader::new(file);
@@ -47,24 +45,27 @@ pub fn nestest() -> io::Result<()>{
 
         if let Some(expected) = parse_nestest_line(&line) {
 
-            assert_hex_eq16!(line_no, cpu.pc, expected.pc, "PC");
-            assert_hex_eq8!(line_no, cpu.a,  expected.a,  "A");
-            assert_hex_eq8!(line_no, cpu.x,  expected.x,  "X");
-            assert_hex_eq8!(line_no, cpu.y,  expected.y,  "Y");
-            assert_hex_eq8!(line_no, cpu.s,  expected.sp, "SP");
-            assert_hex_eq8!(line_no, cpu.p,  expected.p,  "P");
+            assert_hex_eq16!(line_no, nes.cpu.pc, expected.pc, "PC");
+            assert_hex_eq8!(line_no, nes.cpu.a,  expected.a,  "A");
+            assert_hex_eq8!(line_no, nes.cpu.x,  expected.x,  "X");
+            assert_hex_eq8!(line_no, nes.cpu.y,  expected.y,  "Y");
+            assert_hex_eq8!(line_no, nes.cpu.s,  expected.sp, "SP");
+            assert_hex_eq8!(line_no, nes.cpu.p,  expected.p,  "P");
 
             assert_eq!(
-            ppu.cyc(),
+            nes.bus.ppu.dot() as u32,
             expected.cyc,
             "Line {}: CYC mismatch (ppu={})",
             line_no,
-            ppu.cyc()
+            nes.bus.ppu.dot()
             );
 
-            cpu.step(&mut mem);
-            println!("{cpu}");
-            ppu.step_cpu(cpu.cycles);
+            // Column for column, disassembly, effective addresses and scanline included
+            let trace = nes.trace_line(TraceFormat::Nestest);
+            assert_eq!(trace, line.trim_end(), "Line {}: trace mismatch", line_no);
+
+            nes.step()?;
+            println!("{}", trace);
         }
     }
 
@@ -106,7 +107,7 @@ pub fn parse_nestest_line(line: &str) -> Option<NestestLine> {
         } else if let Some(v) = token.strip_prefix("SP:") {
             sp = u8::from_str_radix(v, 16).ok();
         } else if let Some(idx) = line.find("CYC:") {
-            cyc = line[idx + 4..].trim().split_whitespace().next()?.parse().ok();
+            cyc = line[idx + 4..].split_whitespace().next()?.parse().ok();
         }
     }
 
@@ -1,23 +1,658 @@
-pub const BASE_CYCLES: [u8; 256] = [
-/* 0x00 */ 7, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6,
-/* 0x10 */ 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
-/* 0x20 */ 6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6,
-/* 0x30 */ 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
-/* 0x40 */ 6, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6,
-/* 0x50 */ 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
-/* 0x60 */ 6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6,
-/* 0x70 */ 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
-/* 0x80 */ 2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
-/* 0x90 */ 2, 6, 2, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5,
-/* 0xA0 */ 2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
-/* 0xB0 */ 2, 5, 2, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4,
-/* 0xC0 */ 2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
-/* 0xD0 */ 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
-/* 0xE0 */ 2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
-/* 0xF0 */ 2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+use std::fmt::Display;
+
+use crate::processor::Variant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode{
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+    // 65C02 (zp)
+    ZeroPageIndirect,
+    // 65C02 JMP (abs,x)
+    AbsoluteIndexedIndirect,
+    // 65C02 BBRn/BBSn zp, rel
+    ZeroPageRelative,
+}
+
+impl Mode{
+    // Instruction length including the opcode
+    pub const fn bytes(self) -> u8{
+        match self {
+            Mode::Implied | Mode::Accumulator => 1,
+            Mode::Immediate | Mode::ZeroPage | Mode::ZeroPageX | Mode::ZeroPageY
+            | Mode::IndirectX | Mode::IndirectY | Mode::Relative | Mode::ZeroPageIndirect => 2,
+            Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY | Mode::Indirect
+            | Mode::AbsoluteIndexedIndirect | Mode::ZeroPageRelative => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mnemonic{
+    Adc, And, Asl, Bcc, Bcs, Beq, Bit, Bmi, Bne, Bpl, Brk, Bvc, Bvs, Clc,
+    Cld, Cli, Clv, Cmp, Cpx, Cpy, Dec, Dex, Dey, Eor, Inc, Inx, Iny, Jmp,
+    Jsr, Lda, Ldx, Ldy, Lsr, Nop, Ora, Pha, Php, Pla, Plp, Rol, Ror, Rti,
+    Rts, Sbc, Sec, Sed, Sei, Sta, Stx, Sty, Tax, Tay, Tsx, Txa, Txs, Tya,
+    // NMOS undocumented
+    Alr, Anc, Arr, Dcp, Isc, Jam, Las, Lax, Lxa, Rla, Rra, Sax, Sbx, Sha,
+    Shx, Shy, Slo, Sre, Tas, Xaa,
+    // 65C02
+    Bra, Phx, Phy, Plx, Ply, Stp, Stz, Trb, Tsb, Wai,
+    Rmb(u8), Smb(u8), Bbr(u8), Bbs(u8),
+}
+
+// How an operation touches its operand, which decides the bus traffic of the addressing mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access{
+    Read,
+    Write,
+    Modify,
+    // Stack, flow control and register-only operations
+    Other,
+}
+
+impl Mnemonic{
+    pub const fn access(self) -> Access{
+        use Mnemonic::*;
+        match self {
+            Adc | And | Bit | Cmp | Cpx | Cpy | Eor | Lda | Ldx | Ldy | Nop | Ora | Sbc
+            | Alr | Anc | Arr | Las | Lax | Lxa | Sbx | Xaa => Access::Read,
+            Sta | Stx | Sty | Sax | Sha | Shx | Shy | Tas | Stz => Access::Write,
+            Asl | Dec | Inc | Lsr | Rol | Ror | Dcp | Isc | Rla | Rra | Slo | Sre
+            | Trb | Tsb | Rmb(_) | Smb(_) => Access::Modify,
+            _ => Access::Other,
+        }
+    }
+}
+
+impl Display for Mnemonic{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mnemonic::Rmb(n) => write!(f, "RMB{n}"),
+            Mnemonic::Smb(n) => write!(f, "SMB{n}"),
+            Mnemonic::Bbr(n) => write!(f, "BBR{n}"),
+            Mnemonic::Bbs(n) => write!(f, "BBS{n}"),
+            _ => write!(f, "{}", format!("{self:?}").to_uppercase()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Legality{
+    Legal,
+    // Undocumented but deterministic
+    Illegal,
+    // Undocumented and dependent on the analog behaviour of the chip
+    Unstable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Opcode{
+    pub mnemonic: Mnemonic,
+    pub mode: Mode,
+    pub bytes: u8,
+    pub cycles: u8,
+    // One more cycle when indexing crosses a page
+    pub page_penalty: bool,
+    pub legality: Legality,
+}
+
+const fn entry(mnemonic: Mnemonic, mode: Mode, cycles: u8, page_penalty: bool, legality: Legality) -> Opcode{
+    Opcode{ mnemonic, mode, bytes: mode.bytes(), cycles, page_penalty, legality }
+}
+
+const fn op(mnemonic: Mnemonic, mode: Mode, cycles: u8, page_penalty: bool) -> Opcode{
+    entry(mnemonic, mode, cycles, page_penalty, Legality::Legal)
+}
+
+const fn ill(mnemonic: Mnemonic, mode: Mode, cycles: u8, page_penalty: bool) -> Opcode{
+    entry(mnemonic, mode, cycles, page_penalty, Legality::Illegal)
+}
+
+const fn uns(mnemonic: Mnemonic, mode: Mode, cycles: u8, page_penalty: bool) -> Opcode{
+    entry(mnemonic, mode, cycles, page_penalty, Legality::Unstable)
+}
+
+pub fn opcodes(variant: Variant) -> &'static [Opcode; 256]{
+    match variant {
+        Variant::Nmos | Variant::Nes => &NMOS_OPCODES,
+        Variant::Cmos => &CMOS_OPCODES,
+    }
+}
+
+use Mnemonic::*;
+use Mode::*;
+
+// NMOS 6502 and 2A03, branch penalties are added at runtime
+pub const NMOS_OPCODES: [Opcode; 256] = [
+/* 0x00 */ op(Brk, Implied, 7, false),
+/* 0x01 */ op(Ora, IndirectX, 6, false),
+/* 0x02 */ ill(Jam, Implied, 2, false),
+/* 0x03 */ ill(Slo, IndirectX, 8, false),
+/* 0x04 */ ill(Nop, ZeroPage, 3, false),
+/* 0x05 */ op(Ora, ZeroPage, 3, false),
+/* 0x06 */ op(Asl, ZeroPage, 5, false),
+/* 0x07 */ ill(Slo, ZeroPage, 5, false),
+/* 0x08 */ op(Php, Implied, 3, false),
+/* 0x09 */ op(Ora, Immediate, 2, false),
+/* 0x0A */ op(Asl, Accumulator, 2, false),
+/* 0x0B */ ill(Anc, Immediate, 2, false),
+/* 0x0C */ ill(Nop, Absolute, 4, false),
+/* 0x0D */ op(Ora, Absolute, 4, false),
+/* 0x0E */ op(Asl, Absolute, 6, false),
+/* 0x0F */ ill(Slo, Absolute, 6, false),
+/* 0x10 */ op(Bpl, Relative, 2, false),
+/* 0x11 */ op(Ora, IndirectY, 5, true),
+/* 0x12 */ ill(Jam, Implied, 2, false),
+/* 0x13 */ ill(Slo, IndirectY, 8, false),
+/* 0x14 */ ill(Nop, ZeroPageX, 4, false),
+/* 0x15 */ op(Ora, ZeroPageX, 4, false),
+/* 0x16 */ op(Asl, ZeroPageX, 6, false),
+/* 0x17 */ ill(Slo, ZeroPageX, 6, false),
+/* 0x18 */ op(Clc, Implied, 2, false),
+/* 0x19 */ op(Ora, AbsoluteY, 4, true),
+/* 0x1A */ ill(Nop, Implied, 2, false),
+/* 0x1B */ ill(Slo, AbsoluteY, 7, false),
+/* 0x1C */ ill(Nop, AbsoluteX, 4, true),
+/* 0x1D */ op(Ora, AbsoluteX, 4, true),
+/* 0x1E */ op(Asl, AbsoluteX, 7, false),
+/* 0x1F */ ill(Slo, AbsoluteX, 7, false),
+/* 0x20 */ op(Jsr, Absolute, 6, false),
+/* 0x21 */ op(And, IndirectX, 6, false),
+/* 0x22 */ ill(Jam, Implied, 2, false),
+/* 0x23 */ ill(Rla, IndirectX, 8, false),
+/* 0x24 */ op(Bit, ZeroPage, 3, false),
+/* 0x25 */ op(And, ZeroPage, 3, false),
+/* 0x26 */ op(Rol, ZeroPage, 5, false),
+/* 0x27 */ ill(Rla, ZeroPage, 5, false),
+/* 0x28 */ op(Plp, Implied, 4, false),
+/* 0x29 */ op(And, Immediate, 2, false),
+/* 0x2A */ op(Rol, Accumulator, 2, false),
+/* 0x2B */ ill(Anc, Immediate, 2, false),
+/* 0x2C */ op(Bit, Absolute, 4, false),
+/* 0x2D */ op(And, Absolute, 4, false),
+/* 0x2E */ op(Rol, Absolute, 6, false),
+/* 0x2F */ ill(Rla, Absolute, 6, false),
+/* 0x30 */ op(Bmi, Relative, 2, false),
+/* 0x31 */ op(And, IndirectY, 5, true),
+/* 0x32 */ ill(Jam, Implied, 2, false),
+/* 0x33 */ ill(Rla, IndirectY, 8, false),
+/* 0x34 */ ill(Nop, ZeroPageX, 4, false),
+/* 0x35 */ op(And, ZeroPageX, 4, false),
+/* 0x36 */ op(Rol, ZeroPageX, 6, false),
+/* 0x37 */ ill(Rla, ZeroPageX, 6, false),
+/* 0x38 */ op(Sec, Implied, 2, false),
+/* 0x39 */ op(And, AbsoluteY, 4, true),
+/* 0x3A */ ill(Nop, Implied, 2, false),
+/* 0x3B */ ill(Rla, AbsoluteY, 7, false),
+/* 0x3C */ ill(Nop, AbsoluteX, 4, true),
+/* 0x3D */ op(And, AbsoluteX, 4, true),
+/* 0x3E */ op(Rol, AbsoluteX, 7, false),
+/* 0x3F */ ill(Rla, AbsoluteX, 7, false),
+/* 0x40 */ op(Rti, Implied, 6, false),
+/* 0x41 */ op(Eor, IndirectX, 6, false),
+/* 0x42 */ ill(Jam, Implied, 2, false),
+/* 0x43 */ ill(Sre, IndirectX, 8, false),
+/* 0x44 */ ill(Nop, ZeroPage, 3, false),
+/* 0x45 */ op(Eor, ZeroPage, 3, false),
+/* 0x46 */ op(Lsr, ZeroPage, 5, false),
+/* 0x47 */ ill(Sre, ZeroPage, 5, false),
+/* 0x48 */ op(Pha, Implied, 3, false),
+/* 0x49 */ op(Eor, Immediate, 2, false),
+/* 0x4A */ op(Lsr, Accumulator, 2, false),
+/* 0x4B */ ill(Alr, Immediate, 2, false),
+/* 0x4C */ op(Jmp, Absolute, 3, false),
+/* 0x4D */ op(Eor, Absolute, 4, false),
+/* 0x4E */ op(Lsr, Absolute, 6, false),
+/* 0x4F */ ill(Sre, Absolute, 6, false),
+/* 0x50 */ op(Bvc, Relative, 2, false),
+/* 0x51 */ op(Eor, IndirectY, 5, true),
+/* 0x52 */ ill(Jam, Implied, 2, false),
+/* 0x53 */ ill(Sre, IndirectY, 8, false),
+/* 0x54 */ ill(Nop, ZeroPageX, 4, false),
+/* 0x55 */ op(Eor, ZeroPageX, 4, false),
+/* 0x56 */ op(Lsr, ZeroPageX, 6, false),
+/* 0x57 */ ill(Sre, ZeroPageX, 6, false),
+/* 0x58 */ op(Cli, Implied, 2, false),
+/* 0x59 */ op(Eor, AbsoluteY, 4, true),
+/* 0x5A */ ill(Nop, Implied, 2, false),
+/* 0x5B */ ill(Sre, AbsoluteY, 7, false),
+/* 0x5C */ ill(Nop, AbsoluteX, 4, true),
+/* 0x5D */ op(Eor, AbsoluteX, 4, true),
+/* 0x5E */ op(Lsr, AbsoluteX, 7, false),
+/* 0x5F */ ill(Sre, AbsoluteX, 7, false),
+/* 0x60 */ op(Rts, Implied, 6, false),
+/* 0x61 */ op(Adc, IndirectX, 6, false),
+/* 0x62 */ ill(Jam, Implied, 2, false),
+/* 0x63 */ ill(Rra, IndirectX, 8, false),
+/* 0x64 */ ill(Nop, ZeroPage, 3, false),
+/* 0x65 */ op(Adc, ZeroPage, 3, false),
+/* 0x66 */ op(Ror, ZeroPage, 5, false),
+/* 0x67 */ ill(Rra, ZeroPage, 5, false),
+/* 0x68 */ op(Pla, Implied, 4, false),
+/* 0x69 */ op(Adc, Immediate, 2, false),
+/* 0x6A */ op(Ror, Accumulator, 2, false),
+/* 0x6B */ ill(Arr, Immediate, 2, false),
+/* 0x6C */ op(Jmp, Indirect, 5, false),
+/* 0x6D */ op(Adc, Absolute, 4, false),
+/* 0x6E */ op(Ror, Absolute, 6, false),
+/* 0x6F */ ill(Rra, Absolute, 6, false),
+/* 0x70 */ op(Bvs, Relative, 2, false),
+/* 0x71 */ op(Adc, IndirectY, 5, true),
+/* 0x72 */ ill(Jam, Implied, 2, false),
+/* 0x73 */ ill(Rra, IndirectY, 8, false),
+/* 0x74 */ ill(Nop, ZeroPageX, 4, false),
+/* 0x75 */ op(Adc, ZeroPageX, 4, false),
+/* 0x76 */ op(Ror, ZeroPageX, 6, false),
+/* 0x77 */ ill(Rra, ZeroPageX, 6, false),
+/* 0x78 */ op(Sei, Implied, 2, false),
+/* 0x79 */ op(Adc, AbsoluteY, 4, true),
+/* 0x7A */ ill(Nop, Implied, 2, false),
+/* 0x7B */ ill(Rra, AbsoluteY, 7, false),
+/* 0x7C */ ill(Nop, AbsoluteX, 4, true),
+/* 0x7D */ op(Adc, AbsoluteX, 4, true),
+/* 0x7E */ op(Ror, AbsoluteX, 7, false),
+/* 0x7F */ ill(Rra, AbsoluteX, 7, false),
+/* 0x80 */ ill(Nop, Immediate, 2, false),
+/* 0x81 */ op(Sta, IndirectX, 6, false),
+/* 0x82 */ ill(Nop, Immediate, 2, false),
+/* 0x83 */ ill(Sax, IndirectX, 6, false),
+/* 0x84 */ op(Sty, ZeroPage, 3, false),
+/* 0x85 */ op(Sta, ZeroPage, 3, false),
+/* 0x86 */ op(Stx, ZeroPage, 3, false),
+/* 0x87 */ ill(Sax, ZeroPage, 3, false),
+/* 0x88 */ op(Dey, Implied, 2, false),
+/* 0x89 */ ill(Nop, Immediate, 2, false),
+/* 0x8A */ op(Txa, Implied, 2, false),
+/* 0x8B */ uns(Xaa, Immediate, 2, false),
+/* 0x8C */ op(Sty, Absolute, 4, false),
+/* 0x8D */ op(Sta, Absolute, 4, false),
+/* 0x8E */ op(Stx, Absolute, 4, false),
+/* 0x8F */ ill(Sax, Absolute, 4, false),
+/* 0x90 */ op(Bcc, Relative, 2, false),
+/* 0x91 */ op(Sta, IndirectY, 6, false),
+/* 0x92 */ ill(Jam, Implied, 2, false),
+/* 0x93 */ uns(Sha, IndirectY, 6, false),
+/* 0x94 */ op(Sty, ZeroPageX, 4, false),
+/* 0x95 */ op(Sta, ZeroPageX, 4, false),
+/* 0x96 */ op(Stx, ZeroPageY, 4, false),
+/* 0x97 */ ill(Sax, ZeroPageY, 4, false),
+/* 0x98 */ op(Tya, Implied, 2, false),
+/* 0x99 */ op(Sta, AbsoluteY, 5, false),
+/* 0x9A */ op(Txs, Implied, 2, false),
+/* 0x9B */ uns(Tas, AbsoluteY, 5, false),
+/* 0x9C */ uns(Shy, AbsoluteX, 5, false),
+/* 0x9D */ op(Sta, AbsoluteX, 5, false),
+/* 0x9E */ uns(Shx, AbsoluteY, 5, false),
+/* 0x9F */ uns(Sha, AbsoluteY, 5, false),
+/* 0xA0 */ op(Ldy, Immediate, 2, false),
+/* 0xA1 */ op(Lda, IndirectX, 6, false),
+/* 0xA2 */ op(Ldx, Immediate, 2, false),
+/* 0xA3 */ ill(Lax, IndirectX, 6, false),
+/* 0xA4 */ op(Ldy, ZeroPage, 3, false),
+/* 0xA5 */ op(Lda, ZeroPage, 3, false),
+/* 0xA6 */ op(Ldx, ZeroPage, 3, false),
+/* 0xA7 */ ill(Lax, ZeroPage, 3, false),
+/* 0xA8 */ op(Tay, Implied, 2, false),
+/* 0xA9 */ op(Lda, Immediate, 2, false),
+/* 0xAA */ op(Tax, Implied, 2, false),
+/* 0xAB */ uns(Lxa, Immediate, 2, false),
+/* 0xAC */ op(Ldy, Absolute, 4, false),
+/* 0xAD */ op(Lda, Absolute, 4, false),
+/* 0xAE */ op(Ldx, Absolute, 4, false),
+/* 0xAF */ ill(Lax, Absolute, 4, false),
+/* 0xB0 */ op(Bcs, Relative, 2, false),
+/* 0xB1 */ op(Lda, IndirectY, 5, true),
+/* 0xB2 */ ill(Jam, Implied, 2, false),
+/* 0xB3 */ ill(Lax, IndirectY, 5, true),
+/* 0xB4 */ op(Ldy, ZeroPageX, 4, false),
+/* 0xB5 */ op(Lda, ZeroPageX, 4, false),
+/* 0xB6 */ op(Ldx, ZeroPageY, 4, false),
+/* 0xB7 */ ill(Lax, ZeroPageY, 4, false),
+/* 0xB8 */ op(Clv, Implied, 2, false),
+/* 0xB9 */ op(Lda, AbsoluteY, 4, true),
+/* 0xBA */ op(Tsx, Implied, 2, false),
+/* 0xBB */ ill(Las, AbsoluteY, 4, true),
+/* 0xBC */ op(Ldy, AbsoluteX, 4, true),
+/* 0xBD */ op(Lda, AbsoluteX, 4, true),
+/* 0xBE */ op(Ldx, AbsoluteY, 4, true),
+/* 0xBF */ ill(Lax, AbsoluteY, 4, true),
+/* 0xC0 */ op(Cpy, Immediate, 2, false),
+/* 0xC1 */ op(Cmp, IndirectX, 6, false),
+/* 0xC2 */ ill(Nop, Immediate, 2, false),
+/* 0xC3 */ ill(Dcp, IndirectX, 8, false),
+/* 0xC4 */ op(Cpy, ZeroPage, 3, false),
+/* 0xC5 */ op(Cmp, ZeroPage, 3, false),
+/* 0xC6 */ op(Dec, ZeroPage, 5, false),
+/* 0xC7 */ ill(Dcp, ZeroPage, 5, false),
+/* 0xC8 */ op(Iny, Implied, 2, false),
+/* 0xC9 */ op(Cmp, Immediate, 2, false),
+/* 0xCA */ op(Dex, Implied, 2, false),
+/* 0xCB */ ill(Sbx, Immediate, 2, false),
+/* 0xCC */ op(Cpy, Absolute, 4, false),
+/* 0xCD */ op(Cmp, Absolute, 4, false),
+/* 0xCE */ op(Dec, Absolute, 6, false),
+/* 0xCF */ ill(Dcp, Absolute, 6, false),
+/* 0xD0 */ op(Bne, Relative, 2, false),
+/* 0xD1 */ op(Cmp, IndirectY, 5, true),
+/* 0xD2 */ ill(Jam, Implied, 2, false),
+/* 0xD3 */ ill(Dcp, IndirectY, 8, false),
+/* 0xD4 */ ill(Nop, ZeroPageX, 4, false),
+/* 0xD5 */ op(Cmp, ZeroPageX, 4, false),
+/* 0xD6 */ op(Dec, ZeroPageX, 6, false),
+/* 0xD7 */ ill(Dcp, ZeroPageX, 6, false),
+/* 0xD8 */ op(Cld, Implied, 2, false),
+/* 0xD9 */ op(Cmp, AbsoluteY, 4, true),
+/* 0xDA */ ill(Nop, Implied, 2, false),
+/* 0xDB */ ill(Dcp, AbsoluteY, 7, false),
+/* 0xDC */ ill(Nop, AbsoluteX, 4, true),
+/* 0xDD */ op(Cmp, AbsoluteX, 4, true),
+/* 0xDE */ op(Dec, AbsoluteX, 7, false),
+/* 0xDF */ ill(Dcp, AbsoluteX, 7, false),
+/* 0xE0 */ op(Cpx, Immediate, 2, false),
+/* 0xE1 */ op(Sbc, IndirectX, 6, false),
+/* 0xE2 */ ill(Nop, Immediate, 2, false),
+/* 0xE3 */ ill(Isc, IndirectX, 8, false),
+/* 0xE4 */ op(Cpx, ZeroPage, 3, false),
+/* 0xE5 */ op(Sbc, ZeroPage, 3, false),
+/* 0xE6 */ op(Inc, ZeroPage, 5, false),
+/* 0xE7 */ ill(Isc, ZeroPage, 5, false),
+/* 0xE8 */ op(Inx, Implied, 2, false),
+/* 0xE9 */ op(Sbc, Immediate, 2, false),
+/* 0xEA */ op(Nop, Implied, 2, false),
+/* 0xEB */ ill(Sbc, Immediate, 2, false),
+/* 0xEC */ op(Cpx, Absolute, 4, false),
+/* 0xED */ op(Sbc, Absolute, 4, false),
+/* 0xEE */ op(Inc, Absolute, 6, false),
+/* 0xEF */ ill(Isc, Absolute, 6, false),
+/* 0xF0 */ op(Beq, Relative, 2, false),
+/* 0xF1 */ op(Sbc, IndirectY, 5, true),
+/* 0xF2 */ ill(Jam, Implied, 2, false),
+/* 0xF3 */ ill(Isc, IndirectY, 8, false),
+/* 0xF4 */ ill(Nop, ZeroPageX, 4, false),
+/* 0xF5 */ op(Sbc, ZeroPageX, 4, false),
+/* 0xF6 */ op(Inc, ZeroPageX, 6, false),
+/* 0xF7 */ ill(Isc, ZeroPageX, 6, false),
+/* 0xF8 */ op(Sed, Implied, 2, false),
+/* 0xF9 */ op(Sbc, AbsoluteY, 4, true),
+/* 0xFA */ ill(Nop, Implied, 2, false),
+/* 0xFB */ ill(Isc, AbsoluteY, 7, false),
+/* 0xFC */ ill(Nop, AbsoluteX, 4, true),
+/* 0xFD */ op(Sbc, AbsoluteX, 4, true),
+/* 0xFE */ op(Inc, AbsoluteX, 7, false),
+/* 0xFF */ ill(Isc, AbsoluteX, 7, false),
 ];
 
-pub const TEST:u8 = BASE_CYCLES[0xD3];
+// WDC 65C02, branch and decimal ADC/SBC penalties are added at runtime
+pub const CMOS_OPCODES: [Opcode; 256] = [
+/* 0x00 */ op(Brk, Implied, 7, false),
+/* 0x01 */ op(Ora, IndirectX, 6, false),
+/* 0x02 */ ill(Nop, Immediate, 2, false),
+/* 0x03 */ ill(Nop, Implied, 1, false),
+/* 0x04 */ op(Tsb, ZeroPage, 5, false),
+/* 0x05 */ op(Ora, ZeroPage, 3, false),
+/* 0x06 */ op(Asl, ZeroPage, 5, false),
+/* 0x07 */ op(Rmb(0), ZeroPage, 5, false),
+/* 0x08 */ op(Php, Implied, 3, false),
+/* 0x09 */ op(Ora, Immediate, 2, false),
+/* 0x0A */ op(Asl, Accumulator, 2, false),
+/* 0x0B */ ill(Nop, Implied, 1, false),
+/* 0x0C */ op(Tsb, Absolute, 6, false),
+/* 0x0D */ op(Ora, Absolute, 4, false),
+/* 0x0E */ op(Asl, Absolute, 6, false),
+/* 0x0F */ op(Bbr(0), ZeroPageRelative, 5, false),
+/* 0x10 */ op(Bpl, Relative, 2, false),
+/* 0x11 */ op(Ora, IndirectY, 5, true),
+/* 0x12 */ op(Ora, ZeroPageIndirect, 5, false),
+/* 0x13 */ ill(Nop, Implied, 1, false),
+/* 0x14 */ op(Trb, ZeroPage, 5, false),
+/* 0x15 */ op(Ora, ZeroPageX, 4, false),
+/* 0x16 */ op(Asl, ZeroPageX, 6, false),
+/* 0x17 */ op(Rmb(1), ZeroPage, 5, false),
+/* 0x18 */ op(Clc, Implied, 2, false),
+/* 0x19 */ op(Ora, AbsoluteY, 4, true),
+/* 0x1A */ op(Inc, Accumulator, 2, false),
+/* 0x1B */ ill(Nop, Implied, 1, false),
+/* 0x1C */ op(Trb, Absolute, 6, false),
+/* 0x1D */ op(Ora, AbsoluteX, 4, true),
+/* 0x1E */ op(Asl, AbsoluteX, 6, true),
+/* 0x1F */ op(Bbr(1), ZeroPageRelative, 5, false),
+/* 0x20 */ op(Jsr, Absolute, 6, false),
+/* 0x21 */ op(And, IndirectX, 6, false),
+/* 0x22 */ ill(Nop, Immediate, 2, false),
+/* 0x23 */ ill(Nop, Implied, 1, false),
+/* 0x24 */ op(Bit, ZeroPage, 3, false),
+/* 0x25 */ op(And, ZeroPage, 3, false),
+/* 0x26 */ op(Rol, ZeroPage, 5, false),
+/* 0x27 */ op(Rmb(2), ZeroPage, 5, false),
+/* 0x28 */ op(Plp, Implied, 4, false),
+/* 0x29 */ op(And, Immediate, 2, false),
+/* 0x2A */ op(Rol, Accumulator, 2, false),
+/* 0x2B */ ill(Nop, Implied, 1, false),
+/* 0x2C */ op(Bit, Absolute, 4, false),
+/* 0x2D */ op(And, Absolute, 4, false),
+/* 0x2E */ op(Rol, Absolute, 6, false),
+/* 0x2F */ op(Bbr(2), ZeroPageRelative, 5, false),
+/* 0x30 */ op(Bmi, Relative, 2, false),
+/* 0x31 */ op(And, IndirectY, 5, true),
+/* 0x32 */ op(And, ZeroPageIndirect, 5, false),
+/* 0x33 */ ill(Nop, Implied, 1, false),
+/* 0x34 */ op(Bit, ZeroPageX, 4, false),
+/* 0x35 */ op(And, ZeroPageX, 4, false),
+/* 0x36 */ op(Rol, ZeroPageX, 6, false),
+/* 0x37 */ op(Rmb(3), ZeroPage, 5, false),
+/* 0x38 */ op(Sec, Implied, 2, false),
+/* 0x39 */ op(And, AbsoluteY, 4, true),
+/* 0x3A */ op(Dec, Accumulator, 2, false),
+/* 0x3B */ ill(Nop, Implied, 1, false),
+/* 0x3C */ op(Bit, AbsoluteX, 4, true),
+/* 0x3D */ op(And, AbsoluteX, 4, true),
+/* 0x3E */ op(Rol, AbsoluteX, 6, true),
+/* 0x3F */ op(Bbr(3), ZeroPageRelative, 5, false),
+/* 0x40 */ op(Rti, Implied, 6, false),
+/* 0x41 */ op(Eor, IndirectX, 6, false),
+/* 0x42 */ ill(Nop, Immediate, 2, false),
+/* 0x43 */ ill(Nop, Implied, 1, false),
+/* 0x44 */ ill(Nop, ZeroPage, 3, false),
+/* 0x45 */ op(Eor, ZeroPage, 3, false),
+/* 0x46 */ op(Lsr, ZeroPage, 5, false),
+/* 0x47 */ op(Rmb(4), ZeroPage, 5, false),
+/* 0x48 */ op(Pha, Implied, 3, false),
+/* 0x49 */ op(Eor, Immediate, 2, false),
+/* 0x4A */ op(Lsr, Accumulator, 2, false),
+/* 0x4B */ ill(Nop, Implied, 1, false),
+/* 0x4C */ op(Jmp, Absolute, 3, false),
+/* 0x4D */ op(Eor, Absolute, 4, false),
+/* 0x4E */ op(Lsr, Absolute, 6, false),
+/* 0x4F */ op(Bbr(4), ZeroPageRelative, 5, false),
+/* 0x50 */ op(Bvc, Relative, 2, false),
+/* 0x51 */ op(Eor, IndirectY, 5, true),
+/* 0x52 */ op(Eor, ZeroPageIndirect, 5, false),
+/* 0x53 */ ill(Nop, Implied, 1, false),
+/* 0x54 */ ill(Nop, ZeroPageX, 4, false),
+/* 0x55 */ op(Eor, ZeroPageX, 4, false),
+/* 0x56 */ op(Lsr, ZeroPageX, 6, false),
+/* 0x57 */ op(Rmb(5), ZeroPage, 5, false),
+/* 0x58 */ op(Cli, Implied, 2, false),
+/* 0x59 */ op(Eor, AbsoluteY, 4, true),
+/* 0x5A */ op(Phy, Implied, 3, false),
+/* 0x5B */ ill(Nop, Implied, 1, false),
+/* 0x5C */ ill(Nop, Absolute, 8, false),
+/* 0x5D */ op(Eor, AbsoluteX, 4, true),
+/* 0x5E */ op(Lsr, AbsoluteX, 6, true),
+/* 0x5F */ op(Bbr(5), ZeroPageRelative, 5, false),
+/* 0x60 */ op(Rts, Implied, 6, false),
+/* 0x61 */ op(Adc, IndirectX, 6, false),
+/* 0x62 */ ill(Nop, Immediate, 2, false),
+/* 0x63 */ ill(Nop, Implied, 1, false),
+/* 0x64 */ op(Stz, ZeroPage, 3, false),
+/* 0x65 */ op(Adc, ZeroPage, 3, false),
+/* 0x66 */ op(Ror, ZeroPage, 5, false),
+/* 0x67 */ op(Rmb(6), ZeroPage, 5, false),
+/* 0x68 */ op(Pla, Implied, 4, false),
+/* 0x69 */ op(Adc, Immediate, 2, false),
+/* 0x6A */ op(Ror, Accumulator, 2, false),
+/* 0x6B */ ill(Nop, Implied, 1, false),
+/* 0x6C */ op(Jmp, Indirect, 6, false),
+/* 0x6D */ op(Adc, Absolute, 4, false),
+/* 0x6E */ op(Ror, Absolute, 6, false),
+/* 0x6F */ op(Bbr(6), ZeroPageRelative, 5, false),
+/* 0x70 */ op(Bvs, Relative, 2, false),
+/* 0x71 */ op(Adc, IndirectY, 5, true),
+/* 0x72 */ op(Adc, ZeroPageIndirect, 5, false),
+/* 0x73 */ ill(Nop, Implied, 1, false),
+/* 0x74 */ op(Stz, ZeroPageX, 4, false),
+/* 0x75 */ op(Adc, ZeroPageX, 4, false),
+/* 0x76 */ op(Ror, ZeroPageX, 6, false),
+/* 0x77 */ op(Rmb(7), ZeroPage, 5, false),
+/* 0x78 */ op(Sei, Implied, 2, false),
+/* 0x79 */ op(Adc, AbsoluteY, 4, true),
+/* 0x7A */ op(Ply, Implied, 4, false),
+/* 0x7B */ ill(Nop, Implied, 1, false),
+/* 0x7C */ op(Jmp, AbsoluteIndexedIndirect, 6, false),
+/* 0x7D */ op(Adc, AbsoluteX, 4, true),
+/* 0x7E */ op(Ror, AbsoluteX, 6, true),
+/* 0x7F */ op(Bbr(7), ZeroPageRelative, 5, false),
+/* 0x80 */ op(Bra, Relative, 3, false),
+/* 0x81 */ op(Sta, IndirectX, 6, false),
+/* 0x82 */ ill(Nop, Immediate, 2, false),
+/* 0x83 */ ill(Nop, Implied, 1, false),
+/* 0x84 */ op(Sty, ZeroPage, 3, false),
+/* 0x85 */ op(Sta, ZeroPage, 3, false),
+/* 0x86 */ op(Stx, ZeroPage, 3, false),
+/* 0x87 */ op(Smb(0), ZeroPage, 5, false),
+/* 0x88 */ op(Dey, Implied, 2, false),
+/* 0x89 */ op(Bit, Immediate, 2, false),
+/* 0x8A */ op(Txa, Implied, 2, false),
+/* 0x8B */ ill(Nop, Implied, 1, false),
+/* 0x8C */ op(Sty, Absolute, 4, false),
+/* 0x8D */ op(Sta, Absolute, 4, false),
+/* 0x8E */ op(Stx, Absolute, 4, false),
+/* 0x8F */ op(Bbs(0), ZeroPageRelative, 5, false),
+/* 0x90 */ op(Bcc, Relative, 2, false),
+/* 0x91 */ op(Sta, IndirectY, 6, false),
+/* 0x92 */ op(Sta, ZeroPageIndirect, 5, false),
+/* 0x93 */ ill(Nop, Implied, 1, false),
+/* 0x94 */ op(Sty, ZeroPageX, 4, false),
+/* 0x95 */ op(Sta, ZeroPageX, 4, false),
+/* 0x96 */ op(Stx, ZeroPageY, 4, false),
+/* 0x97 */ op(Smb(1), ZeroPage, 5, false),
+/* 0x98 */ op(Tya, Implied, 2, false),
+/* 0x99 */ op(Sta, AbsoluteY, 5, false),
+/* 0x9A */ op(Txs, Implied, 2, false),
+/* 0x9B */ ill(Nop, Implied, 1, false),
+/* 0x9C */ op(Stz, Absolute, 4, false),
+/* 0x9D */ op(Sta, AbsoluteX, 5, false),
+/* 0x9E */ op(Stz, AbsoluteX, 5, false),
+/* 0x9F */ op(Bbs(1), ZeroPageRelative, 5, false),
+/* 0xA0 */ op(Ldy, Immediate, 2, false),
+/* 0xA1 */ op(Lda, IndirectX, 6, false),
+/* 0xA2 */ op(Ldx, Immediate, 2, false),
+/* 0xA3 */ ill(Nop, Implied, 1, false),
+/* 0xA4 */ op(Ldy, ZeroPage, 3, false),
+/* 0xA5 */ op(Lda, ZeroPage, 3, false),
+/* 0xA6 */ op(Ldx, ZeroPage, 3, false),
+/* 0xA7 */ op(Smb(2), ZeroPage, 5, false),
+/* 0xA8 */ op(Tay, Implied, 2, false),
+/* 0xA9 */ op(Lda, Immediate, 2, false),
+/* 0xAA */ op(Tax, Implied, 2, false),
+/* 0xAB */ ill(Nop, Implied, 1, false),
+/* 0xAC */ op(Ldy, Absolute, 4, false),
+/* 0xAD */ op(Lda, Absolute, 4, false),
+/* 0xAE */ op(Ldx, Absolute, 4, false),
+/* 0xAF */ op(Bbs(2), ZeroPageRelative, 5, false),
+/* 0xB0 */ op(Bcs, Relative, 2, false),
+/* 0xB1 */ op(Lda, IndirectY, 5, true),
+/* 0xB2 */ op(Lda, ZeroPageIndirect, 5, false),
+/* 0xB3 */ ill(Nop, Implied, 1, false),
+/* 0xB4 */ op(Ldy, ZeroPageX, 4, false),
+/* 0xB5 */ op(Lda, ZeroPageX, 4, false),
+/* 0xB6 */ op(Ldx, ZeroPageY, 4, false),
+/* 0xB7 */ op(Smb(3), ZeroPage, 5, false),
+/* 0xB8 */ op(Clv, Implied, 2, false),
+/* 0xB9 */ op(Lda, AbsoluteY, 4, true),
+/* 0xBA */ op(Tsx, Implied, 2, false),
+/* 0xBB */ ill(Nop, Implied, 1, false),
+/* 0xBC */ op(Ldy, AbsoluteX, 4, true),
+/* 0xBD */ op(Lda, AbsoluteX, 4, true),
+/* 0xBE */ op(Ldx, AbsoluteY, 4, true),
+/* 0xBF */ op(Bbs(3), ZeroPageRelative, 5, false),
+/* 0xC0 */ op(Cpy, Immediate, 2, false),
+/* 0xC1 */ op(Cmp, IndirectX, 6, false),
+/* 0xC2 */ ill(Nop, Immediate, 2, false),
+/* 0xC3 */ ill(Nop, Implied, 1, false),
+/* 0xC4 */ op(Cpy, ZeroPage, 3, false),
+/* 0xC5 */ op(Cmp, ZeroPage, 3, false),
+/* 0xC6 */ op(Dec, ZeroPage, 5, false),
+/* 0xC7 */ op(Smb(4), ZeroPage, 5, false),
+/* 0xC8 */ op(Iny, Implied, 2, false),
+/* 0xC9 */ op(Cmp, Immediate, 2, false),
+/* 0xCA */ op(Dex, Implied, 2, false),
+/* 0xCB */ op(Wai, Implied, 3, false),
+/* 0xCC */ op(Cpy, Absolute, 4, false),
+/* 0xCD */ op(Cmp, Absolute, 4, false),
+/* 0xCE */ op(Dec, Absolute, 6, false),
+/* 0xCF */ op(Bbs(4), ZeroPageRelative, 5, false),
+/* 0xD0 */ op(Bne, Relative, 2, false),
+/* 0xD1 */ op(Cmp, IndirectY, 5, true),
+/* 0xD2 */ op(Cmp, ZeroPageIndirect, 5, false),
+/* 0xD3 */ ill(Nop, Implied, 1, false),
+/* 0xD4 */ ill(Nop, ZeroPageX, 4, false),
+/* 0xD5 */ op(Cmp, ZeroPageX, 4, false),
+/* 0xD6 */ op(Dec, ZeroPageX, 6, false),
+/* 0xD7 */ op(Smb(5), ZeroPage, 5, false),
+/* 0xD8 */ op(Cld, Implied, 2, false),
+/* 0xD9 */ op(Cmp, AbsoluteY, 4, true),
+/* 0xDA */ op(Phx, Implied, 3, false),
+/* 0xDB */ op(Stp, Implied, 3, false),
+/* 0xDC */ ill(Nop, Absolute, 4, false),
+/* 0xDD */ op(Cmp, AbsoluteX, 4, true),
+/* 0xDE */ op(Dec, AbsoluteX, 7, false),
+/* 0xDF */ op(Bbs(5), ZeroPageRelative, 5, false),
+/* 0xE0 */ op(Cpx, Immediate, 2, false),
+/* 0xE1 */ op(Sbc, IndirectX, 6, false),
+/* 0xE2 */ ill(Nop, Immediate, 2, false),
+/* 0xE3 */ ill(Nop, Implied, 1, false),
+/* 0xE4 */ op(Cpx, ZeroPage, 3, false),
+/* 0xE5 */ op(Sbc, ZeroPage, 3, false),
+/* 0xE6 */ op(Inc, ZeroPage, 5, false),
+/* 0xE7 */ op(Smb(6), ZeroPage, 5, false),
+/* 0xE8 */ op(Inx, Implied, 2, false),
+/* 0xE9 */ op(Sbc, Immediate, 2, false),
+/* 0xEA */ op(Nop, Implied, 2, false),
+/* 0xEB */ ill(Nop, Implied, 1, false),
+/* 0xEC */ op(Cpx, Absolute, 4, false),
+/* 0xED */ op(Sbc, Absolute, 4, false),
+/* 0xEE */ op(Inc, Absolute, 6, false),
+/* 0xEF */ op(Bbs(6), ZeroPageRelative, 5, false),
+/* 0xF0 */ op(Beq, Relative, 2, false),
+/* 0xF1 */ op(Sbc, IndirectY, 5, true),
+/* 0xF2 */ op(Sbc, ZeroPageIndirect, 5, false),
+/* 0xF3 */ ill(Nop, Implied, 1, false),
+/* 0xF4 */ ill(Nop, ZeroPageX, 4, false),
+/* 0xF5 */ op(Sbc, ZeroPageX, 4, false),
+/* 0xF6 */ op(Inc, ZeroPageX, 6, false),
+/* 0xF7 */ op(Smb(7), ZeroPage, 5, false),
+/* 0xF8 */ op(Sed, Implied, 2, false),
+/* 0xF9 */ op(Sbc, AbsoluteY, 4, true),
+/* 0xFA */ op(Plx, Implied, 4, false),
+/* 0xFB */ ill(Nop, Implied, 1, false),
+/* 0xFC */ ill(Nop, Absolute, 4, false),
+/* 0xFD */ op(Sbc, AbsoluteX, 4, true),
+/* 0xFE */ op(Inc, AbsoluteX, 7, false),
+/* 0xFF */ op(Bbs(7), ZeroPageRelative, 5, false),
+];
 
 pub const LDA_IMM: u8 = 0xA9;
 pub const LDA_ZP: u8 = 0xA5;
@@ -260,3 +895,48 @@ pub const RRA_ABSX:  u8 = 0x7F;
 pub const RRA_ABSY:  u8 = 0x7B;
 pub const RRA_INDX:  u8 = 0x63;
 pub const RRA_INDY:  u8 = 0x73;
+
+// 65C02 opcodes
+pub const BRA: u8 = 0x80;
+
+pub const PHX: u8 = 0xDA;
+pub const PHY: u8 = 0x5A;
+pub const PLX: u8 = 0xFA;
+pub const PLY: u8 = 0x7A;
+
+pub const STZ_ZP:   u8 = 0x64;
+pub const STZ_ZPX:  u8 = 0x74;
+pub const STZ_ABS:  u8 = 0x9C;
+pub const STZ_ABSX: u8 = 0x9E;
+
+pub const TRB_ZP:  u8 = 0x14;
+pub const TRB_ABS: u8 = 0x1C;
+pub const TSB_ZP:  u8 = 0x04;
+pub const TSB_ABS: u8 = 0x0C;
+
+pub const ORA_ZPI: u8 = 0x12;
+pub const AND_ZPI: u8 = 0x32;
+pub const EOR_ZPI: u8 = 0x52;
+pub const ADC_ZPI: u8 = 0x72;
+pub const STA_ZPI: u8 = 0x92;
+pub const LDA_ZPI: u8 = 0xB2;
+pub const CMP_ZPI: u8 = 0xD2;
+pub const SBC_ZPI: u8 = 0xF2;
+
+pub const BIT_IMM:  u8 = 0x89;
+pub const BIT_ZPX:  u8 = 0x34;
+pub const BIT_ABSX: u8 = 0x3C;
+
+pub const INC_ACC: u8 = 0x1A;
+pub const DEC_ACC: u8 = 0x3A;
+
+pub const JMP_ABSX_IND: u8 = 0x7C;
+
+pub const WAI: u8 = 0xCB;
+pub const STP: u8 = 0xDB;
+
+// RMBn/SMBn and BBRn/BBSn, n in bits 4-6 of the opcode
+pub const RMB0: u8 = 0x07;
+pub const SMB0: u8 = 0x87;
+pub const BBR0: u8 = 0x0F;
+pub const BBS0: u8 = 0x8F;
@@ -0,0 +1,75 @@
+use std::io;
+use std::path::Path;
+
+use crate::crc::crc32;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+// Largest stored deflate block
+const BLOCK: usize = 0xFFFF;
+
+fn adler32(data: &[u8]) -> u32{
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+// zlib stream of stored blocks, no compression needed for screenshots
+fn zlib(data: &[u8]) -> Vec<u8>{
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend([0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        out.push(blocks.peek().is_none() as u8);
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(block);
+    }
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]){
+    out.extend((data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend(kind);
+    out.extend(data);
+    let crc = crc32(&out[start..]);
+    out.extend(crc.to_be_bytes());
+}
+
+// 8-bit RGB image, rgb holds width * height * 3 bytes
+pub fn encode(width: u32, height: u32, rgb: &[u8]) -> io::Result<Vec<u8>>{
+    if rgb.len() as u64 != width as u64 * height as u64 * 3 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "RGB data does not match the image size"));
+    }
+    let mut header = Vec::with_capacity(13);
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    // Bit depth 8, color type 2 (RGB), default compression, filter and no interlace
+    header.extend([8, 2, 0, 0, 0]);
+
+    // Every scanline starts with filter type 0
+    let mut raw = Vec::with_capacity(rgb.len() + height as usize);
+    if width > 0 {
+        for row in rgb.chunks(width as usize * 3) {
+            raw.push(0);
+            raw.extend(row);
+        }
+    }
+
+    let mut out = SIGNATURE.to_vec();
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"IDAT", &zlib(&raw));
+    chunk(&mut out, b"IEND", &[]);
+    Ok(out)
+}
+
+pub fn write<P: AsRef<Path>>(path: P, width: u32, height: u32, rgb: &[u8]) -> io::Result<()>{
+    std::fs::write(path, encode(width, height, rgb)?)
+}
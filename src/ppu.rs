@@ -1,17 +1,613 @@
-pub struct ClockPPU {
-    cycles: u64, // total PPU cycles
+use crate::cartridge::Mirroring;
+use crate::mapper::Mapper;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+// PPUCTRL
+const INCREMENT: u8 = 0x04;
+const SPRITE_TABLE: u8 = 0x08;
+const BACKGROUND_TABLE: u8 = 0x10;
+const SPRITE_SIZE: u8 = 0x20;
+const NMI_ENABLE: u8 = 0x80;
+
+// PPUMASK
+const GREYSCALE: u8 = 0x01;
+const BACKGROUND_LEFT: u8 = 0x02;
+const SPRITES_LEFT: u8 = 0x04;
+const SHOW_BACKGROUND: u8 = 0x08;
+const SHOW_SPRITES: u8 = 0x10;
+
+// PPUSTATUS
+const OVERFLOW: u8 = 0x20;
+const SPRITE_ZERO: u8 = 0x40;
+const VBLANK: u8 = 0x80;
+
+const PRE_RENDER: u16 = 261;
+const VBLANK_START: u16 = 241;
+
+// RGB of the 64 colours of the 2C02
+pub const PALETTE: [[u8; 3]; 64] = [
+    [0x66, 0x66, 0x66], [0x00, 0x2A, 0x88], [0x14, 0x12, 0xA7], [0x3B, 0x00, 0xA4],
+    [0x5C, 0x00, 0x7E], [0x6E, 0x00, 0x40], [0x6C, 0x06, 0x00], [0x56, 0x1D, 0x00],
+    [0x33, 0x35, 0x00], [0x0B, 0x48, 0x00], [0x00, 0x52, 0x00], [0x00, 0x4F, 0x08],
+    [0x00, 0x40, 0x4D], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00],
+    [0xAD, 0xAD, 0xAD], [0x15, 0x5F, 0xD9], [0x42, 0x40, 0xFF], [0x75, 0x27, 0xFE],
+    [0xA0, 0x1A, 0xCC], [0xB7, 0x1E, 0x7B], [0xB5, 0x31, 0x20], [0x99, 0x4E, 0x00],
+    [0x6B, 0x6D, 0x00], [0x38, 0x87, 0x00], [0x0C, 0x93, 0x00], [0x00, 0x8F, 0x32],
+    [0x00, 0x7C, 0x8D], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00],
+    [0xFF, 0xFE, 0xFF], [0x64, 0xB0, 0xFF], [0x92, 0x90, 0xFF], [0xC6, 0x76, 0xFF],
+    [0xF3, 0x6A, 0xFF], [0xFE, 0x6E, 0xCC], [0xFE, 0x81, 0x70], [0xEA, 0x9E, 0x22],
+    [0xBC, 0xBE, 0x00], [0x88, 0xD8, 0x00], [0x5C, 0xE4, 0x30], [0x45, 0xE0, 0x82],
+    [0x48, 0xCD, 0xDE], [0x4F, 0x4F, 0x4F], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00],
+    [0xFF, 0xFE, 0xFF], [0xC0, 0xDF, 0xFF], [0xD3, 0xD2, 0xFF], [0xE8, 0xC8, 0xFF],
+    [0xFB, 0xC2, 0xFF], [0xFE, 0xC4, 0xEA], [0xFE, 0xCC, 0xC5], [0xF7, 0xD8, 0xA5],
+    [0xE4, 0xE5, 0x94], [0xCF, 0xEF, 0x96], [0xBD, 0xF4, 0xAB], [0xB3, 0xF3, 0xCC],
+    [0xB5, 0xEB, 0xF2], [0xB8, 0xB8, 0xB8], [0x00, 0x00, 0x00], [0x00, 0x00, 0x00],
+];
+
+// Sprite fetched for the scanline being drawn, pattern bits already flipped horizontally
+#[derive(Debug, Clone, Copy, Default)]
+struct Sprite{
+    x: u8,
+    attributes: u8,
+    low: u8,
+    high: u8,
+    zero: bool,
+}
+
+pub struct Ppu{
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_addr: u8,
+    // Loopy registers: current and temporary VRAM address, fine X scroll and the write toggle
+    v: u16,
+    t: u16,
+    x: u8,
+    w: bool,
+    // PPUDATA read buffer
+    buffer: u8,
+    // Last value on the register bus, write-only registers read it back
+    latch: u8,
+    // 2KB of console VRAM plus the 2KB four-screen boards add
+    vram: [u8; 0x1000],
+    palette: [u8; 32],
+    oam: [u8; 256],
+    scanline: u16,
+    dot: u16,
+    frame: u64,
+    odd: bool,
+    // Background pipeline
+    tile: u8,
+    attribute: u8,
+    tile_low: u8,
+    tile_high: u8,
+    pattern_low: u16,
+    pattern_high: u16,
+    attribute_low: u16,
+    attribute_high: u16,
+    sprites: [Sprite; 8],
+    sprite_count: usize,
+    nmi_edge: bool,
+    frame_ready: bool,
+    // Palette index of every pixel of the last frame
+    framebuffer: Vec<u8>,
+}
+
+impl Default for Ppu{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl ClockPPU {
-    pub fn new() -> Self {
-        Self { cycles: 0 }
+impl Ppu{
+
+    pub fn new() -> Ppu{
+        Ppu{
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            buffer: 0,
+            latch: 0,
+            vram: [0; 0x1000],
+            palette: [0; 32],
+            oam: [0; 256],
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            odd: false,
+            tile: 0,
+            attribute: 0,
+            tile_low: 0,
+            tile_high: 0,
+            pattern_low: 0,
+            pattern_high: 0,
+            attribute_low: 0,
+            attribute_high: 0,
+            sprites: [Sprite::default(); 8],
+            sprite_count: 0,
+            nmi_edge: false,
+            frame_ready: false,
+            framebuffer: vec![0; WIDTH * HEIGHT],
+        }
+    }
+
+    pub fn scanline(&self) -> u16{
+        self.scanline
     }
 
-    pub fn step_cpu(&mut self, cpu_cycles: u32) {
-        self.cycles += cpu_cycles as u64 * 3;
+    pub fn dot(&self) -> u16{
+        self.dot
+    }
+
+    // Moves the beam without running the dots in between, for matching logs of another power-up position
+    pub fn set_position(&mut self, scanline: u16, dot: u16){
+        self.scanline = scanline.min(PRE_RENDER);
+        self.dot = dot.min(340);
+    }
+
+    pub fn frame_count(&self) -> u64{
+        self.frame
+    }
+
+    pub fn oam(&self) -> &[u8; 256]{
+        &self.oam
+    }
+
+    pub fn framebuffer(&self) -> &[u8]{
+        &self.framebuffer
+    }
+
+    // Framebuffer as packed 8-bit RGB
+    pub fn frame_rgb(&self) -> Vec<u8>{
+        self.framebuffer.iter().flat_map(|&index| PALETTE[(index&0x3F) as usize]).collect()
+    }
+
+    // True once after the NMI output went high
+    pub fn take_nmi(&mut self) -> bool{
+        std::mem::take(&mut self.nmi_edge)
+    }
+
+    // True once after a frame was completed at the start of vblank
+    pub fn take_frame(&mut self) -> bool{
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    fn rendering(&self) -> bool{
+        self.mask&(SHOW_BACKGROUND|SHOW_SPRITES) != 0
+    }
+
+    // Memory
+
+    fn nametable(&self, mirroring: Mirroring, addr: u16) -> usize{
+        let addr = (addr&0x0FFF) as usize;
+        let table = addr / 0x400;
+        let bank = match mirroring {
+            Mirroring::Horizontal => table / 2,
+            Mirroring::Vertical => table % 2,
+            Mirroring::SingleLower => 0,
+            Mirroring::SingleUpper => 1,
+            Mirroring::FourScreen => table,
+        };
+        bank * 0x400 + (addr&0x03FF)
+    }
+
+    // $3F10/$3F14/$3F18/$3F1C mirror the backdrop entries below them
+    fn palette_index(addr: u16) -> usize{
+        let index = (addr&0x1F) as usize;
+        if index&0x13 == 0x10 { index&!0x10 } else { index }
+    }
+
+    fn read(&mut self, mapper: &mut dyn Mapper, addr: u16) -> u8{
+        let addr = addr&0x3FFF;
+        match addr {
+            0x0000..=0x1FFF => mapper.ppu_read(addr),
+            0x2000..=0x3EFF => self.vram[self.nametable(mapper.mirroring(), addr)],
+            _ => self.palette[Ppu::palette_index(addr)],
+        }
+    }
+
+    fn write(&mut self, mapper: &mut dyn Mapper, addr: u16, value: u8){
+        let addr = addr&0x3FFF;
+        match addr {
+            0x0000..=0x1FFF => mapper.ppu_write(addr, value),
+            0x2000..=0x3EFF => self.vram[self.nametable(mapper.mirroring(), addr)] = value,
+            _ => self.palette[Ppu::palette_index(addr)] = value&0x3F,
+        }
+    }
+
+    // Registers
+
+    pub fn read_register(&mut self, reg: u16, mapper: &mut dyn Mapper) -> u8{
+        match reg&0x07 {
+            2 => {
+                self.latch = self.status&0xE0 | self.latch&0x1F;
+                self.status &= !VBLANK;
+                self.w = false;
+            },
+            4 => self.latch = self.oam[self.oam_addr as usize],
+            7 => {
+                let addr = self.v&0x3FFF;
+                if addr < 0x3F00 {
+                    self.latch = self.buffer;
+                    self.buffer = self.read(mapper, addr);
+                } else {
+                    // Palette reads skip the buffer, which gets the nametable byte underneath
+                    self.latch = self.read(mapper, addr) | self.latch&0xC0;
+                    self.buffer = self.read(mapper, addr - 0x1000);
+                }
+                self.increment();
+            },
+            _ => (),
+        }
+        self.latch
+    }
+
+    // Register read without side effects
+    pub fn peek_register(&self, reg: u16) -> u8{
+        match reg&0x07 {
+            2 => self.status&0xE0 | self.latch&0x1F,
+            4 => self.oam[self.oam_addr as usize],
+            7 => self.buffer,
+            _ => self.latch,
+        }
+    }
+
+    pub fn write_register(&mut self, reg: u16, value: u8, mapper: &mut dyn Mapper){
+        self.latch = value;
+        match reg&0x07 {
+            0 => {
+                // Enabling NMI during vblank raises the output immediately
+                if self.ctrl&NMI_ENABLE == 0 && value&NMI_ENABLE != 0 && self.status&VBLANK != 0 {
+                    self.nmi_edge = true;
+                }
+                self.ctrl = value;
+                self.t = self.t&!0x0C00 | ((value&0x03) as u16) << 10;
+            },
+            1 => self.mask = value,
+            3 => self.oam_addr = value,
+            4 => {
+                self.oam[self.oam_addr as usize] = value;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            },
+            5 => {
+                if !self.w {
+                    self.t = self.t&!0x001F | (value >> 3) as u16;
+                    self.x = value&0x07;
+                } else {
+                    self.t = self.t&!0x73E0 | ((value&0x07) as u16) << 12 | ((value >> 3) as u16) << 5;
+                }
+                self.w = !self.w;
+            },
+            6 => {
+                if !self.w {
+                    self.t = self.t&0x00FF | ((value&0x3F) as u16) << 8;
+                } else {
+                    self.t = self.t&0xFF00 | value as u16;
+                    self.v = self.t;
+                }
+                self.w = !self.w;
+            },
+            7 => {
+                self.write(mapper, self.v, value);
+                self.increment();
+            },
+            _ => (),
+        }
+    }
+
+    // OAM DMA writes through OAMDATA
+    pub fn write_oam(&mut self, value: u8){
+        self.oam[self.oam_addr as usize] = value;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    // PPUDATA access moves v by 1 or 32, while rendering it bumps both scroll counters instead
+    fn increment(&mut self){
+        if self.rendering() && (self.scanline < 240 || self.scanline == PRE_RENDER) {
+            self.increment_x();
+            self.increment_y();
+        } else {
+            self.v = self.v.wrapping_add(if self.ctrl&INCREMENT != 0 { 32 } else { 1 })&0x7FFF;
+        }
+    }
+
+    // Scrolling
+
+    fn increment_x(&mut self){
+        if self.v&0x001F == 31 {
+            self.v &= !0x001F;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_y(&mut self){
+        if self.v&0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let mut y = (self.v&0x03E0) >> 5;
+        if y == 29 {
+            y = 0;
+            self.v ^= 0x0800;
+        } else if y == 31 {
+            y = 0;
+        } else {
+            y += 1;
+        }
+        self.v = self.v&!0x03E0 | y << 5;
+    }
+
+    fn copy_x(&mut self){
+        self.v = self.v&!0x041F | self.t&0x041F;
+    }
+
+    fn copy_y(&mut self){
+        self.v = self.v&!0x7BE0 | self.t&0x7BE0;
+    }
+
+    // Rendering
+
+    fn load_shifters(&mut self){
+        self.pattern_low = self.pattern_low&0xFF00 | self.tile_low as u16;
+        self.pattern_high = self.pattern_high&0xFF00 | self.tile_high as u16;
+        self.attribute_low = self.attribute_low&0xFF00 | if self.attribute&0x01 != 0 { 0xFF } else { 0x00 };
+        self.attribute_high = self.attribute_high&0xFF00 | if self.attribute&0x02 != 0 { 0xFF } else { 0x00 };
+    }
+
+    fn shift(&mut self){
+        self.pattern_low <<= 1;
+        self.pattern_high <<= 1;
+        self.attribute_low <<= 1;
+        self.attribute_high <<= 1;
+    }
+
+    fn fetch(&mut self, mapper: &mut dyn Mapper){
+        let table = if self.ctrl&BACKGROUND_TABLE != 0 { 0x1000 } else { 0 };
+        let fine_y = (self.v >> 12)&0x07;
+        match (self.dot - 1) % 8 {
+            0 => {
+                self.load_shifters();
+                self.tile = self.read(mapper, 0x2000 | self.v&0x0FFF);
+            },
+            2 => {
+                let addr = 0x23C0 | self.v&0x0C00 | (self.v >> 4)&0x38 | (self.v >> 2)&0x07;
+                let shift = (self.v >> 4)&0x04 | self.v&0x02;
+                self.attribute = (self.read(mapper, addr) >> shift)&0x03;
+            },
+            4 => self.tile_low = self.read(mapper, table + (self.tile as u16) * 16 + fine_y),
+            6 => self.tile_high = self.read(mapper, table + (self.tile as u16) * 16 + fine_y + 8),
+            7 => self.increment_x(),
+            _ => (),
+        }
+    }
+
+    // Picks the sprites of the next scanline and fetches their patterns
+    fn evaluate_sprites(&mut self, mapper: &mut dyn Mapper){
+        let height = if self.ctrl&SPRITE_SIZE != 0 { 16 } else { 8 };
+        self.sprite_count = 0;
+        for i in 0..64 {
+            let y = self.oam[i * 4] as u16;
+            if self.scanline < y || self.scanline - y >= height {
+                continue;
+            }
+            if self.sprite_count == 8 {
+                self.status |= OVERFLOW;
+                break;
+            }
+            let tile = self.oam[i * 4 + 1];
+            let attributes = self.oam[i * 4 + 2];
+            let mut row = self.scanline - y;
+            if attributes&0x80 != 0 {
+                row = height - 1 - row;
+            }
+            let addr = if height == 16 {
+                let table = (tile&0x01) as u16 * 0x1000;
+                let tile = (tile&0xFE) as u16 + (row >> 3);
+                table + tile * 16 + (row&0x07)
+            } else {
+                let table = if self.ctrl&SPRITE_TABLE != 0 { 0x1000 } else { 0 };
+                table + tile as u16 * 16 + row
+            };
+            let mut low = self.read(mapper, addr);
+            let mut high = self.read(mapper, addr + 8);
+            if attributes&0x40 != 0 {
+                low = low.reverse_bits();
+                high = high.reverse_bits();
+            }
+            self.sprites[self.sprite_count] = Sprite{ x: self.oam[i * 4 + 3], attributes, low, high, zero: i == 0 };
+            self.sprite_count += 1;
+        }
+    }
+
+    fn render_pixel(&mut self){
+        let x = (self.dot - 1) as u8;
+        let mut background = 0;
+        if self.mask&SHOW_BACKGROUND != 0 && (x >= 8 || self.mask&BACKGROUND_LEFT != 0) {
+            let bit = 0x8000 >> self.x;
+            let pixel = (self.pattern_low&bit != 0) as u8 | ((self.pattern_high&bit != 0) as u8) << 1;
+            let palette = (self.attribute_low&bit != 0) as u8 | ((self.attribute_high&bit != 0) as u8) << 1;
+            if pixel != 0 {
+                background = palette << 2 | pixel;
+            }
+        }
+        let mut sprite = None;
+        if self.mask&SHOW_SPRITES != 0 && (x >= 8 || self.mask&SPRITES_LEFT != 0) {
+            for s in &self.sprites[..self.sprite_count] {
+                let offset = x.wrapping_sub(s.x);
+                if x < s.x || offset >= 8 {
+                    continue;
+                }
+                let pixel = (s.low >> (7 - offset))&0x01 | ((s.high >> (7 - offset))&0x01) << 1;
+                if pixel != 0 {
+                    sprite = Some((0x10 | (s.attributes&0x03) << 2 | pixel, s.attributes&0x20 != 0, s.zero));
+                    break;
+                }
+            }
+        }
+        let index = match sprite {
+            Some((color, behind, zero)) => {
+                if zero && background != 0 && x != 255 {
+                    self.status |= SPRITE_ZERO;
+                }
+                if behind && background != 0 { background } else { color }
+            },
+            None => background,
+        };
+        let greyscale = if self.mask&GREYSCALE != 0 { 0x30 } else { 0x3F };
+        let color = self.palette[Ppu::palette_index(index as u16)]&greyscale;
+        self.framebuffer[self.scanline as usize * WIDTH + x as usize] = color;
+    }
+
+    // Advances the PPU by one dot
+    pub fn tick(&mut self, mapper: &mut dyn Mapper){
+        let visible = self.scanline < 240;
+        let pre_render = self.scanline == PRE_RENDER;
+        if (visible || pre_render) && self.rendering() {
+            // The shifters run on 2-257 and 322-337, the prefetch of the next line's first tile starts at 321
+            if (2..=257).contains(&self.dot) || (321..=337).contains(&self.dot) {
+                if self.mask&SHOW_BACKGROUND != 0 && self.dot != 321 {
+                    self.shift();
+                }
+                self.fetch(mapper);
+            }
+            match self.dot {
+                256 => self.increment_y(),
+                257 => {
+                    self.copy_x();
+                    if visible {
+                        self.evaluate_sprites(mapper);
+                    } else {
+                        self.sprite_count = 0;
+                    }
+                },
+                // Stands in for the A12 rise of the sprite pattern fetches that clock MMC3
+                260 => mapper.scanline(),
+                280..=304 if pre_render => self.copy_y(),
+                _ => (),
+            }
+        }
+        if visible && (1..=256).contains(&self.dot) {
+            self.render_pixel();
+        }
+        if self.scanline == VBLANK_START && self.dot == 1 {
+            self.status |= VBLANK;
+            if self.ctrl&NMI_ENABLE != 0 {
+                self.nmi_edge = true;
+            }
+            self.frame_ready = true;
+        }
+        if pre_render && self.dot == 1 {
+            self.status &= !(VBLANK|SPRITE_ZERO|OVERFLOW);
+        }
+
+        // Odd frames skip the last dot of the pre-render scanline while rendering
+        let last = if pre_render && self.odd && self.rendering() { 339 } else { 340 };
+        self.dot += 1;
+        if self.dot > last {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline > PRE_RENDER {
+                self.scanline = 0;
+                self.odd = !self.odd;
+                self.frame += 1;
+            }
+        }
+    }
+}
+
+impl Snapshot for Ppu{
+    // The framebuffer is left alone, the next frame redraws it
+    fn save(&self, w: &mut StateWriter){
+        w.u8(self.ctrl);
+        w.u8(self.mask);
+        w.u8(self.status);
+        w.u8(self.oam_addr);
+        w.u16(self.v);
+        w.u16(self.t);
+        w.u8(self.x);
+        w.bool(self.w);
+        w.u8(self.buffer);
+        w.u8(self.latch);
+        w.bytes(&self.vram);
+        w.bytes(&self.palette);
+        w.bytes(&self.oam);
+        w.u16(self.scanline);
+        w.u16(self.dot);
+        w.u64(self.frame);
+        w.bool(self.odd);
+        w.u8(self.tile);
+        w.u8(self.attribute);
+        w.u8(self.tile_low);
+        w.u8(self.tile_high);
+        w.u16(self.pattern_low);
+        w.u16(self.pattern_high);
+        w.u16(self.attribute_low);
+        w.u16(self.attribute_high);
+        for sprite in &self.sprites {
+            w.u8(sprite.x);
+            w.u8(sprite.attributes);
+            w.u8(sprite.low);
+            w.u8(sprite.high);
+            w.bool(sprite.zero);
+        }
+        w.u8(self.sprite_count as u8);
+        w.bool(self.nmi_edge);
+        w.bool(self.frame_ready);
     }
 
-    pub fn cyc(&self) -> u32 {
-        (self.cycles % 341) as u32
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        self.ctrl = r.u8()?;
+        self.mask = r.u8()?;
+        self.status = r.u8()?;
+        self.oam_addr = r.u8()?;
+        self.v = r.u16()?;
+        self.t = r.u16()?;
+        self.x = r.u8()?;
+        self.w = r.bool()?;
+        self.buffer = r.u8()?;
+        self.latch = r.u8()?;
+        r.bytes(&mut self.vram)?;
+        r.bytes(&mut self.palette)?;
+        r.bytes(&mut self.oam)?;
+        self.scanline = r.u16()?;
+        self.dot = r.u16()?;
+        if self.scanline > PRE_RENDER || self.dot > 340 {
+            return Err(StateError::Invalid("PPU position"));
+        }
+        self.frame = r.u64()?;
+        self.odd = r.bool()?;
+        self.tile = r.u8()?;
+        self.attribute = r.u8()?;
+        self.tile_low = r.u8()?;
+        self.tile_high = r.u8()?;
+        self.pattern_low = r.u16()?;
+        self.pattern_high = r.u16()?;
+        self.attribute_low = r.u16()?;
+        self.attribute_high = r.u16()?;
+        for sprite in &mut self.sprites {
+            sprite.x = r.u8()?;
+            sprite.attributes = r.u8()?;
+            sprite.low = r.u8()?;
+            sprite.high = r.u8()?;
+            sprite.zero = r.bool()?;
+        }
+        self.sprite_count = r.u8()? as usize;
+        if self.sprite_count > 8 {
+            return Err(StateError::Invalid("sprite count"));
+        }
+        self.nmi_edge = r.bool()?;
+        self.frame_ready = r.bool()?;
+        Ok(())
     }
 }
@@ -1,8 +1,9 @@
+use std::error::Error;
 use std::fmt::Display;
-use std::{ops::Add};
 
-use crate::memory::{Memory};
+use crate::bus::Bus;
 use crate::op::*;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
 const N: u8 = 0x80;
 const V: u8 = 0x40;
@@ -12,6 +13,89 @@ const D: u8 = 0x08;
 const I: u8 = 0x04;
 const Z: u8 = 0x02;
 const C: u8 = 0x01;
+
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant{
+    // NMOS 6502 with decimal mode
+    Nmos,
+    // Ricoh 2A03, decimal mode is wired off
+    Nes,
+    // WDC 65C02 including the Rockwell bit instructions
+    Cmos,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunState{
+    Running,
+    // WAI, resumes on NMI or IRQ
+    Waiting,
+    // STP, only a reset restarts the CPU
+    Stopped,
+    // KIL/JAM locked up the NMOS core until a reset
+    Jammed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError{
+    // Undocumented opcode while undocumented opcodes are disabled, PC is left on it
+    UnknownOpcode{ opcode: u8, pc: u16 },
+    // KIL/JAM locked up the CPU, every step fails until a reset
+    Jammed{ pc: u16 },
+}
+
+impl Display for CpuError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CpuError::UnknownOpcode{ opcode, pc } => write!(f, "unknown opcode ${opcode:02X} at ${pc:04X}"),
+            CpuError::Jammed{ pc } => write!(f, "CPU jammed at ${pc:04X}"),
+        }
+    }
+}
+
+impl Error for CpuError{}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event{
+    Instruction(u8),
+    Nmi,
+    Irq,
+    // Waiting or stopped, nothing was fetched
+    Idle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepInfo{
+    // PC at the start of the step
+    pub pc: u16,
+    pub cycles: u32,
+    pub event: Event,
+}
+
+// Progress through the instruction or interrupt sequence being ticked
+#[derive(Debug, Clone, Copy)]
+struct Micro{
+    op: Opcode,
+    event: Event,
+    // PC at the start of the sequence
+    pc: u16,
+    // Cycles done so far, the opcode fetch is cycle 1
+    t: u8,
+    // Cycle that produced the effective address, 0 until then
+    ready: u8,
+    addr: u16,
+    ptr: u8,
+    data: u8,
+    // The indexed address still lacks the carry into its high byte
+    fixup: bool,
+    fixed: bool,
+    // Dummy cycles left after the operation
+    extra: u8,
+}
+
 pub struct Processor{
     pub a: u8,
     pub x: u8,
@@ -20,12 +104,37 @@ pub struct Processor{
     pub pc: u16,
     pub p: u8,
     pub cycles: u32,
-    page_crossed: bool
+    pub variant: Variant,
+    // Chip dependent constants ORed into A by XAA and LXA
+    pub xaa_magic: u8,
+    pub lxa_magic: u8,
+    // Execute the undocumented NMOS opcodes instead of failing with CpuError::UnknownOpcode
+    pub undocumented: bool,
+    state: RunState,
+    page_crossed: bool,
+    nmi_pending: bool,
+    irq_line: bool,
+    // I flag as seen by the interrupt poll at the end of the last instruction
+    irq_inhibit: bool,
+    // Interrupts sampled by the last poll, taken at the next instruction boundary
+    poll_nmi: bool,
+    poll_irq: bool,
+    micro: Option<Micro>,
+}
+
+impl Default for Processor{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Processor{
 
     pub fn new() -> Processor{
+        Processor::with_variant(Variant::Nmos)
+    }
+
+    pub fn with_variant(variant: Variant) -> Processor{
         Processor{
             a: 0,
             x: 0,
@@ -34,26 +143,101 @@ impl Processor{
             pc: 0x8000,
             p: 0,
             cycles: 0,
-            page_crossed: false
+            variant,
+            xaa_magic: 0xEE,
+            lxa_magic: 0xEE,
+            undocumented: true,
+            state: RunState::Running,
+            page_crossed: false,
+            nmi_pending: false,
+            irq_line: false,
+            irq_inhibit: false,
+            poll_nmi: false,
+            poll_irq: false,
+            micro: None,
         }
     }
-    pub fn nes() -> Processor{
-        Processor{
+    
+    // Power-on state of the 2A03 followed by the reset sequence
+    pub fn nes<B: Bus>(bus: &mut B) -> Processor{
+        let mut cpu = Processor{
             a: 0,
             x: 0,
             y: 0,
-            s: 0xFD,
-            pc: 0xC000,
-            p: 0x24,
+            s: 0x00,
+            pc: 0,
+            p: U,
             cycles: 0,
-            page_crossed: false
-        }
+            variant: Variant::Nes,
+            xaa_magic: 0xEE,
+            lxa_magic: 0xEE,
+            undocumented: true,
+            state: RunState::Running,
+            page_crossed: false,
+            nmi_pending: false,
+            irq_line: false,
+            irq_inhibit: false,
+            poll_nmi: false,
+            poll_irq: false,
+            micro: None,
+        };
+        cpu.reset(bus);
+        cpu
     }
     
     pub fn page_crossed(&self) -> bool{
         self.page_crossed
     }
 
+    pub fn state(&self) -> RunState{
+        self.state
+    }
+
+    pub fn nmi_pending(&self) -> bool{
+        self.nmi_pending
+    }
+
+    pub fn irq_line(&self) -> bool{
+        self.irq_line
+    }
+
+    // Latches an NMI edge, serviced at the next instruction boundary
+    pub fn nmi(&mut self){
+        self.nmi_pending = true;
+    }
+
+    // Drives the level-triggered IRQ line; it is serviced at an instruction boundary while asserted and I is clear
+    pub fn irq(&mut self, asserted: bool){
+        self.irq_line = asserted;
+    }
+
+    pub fn reset<B: Bus>(&mut self, bus: &mut B){
+        self.s = self.s.wrapping_sub(3);
+        self.p |= I;
+        self.pc = self.read_u16(bus, RESET_VECTOR);
+        self.cycles = 7;
+        self.state = RunState::Running;
+        self.page_crossed = false;
+        self.nmi_pending = false;
+        self.irq_inhibit = true;
+        self.poll_nmi = false;
+        self.poll_irq = false;
+        self.micro = None;
+        if self.variant==Variant::Cmos {
+            self.p &= !D;
+        }
+    }
+
+    // An NMI latched before the vector fetch of a BRK or IRQ sequence takes over its vector
+    fn hijack(&mut self, vector: u16) -> u16{
+        if vector == IRQ_VECTOR && self.nmi_pending {
+            self.nmi_pending = false;
+            NMI_VECTOR
+        } else {
+            vector
+        }
+    }
+
     fn setn(&mut self, value: u8){
         self.p = if value==N{ self.p|N} else {self.p&!N };
     }
@@ -70,2069 +254,1041 @@ impl Processor{
         self.p = if value==C{ self.p|C} else {self.p&!C };
     }
 
-    fn read(&self, mem: &Memory, addr: u16) -> u8 {
-        mem.read(addr)
+    fn read<B: Bus>(&self, bus: &mut B, addr: u16) -> u8 {
+        bus.read(addr)
     }
     
-    fn read_i8(&self, mem: &Memory, addr: u16) -> i8 {
-        mem.read_i8(addr)
+    fn fetch<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let value = bus.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        value
     }
 
-    fn read_u16(&self, mem: &Memory, addr: u16) -> u16 {
-        mem.read_u16(addr)
+    fn read_u16<B: Bus>(&self, bus: &mut B, addr: u16) -> u16 {
+        let lo = bus.read(addr) as u16;
+        let hi = bus.read(addr.wrapping_add(1)) as u16;
+        (hi << 8) | lo
     }
 
-    fn write(&self, mem: &mut Memory, addr: u16, value:u8){
-        mem.write(addr, value);
+    fn write<B: Bus>(&self, bus: &mut B, addr: u16, value:u8){
+        bus.write(addr, value);
     }
     
-    fn push(&mut self, mem: &mut Memory, value: u8){
-        mem.write(0x0100+self.s as u16, value);
+    fn push<B: Bus>(&mut self, bus: &mut B, value: u8){
+        bus.write(0x0100+self.s as u16, value);
         self.s = self.s.wrapping_sub(1);
     }
     
-    fn pull(&mut self, mem: &Memory) -> u8 {
+    fn pull<B: Bus>(&mut self, bus: &mut B) -> u8 {
         self.s = self.s.wrapping_add(1);
-        self.read(mem, 0x0100|self.s as u16)
+        self.read(bus, 0x0100|self.s as u16)
     }
     
-    // Addressing
-
-    fn imm(&mut self) -> u16{
-        let addr = self.pc;
-        self.pc = self.pc.wrapping_add(1);
-        addr
-    }
+    // Operations
 
-    fn zp(&mut self, mem:&Memory ) -> u16{
-        let addr = self.read(mem, self.pc);
-        self.pc = self.pc.wrapping_add(1);
-        addr as u16
+    fn setzn(&mut self, value: u8){
+        self.setz(if value==0{Z}else{0});
+        self.setn(if value&N!=0{N}else{0});
     }
 
-    fn zpx(&mut self, mem:&Memory) -> u16{
-        let addr = self.read(mem, self.pc);
-        self.pc = self.pc.add(1);
-        addr.wrapping_add(self.x) as u16
+    fn decimal(&self) -> bool{
+        self.p&D!=0 && self.variant!=Variant::Nes
     }
 
-    fn zpy(&mut self, mem:&Memory) -> u16{
-        let addr = self.read(mem, self.pc);
-        self.pc = self.pc.wrapping_add(1);
-        addr.wrapping_add(self.y) as u16
-    }
-    
-    fn abs(&mut self, mem: &Memory) -> u16{
-        let addr = self.read_u16(mem, self.pc);
-        self.pc = self.pc.wrapping_add(2);
-        addr
-    }
-    
-    fn absx(&mut self, mem: &Memory) -> u16{
-        let base = self.read_u16(mem, self.pc);
-        self.pc = self.pc.wrapping_add(2);
-        let addr = base.wrapping_add(self.x as u16);
-        if base&0xFF00 != addr &0xFF00{
-            self.cycles+=1;
-            self.page_crossed = true;
+    fn adc_value(&mut self, m: u8){
+        let c = self.p&C;
+        let sum = self.a as u16 + m as u16 + c as u16;
+        if !self.decimal(){
+            self.setc(if sum&0x100!=0{C}else{0});
+            let overflow= !(self.a^m) & (self.a^sum as u8) & N !=0;
+            self.setv(if overflow{V}else{0});
+            self.a = sum as u8;
+            self.setz(if self.a==0{Z}else{0});
+            self.setn(if self.a&N!=0{N}else{0});
+            return;
         }
-        addr
-    }
-    
-    fn absx_ro(&mut self, mem: &Memory) -> u16{
-        let base = self.read_u16(mem, self.pc);
-        self.pc = self.pc.wrapping_add(2);
-        let addr = base.wrapping_add(self.x as u16);
-        addr
-    }
-    
-    fn absy(&mut self, mem: &Memory) -> u16{
-        let base= self.read_u16(mem, self.pc);
-        self.pc = self.pc.wrapping_add(2);
-        let addr = base.wrapping_add(self.y as u16);
-        if base&0xFF00 != addr &0xFF00{
-            self.cycles+=1;
-            self.page_crossed = true;
+        // NMOS decimal mode: Z comes from the binary sum, N and V from the sum before the high nibble is adjusted
+        let mut lo = (self.a&0x0F) as u16 + (m&0x0F) as u16 + c as u16;
+        if lo >= 0x0A {
+            lo = ((lo + 0x06) & 0x0F) + 0x10;
+        }
+        let mut result = (self.a&0xF0) as u16 + (m&0xF0) as u16 + lo;
+        self.setz(if sum as u8==0{Z}else{0});
+        self.setn(if result as u8&N!=0{N}else{0});
+        let overflow= !(self.a^m) & (self.a^result as u8) & N !=0;
+        self.setv(if overflow{V}else{0});
+        if result >= 0xA0 {
+            result += 0x60;
+        }
+        self.setc(if result>=0x100{C}else{0});
+        self.a = result as u8;
+        // The 65C02 produces valid N and Z from the decimal result, at the cost of a cycle
+        if self.variant==Variant::Cmos {
+            self.setz(if self.a==0{Z}else{0});
+            self.setn(if self.a&N!=0{N}else{0});
         }
-        addr
     }
 
-    fn absy_ro(&mut self, mem: &Memory) -> u16{
-        let base= self.read_u16(mem, self.pc);
-        self.pc = self.pc.wrapping_add(2);
-        let addr = base.wrapping_add(self.y as u16);
-        addr
-    }
-    
-    fn ind(&mut self, mem: &Memory) -> u16{
-        let addr = self.read_u16(mem, self.pc);
-        self.pc = self.pc.wrapping_add(2);
-        // addr & 0xFF00 means keep the higher order and (addr + 1) & 0x00FF means don't add the carry bit to higher order
-        (((self.read(mem, addr & 0xFF00 | ((addr+1) & 0x00FF))) as u16) << 8 ) | self.read(mem, addr) as u16
-    }
-    
-    fn indx(&mut self, mem: &Memory) -> u16{
-        let zp = self.read(mem, self.pc);
-        let addr = zp.wrapping_add(self.x);
-        self.pc = self.pc.wrapping_add(1);
-        ((self.read(mem, addr.wrapping_add(1) as u16) as u16) << 8 ) | self.read(mem, addr as u16) as u16
-    }
-    
-    fn indy(&mut self, mem: &Memory) -> u16{
-        let zp = self.read(mem, self.pc);
-        let high = self.read(mem, zp.wrapping_add(1) as u16) as u16;
-        let low = self.read(mem, zp as u16) as u16;
-        let base = high << 8 | low ;
-        let addr = base.wrapping_add(self.y as u16);
-        if base&0xFF00 != addr &0xFF00{
-            self.cycles+=1;
-            self.page_crossed = true;
+    fn sbc_value(&mut self, m: u8){
+        let m_inverse = m ^ 0xFF;
+        let c = self.p&C;
+        let diff = self.a as u16 + m_inverse as u16 + c as u16;
+        self.setc(if diff&0x100!=0{C}else{0});
+        let overflow= ((diff as u8)^m_inverse) & (self.a^diff as u8) & N !=0;
+        self.setv(if overflow{V}else{0});
+        self.setz(if diff as u8==0{Z}else{0});
+        self.setn(if diff as u8&N!=0{N}else{0});
+        if !self.decimal(){
+            self.a = diff as u8;
+            return;
         }
-        self.pc = self.pc.wrapping_add(1);
-        addr
+        if self.variant==Variant::Cmos {
+            let lo = (self.a&0x0F) as i16 - (m&0x0F) as i16 + c as i16 - 1;
+            let mut result = self.a as i16 - m as i16 + c as i16 - 1;
+            if result < 0 {
+                result -= 0x60;
+            }
+            if lo < 0 {
+                result -= 0x06;
+            }
+            self.a = result as u8;
+            self.setz(if self.a==0{Z}else{0});
+            self.setn(if self.a&N!=0{N}else{0});
+            return;
+        }
+        // NMOS decimal mode: flags are those of the binary subtraction, only A is adjusted
+        let mut lo = (self.a&0x0F) as i16 - (m&0x0F) as i16 + c as i16 - 1;
+        if lo < 0 {
+            lo = ((lo - 0x06) & 0x0F) - 0x10;
+        }
+        let mut result = (self.a&0xF0) as i16 - (m&0xF0) as i16 + lo;
+        if result < 0 {
+            result -= 0x60;
+        }
+        self.a = result as u8;
     }
 
-    fn indy_ro(&mut self, mem: &Memory) -> u16{
-        let zp = self.read(mem, self.pc);
-        let high = self.read(mem, zp.wrapping_add(1) as u16) as u16;
-        let low = self.read(mem, zp as u16) as u16;
-        let base = high << 8 | low ;
-        let addr = base.wrapping_add(self.y as u16);
-        self.pc = self.pc.wrapping_add(1);
-        addr
-    }
-    
-    fn rel(&mut self, mem: &Memory) -> u16{
-        let offset = self.read_i8(mem, self.pc) as i16;
-        self.pc = self.pc.wrapping_add(1);
-        ((self.pc as i16).wrapping_add(offset)) as u16
-    }
-    
-    // Instructions
-    fn lda_imm(&mut self, mem: &Memory){
-        let addr = self.imm();
-        self.a = self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn lda_zp(&mut self, mem: &Memory){
-        let addr = self.zp(mem);
-        self.a = self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn lda_zpx(&mut self, mem: &Memory){
-        let addr = self.zpx(mem);
-        self.a = self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn lda_abs(&mut self, mem: &Memory){
-        let addr = self.abs(mem);
-        self.a = self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn lda_absx(&mut self, mem: &Memory){
-        let addr = self.absx(mem);
-        self.a = self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn lda_absy(&mut self, mem: &Memory){
-        let addr = self.absy(mem);
-        self.a = self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn lda_indx(&mut self, mem: &Memory){
-        let addr = self.indx(mem);
-        self.a = self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn lda_indy(&mut self, mem: &Memory){
-        let addr = self.indy(mem);
-        self.a = self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+    fn compare(&mut self, register: u8, m: u8){
+        self.setc(if register >= m {C} else {0});
+        self.setz(if register == m {Z} else {0});
+        let diff = register.wrapping_sub(m);
+        self.setn(if diff&N!=0{N}else{0});
     }
 
-    fn ldx_imm(&mut self, mem: &Memory){
-        let addr = self.imm();
-        self.x = self.read(mem, addr);
-        self.setz(if self.x==0{Z}else{0});
-        self.setn(if self.x&N!=0{N}else{0});
-    }
-    fn ldx_zp(&mut self, mem: &Memory){
-        let addr = self.zp(mem);
-        self.x = self.read(mem, addr);
-        self.setz(if self.x==0{Z}else{0});
-        self.setn(if self.x&N!=0{N}else{0});
-    }
-    fn ldx_zpy(&mut self, mem: &Memory){
-        let addr = self.zpy(mem);
-        self.x = self.read(mem, addr);
-        self.setz(if self.x==0{Z}else{0});
-        self.setn(if self.x&N!=0{N}else{0});
-    }
-    fn ldx_abs(&mut self, mem: &Memory){
-        let addr = self.abs(mem);
-        self.x = self.read(mem, addr);
-        self.setz(if self.x==0{Z}else{0});
-        self.setn(if self.x&N!=0{N}else{0});
-    }
-    fn ldx_absy(&mut self, mem: &Memory){
-        let addr = self.absy(mem);
-        self.x = self.read(mem, addr);
-        self.setz(if self.x==0{Z}else{0});
-        self.setn(if self.x&N!=0{N}else{0});
-    }
-    fn ldy_imm(&mut self, mem: &Memory){
-        let addr = self.imm();
-        self.y = self.read(mem, addr);
-        self.setz(if self.y==0{Z}else{0});
-        self.setn(if self.y&N!=0{N}else{0});
-    }
-    fn ldy_zp(&mut self, mem: &Memory){
-        let addr = self.zp(mem);
-        self.y = self.read(mem, addr);
-        self.setz(if self.y==0{Z}else{0});
-        self.setn(if self.y&N!=0{N}else{0});
-    }
-    fn ldy_zpx(&mut self, mem: &Memory){
-        let addr = self.zpx(mem);
-        self.y = self.read(mem, addr);
-        self.setz(if self.y==0{Z}else{0});
-        self.setn(if self.y&N!=0{N}else{0});
-    } fn ldy_abs(&mut self, mem: &Memory){
-        let addr = self.abs(mem);
-        self.y = self.read(mem, addr);
-        self.setz(if self.y==0{Z}else{0});
-        self.setn(if self.y&N!=0{N}else{0});
-    }
-    fn ldy_absx(&mut self, mem: &Memory){
-        let addr = self.absx(mem);
-        self.y = self.read(mem, addr);
-        self.setz(if self.y==0{Z}else{0});
-        self.setn(if self.y&N!=0{N}else{0});
-    }
-    
-    fn sta_zp(&mut self, mem: &mut Memory){
-        let addr = self.zp(mem);
-        self.write(mem, addr, self.a);
-    }
-    fn sta_zpx(&mut self, mem: &mut Memory){
-        let addr = self.zpx(mem);
-        self.write(mem, addr, self.a);
-    }
-    fn sta_abs(&mut self, mem: &mut Memory){
-        let addr = self.abs(mem);
-        self.write(mem, addr, self.a);
-    }
-    fn sta_absx(&mut self, mem: &mut Memory){
-        let addr = self.absx_ro(mem);
-        self.write(mem, addr, self.a);
-    }
-    fn sta_absy(&mut self, mem: &mut Memory){
-        let addr = self.absy_ro(mem);
-        self.write(mem, addr, self.a);
-    }
-    fn sta_indx(&mut self, mem: &mut Memory){
-        let addr = self.indx(mem);
-        self.write(mem, addr, self.a);
-    }
-    fn sta_indy(&mut self, mem: &mut Memory){
-        let addr = self.indy_ro(mem);
-        self.write(mem, addr, self.a);
+    fn asl_value(&mut self, m: u8) -> u8{
+        self.setc(if m&0x80==0x80 {C} else {0});
+        let m = m.wrapping_shl(1);
+        self.setzn(m);
+        m
     }
 
-    fn stx_zp(&mut self, mem: &mut Memory){
-        let addr = self.zp(mem);
-        self.write(mem, addr, self.x);
-    }
-    fn stx_zpy(&mut self, mem: &mut Memory){
-        let addr = self.zpy(mem);
-        self.write(mem, addr, self.x);
-    }
-    fn stx_abs(&mut self, mem: &mut Memory){
-        let addr = self.abs(mem);
-        self.write(mem, addr, self.x);
+    fn lsr_value(&mut self, m: u8) -> u8{
+        self.setc(if m&C==C {C} else {0});
+        let m = m.wrapping_shr(1);
+        self.setzn(m);
+        m
     }
 
-    fn sty_zp(&mut self, mem: &mut Memory){
-        let addr = self.zp(mem);
-        self.write(mem, addr, self.y);
-    }
-    fn sty_zpx(&mut self, mem: &mut Memory){
-        let addr = self.zpx(mem);
-        self.write(mem, addr, self.y);
-    }
-    fn sty_abs(&mut self, mem: &mut Memory){
-        let addr = self.abs(mem);
-        self.write(mem, addr, self.y);
-    }
-    
-    fn pha(&mut self, mem: &mut Memory){
-        self.push(mem, self.a);
-    }
-    fn php(&mut self, mem: &mut Memory){
-        self.push(mem, self.p | U | B);
+    fn rol_value(&mut self, m: u8) -> u8{
+        let c = self.p&C;
+        self.setc(if m&0x80==0x80 {C} else {0});
+        let m = m.wrapping_shl(1)|c;
+        self.setzn(m);
+        m
     }
 
-    fn tax(&mut self){
-        self.x = self.a;
-        self.setz(if self.x==0{Z}else{0});
-        self.setn(if self.x&N!=0{N}else{0});
-    }
-    fn tay(&mut self){
-        self.y = self.a;
-        self.setz(if self.y==0{Z}else{0});
-        self.setn(if self.y&N!=0{N}else{0});
-    }
-    fn txa(&mut self){
-        self.a = self.x;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn tya(&mut self){
-        self.a = self.y;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn tsx(&mut self){
-        self.x = self.s;
-        self.setz(if self.x==0{Z}else{0});
-        self.setn(if self.x&N!=0{N}else{0});
-    }
-    fn txs(&mut self){
-        self.s = self.x;
+    fn ror_value(&mut self, m: u8) -> u8{
+        let c = (self.p&C)<<7;
+        self.setc(if m&C==C {C} else {0});
+        let m = m.wrapping_shr(1)|c;
+        self.setzn(m);
+        m
     }
 
-    fn pla(&mut self, mem: &Memory){
-        self.a = self.pull(mem);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn plp(&mut self, mem: &Memory){
-        self.p = self.pull(mem)&!B|U;
-    }
-   
-    fn and_imm(&mut self, mem: &Memory){
-        let addr = self.imm();
-        self.a &= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn and_zp(&mut self, mem: &Memory){
-        let addr = self.zp(mem);
-        self.a &= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn and_zpx(&mut self, mem: &Memory){
-        let addr = self.zpx(mem);
-        self.a &= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn and_abs(&mut self, mem: &Memory){
-        let addr = self.abs(mem);
-        self.a &= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn and_absx(&mut self, mem: &Memory){
-        let addr = self.absx(mem);
-        self.a &= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn and_absy(&mut self, mem: &Memory){
-        let addr = self.absy(mem);
-        self.a &= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn and_indx(&mut self, mem: &Memory){
-        let addr = self.indx(mem);
-        self.a &= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn and_indy(&mut self, mem: &Memory){
-        let addr = self.indy(mem);
-        self.a &= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    
-    fn eor_imm(&mut self, mem: &Memory){
-        let addr = self.imm();
-        self.a ^= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn eor_zp(&mut self, mem: &Memory){
-        let addr = self.zp(mem);
-        self.a ^= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn eor_zpx(&mut self, mem: &Memory){
-        let addr = self.zpx(mem);
-        self.a ^= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn eor_abs(&mut self, mem: &Memory){
-        let addr = self.abs(mem);
-        self.a ^= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn eor_absx(&mut self, mem: &Memory){
-        let addr = self.absx(mem);
-        self.a ^= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn eor_absy(&mut self, mem: &Memory){
-        let addr = self.absy(mem);
-        self.a ^= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn eor_indx(&mut self, mem: &Memory){
-        let addr = self.indx(mem);
-        self.a ^= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+    fn arr_value(&mut self, m: u8){
+        let t = self.a&m;
+        let result = (t >> 1) | ((self.p&C) << 7);
+        self.setzn(result);
+        if !self.decimal(){
+            self.setc(if result&0x40!=0{C}else{0});
+            self.setv(if (result^(result<<1))&0x40!=0{V}else{0});
+            self.a = result;
+            return;
+        }
+        // NMOS decimal mode fixes up each nibble of the rotated value based on the unrotated one
+        self.setv(if (t^result)&0x40!=0{V}else{0});
+        let mut result = result;
+        if (t&0x0F) + (t&0x01) > 0x05 {
+            result = (result&0xF0) | (result.wrapping_add(0x06)&0x0F);
+        }
+        if (t&0xF0) as u16 + (t&0x10) as u16 > 0x50 {
+            result = result.wrapping_add(0x60);
+            self.setc(C);
+        } else {
+            self.setc(0);
+        }
+        self.a = result;
     }
-    fn eor_indy(&mut self, mem: &Memory){
-        let addr = self.indy(mem);
-        self.a ^= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+
+    // SHA, SHX, SHY and TAS AND the stored value with the high byte of the base address plus one,
+    // and when indexing crosses a page that value also replaces the high byte of the target
+    fn store_high<B: Bus>(&mut self, bus: &mut B, mnemonic: Mnemonic, mode: Mode, addr: u16){
+        let index = if mode==Mode::AbsoluteX { self.x } else { self.y };
+        let base = addr.wrapping_sub(index as u16);
+        let high = ((base >> 8) as u8).wrapping_add(1);
+        let value = match mnemonic {
+            Mnemonic::Sha => self.a & self.x & high,
+            Mnemonic::Shx => self.x & high,
+            Mnemonic::Shy => self.y & high,
+            Mnemonic::Tas => {
+                self.s = self.a & self.x;
+                self.s & high
+            },
+            _ => unreachable!("{mnemonic} is not a high byte store"),
+        };
+        let addr = if base&0xFF00 != addr&0xFF00 { (value as u16) << 8 | (addr&0x00FF) } else { addr };
+        self.write(bus, addr, value);
     }
 
-    fn ora_imm(&mut self, mem: &Memory){
-        let addr = self.imm();
-        self.a |= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+    fn read_op(&mut self, mnemonic: Mnemonic, mode: Mode, m: u8){
+        use Mnemonic::*;
+        match mnemonic {
+            Lda => {self.a = m; self.setzn(m);},
+            Ldx => {self.x = m; self.setzn(m);},
+            Ldy => {self.y = m; self.setzn(m);},
+            Lax => {self.a = m; self.x = m; self.setzn(m);},
+            And => {self.a &= m; self.setzn(self.a);},
+            Ora => {self.a |= m; self.setzn(self.a);},
+            Eor => {self.a ^= m; self.setzn(self.a);},
+            Adc => self.adc_value(m),
+            Sbc => self.sbc_value(m),
+            Cmp => self.compare(self.a, m),
+            Cpx => self.compare(self.x, m),
+            Cpy => self.compare(self.y, m),
+            Bit => {
+                self.setz(if self.a&m==0{Z}else{0});
+                // BIT #imm only affects Z
+                if mode != Mode::Immediate {
+                    self.setv(m&V);
+                    self.setn(m&N);
+                }
+            },
+            Nop => (),
+            Anc => {
+                self.a &= m;
+                self.setzn(self.a);
+                self.setc(if self.a&N!=0{C}else{0});
+            },
+            Alr => self.a = self.lsr_value(self.a&m),
+            Arr => self.arr_value(m),
+            Sbx => {
+                let t = self.a&self.x;
+                self.setc(if t >= m {C} else {0});
+                self.x = t.wrapping_sub(m);
+                self.setzn(self.x);
+            },
+            Las => {
+                let value = m&self.s;
+                self.a = value;
+                self.x = value;
+                self.s = value;
+                self.setzn(value);
+            },
+            Xaa => {
+                self.a = (self.a|self.xaa_magic) & self.x & m;
+                self.setzn(self.a);
+            },
+            Lxa => {
+                self.a = (self.a|self.lxa_magic) & m;
+                self.x = self.a;
+                self.setzn(self.a);
+            },
+            _ => unreachable!("{mnemonic} does not read its operand"),
+        }
     }
-    fn ora_zp(&mut self, mem: &Memory){
-        let addr = self.zp(mem);
-        self.a |= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+
+    fn store_op(&mut self, mnemonic: Mnemonic) -> u8{
+        use Mnemonic::*;
+        match mnemonic {
+            Sta => self.a,
+            Stx => self.x,
+            Sty => self.y,
+            Sax => self.a&self.x,
+            Stz => 0,
+            _ => unreachable!("{mnemonic} does not write its operand"),
+        }
     }
-    fn ora_zpx(&mut self, mem: &Memory){
-        let addr = self.zpx(mem);
-        self.a |= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+
+    fn modify_op(&mut self, mnemonic: Mnemonic, m: u8) -> u8{
+        use Mnemonic::*;
+        match mnemonic {
+            Asl => self.asl_value(m),
+            Lsr => self.lsr_value(m),
+            Rol => self.rol_value(m),
+            Ror => self.ror_value(m),
+            Inc => {
+                let m = m.wrapping_add(1);
+                self.setzn(m);
+                m
+            },
+            Dec => {
+                let m = m.wrapping_sub(1);
+                self.setzn(m);
+                m
+            },
+            Slo => {
+                let m = self.asl_value(m);
+                self.a |= m;
+                self.setzn(self.a);
+                m
+            },
+            Rla => {
+                let m = self.rol_value(m);
+                self.a &= m;
+                self.setzn(self.a);
+                m
+            },
+            Sre => {
+                let m = self.lsr_value(m);
+                self.a ^= m;
+                self.setzn(self.a);
+                m
+            },
+            Rra => {
+                let m = self.ror_value(m);
+                self.adc_value(m);
+                m
+            },
+            Dcp => {
+                let m = m.wrapping_sub(1);
+                self.compare(self.a, m);
+                m
+            },
+            Isc => {
+                let m = m.wrapping_add(1);
+                self.sbc_value(m);
+                m
+            },
+            Trb => {
+                self.setz(if self.a&m==0{Z}else{0});
+                m&!self.a
+            },
+            Tsb => {
+                self.setz(if self.a&m==0{Z}else{0});
+                m|self.a
+            },
+            Rmb(n) => m&!(1<<n),
+            Smb(n) => m|(1<<n),
+            _ => unreachable!("{mnemonic} does not modify its operand"),
+        }
     }
-    fn ora_abs(&mut self, mem: &Memory){
-        let addr = self.abs(mem);
-        self.a |= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+
+    // Register and flag operations of the two cycle implied instructions
+    fn implied(&mut self, mnemonic: Mnemonic){
+        use Mnemonic::*;
+        match mnemonic {
+            Tax => {self.x = self.a; self.setzn(self.x);},
+            Tay => {self.y = self.a; self.setzn(self.y);},
+            Txa => {self.a = self.x; self.setzn(self.a);},
+            Tya => {self.a = self.y; self.setzn(self.a);},
+            Tsx => {self.x = self.s; self.setzn(self.x);},
+            Txs => self.s = self.x,
+
+            Inx => {self.x = self.x.wrapping_add(1); self.setzn(self.x);},
+            Iny => {self.y = self.y.wrapping_add(1); self.setzn(self.y);},
+            Dex => {self.x = self.x.wrapping_sub(1); self.setzn(self.x);},
+            Dey => {self.y = self.y.wrapping_sub(1); self.setzn(self.y);},
+
+            Clc => self.p &= !C,
+            Cld => self.p &= !D,
+            Cli => self.p &= !I,
+            Clv => self.p &= !V,
+            Sec => self.p |= C,
+            Sed => self.p |= D,
+            Sei => self.p |= I,
+            _ => unreachable!("{mnemonic} is not an implied operation"),
+        }
     }
-    fn ora_absx(&mut self, mem: &Memory){
-        let addr = self.absx(mem);
-        self.a |= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+
+    // Samples the interrupt lines, the CPU does this at the end of every cycle but the last of an instruction
+    fn poll(&mut self){
+        self.irq_inhibit = self.p&I!=0;
+        self.poll_nmi = self.nmi_pending;
+        self.poll_irq = self.irq_line && !self.irq_inhibit;
     }
-    fn ora_absy(&mut self, mem: &Memory){
-        let addr = self.absy(mem);
-        self.a |= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+
+    // Second sample of a taken branch crossing a page, ORed with the one before the offset fetch
+    fn poll_again(&mut self){
+        self.irq_inhibit = self.p&I!=0;
+        self.poll_nmi |= self.nmi_pending;
+        self.poll_irq |= self.irq_line && !self.irq_inhibit;
     }
-    fn ora_indx(&mut self, mem: &Memory){
-        let addr = self.indx(mem);
-        self.a |= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+
+    // Cycles
+
+    // Operand fetch of the addressing mode, returns true on the cycle that leaves the effective address in m.addr
+    fn address<B: Bus>(&mut self, bus: &mut B, m: &mut Micro) -> bool{
+        match (m.op.mode, m.t) {
+            (Mode::ZeroPage, 2) => {
+                m.addr = self.fetch(bus) as u16;
+                true
+            },
+            (Mode::ZeroPageX | Mode::ZeroPageY | Mode::IndirectX | Mode::IndirectY | Mode::ZeroPageIndirect, 2) => {
+                m.ptr = self.fetch(bus);
+                false
+            },
+            (Mode::ZeroPageX | Mode::ZeroPageY, 3) => {
+                self.read(bus, m.ptr as u16);
+                let index = if m.op.mode==Mode::ZeroPageX { self.x } else { self.y };
+                m.addr = m.ptr.wrapping_add(index) as u16;
+                true
+            },
+            (Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY, 2) => {
+                m.data = self.fetch(bus);
+                false
+            },
+            (Mode::Absolute, 3) => {
+                m.addr = (self.fetch(bus) as u16) << 8 | m.data as u16;
+                true
+            },
+            (Mode::AbsoluteX | Mode::AbsoluteY, 3) => {
+                let high = self.fetch(bus);
+                let index = if m.op.mode==Mode::AbsoluteX { self.x } else { self.y };
+                let (low, carry) = m.data.overflowing_add(index);
+                m.addr = (high as u16) << 8 | low as u16;
+                m.fixup = carry;
+                true
+            },
+            (Mode::IndirectX, 3) => {
+                self.read(bus, m.ptr as u16);
+                m.ptr = m.ptr.wrapping_add(self.x);
+                false
+            },
+            (Mode::IndirectX, 4) | (Mode::IndirectY | Mode::ZeroPageIndirect, 3) => {
+                m.data = self.read(bus, m.ptr as u16);
+                false
+            },
+            (Mode::IndirectX, 5) | (Mode::ZeroPageIndirect, 4) => {
+                let high = self.read(bus, m.ptr.wrapping_add(1) as u16);
+                m.addr = (high as u16) << 8 | m.data as u16;
+                true
+            },
+            (Mode::IndirectY, 4) => {
+                let high = self.read(bus, m.ptr.wrapping_add(1) as u16);
+                let (low, carry) = m.data.overflowing_add(self.y);
+                m.addr = (high as u16) << 8 | low as u16;
+                m.fixup = carry;
+                true
+            },
+            (mode, t) => unreachable!("{mode:?} has no addressing cycle {t}"),
+        }
     }
-    fn ora_indy(&mut self, mem: &Memory){
-        let addr = self.indy(mem);
-        self.a |= self.read(mem, addr);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+
+    // Cycles after the effective address is known, k counts from 1
+    fn access<B: Bus>(&mut self, bus: &mut B, m: &mut Micro, k: u8) -> bool{
+        let mnemonic = m.op.mnemonic;
+        let indexed = matches!(m.op.mode, Mode::AbsoluteX | Mode::AbsoluteY | Mode::IndirectY);
+        // Indexed modes read from the address before its high byte is fixed up, reads skip this when no page was crossed
+        if indexed && !m.fixed {
+            m.fixed = true;
+            if m.fixup || !m.op.page_penalty {
+                self.read(bus, m.addr);
+                if m.fixup {
+                    m.addr = m.addr.wrapping_add(0x100);
+                    self.page_crossed = m.op.page_penalty;
+                }
+                m.ready = m.t;
+                return false;
+            }
+        }
+        match (mnemonic.access(), m.op.mode, k) {
+            (Access::Read, Mode::Implied, 1) => {
+                self.read(bus, self.pc);
+                self.read_op(mnemonic, m.op.mode, 0);
+                true
+            },
+            (Access::Read, mode, 1) => {
+                let value = self.read(bus, m.addr);
+                let decimal = self.variant==Variant::Cmos && self.p&D!=0 && matches!(mnemonic, Mnemonic::Adc | Mnemonic::Sbc);
+                self.read_op(mnemonic, mode, value);
+                // The 65C02 spends a cycle fixing up N and Z after a decimal add or subtract,
+                // and its $5C NOP keeps the bus busy for the eight cycles of the table
+                m.extra = m.op.cycles.saturating_sub(m.t) + decimal as u8;
+                m.extra == 0
+            },
+            (Access::Write, mode, 1) => {
+                if matches!(mnemonic, Mnemonic::Sha | Mnemonic::Shx | Mnemonic::Shy | Mnemonic::Tas) {
+                    self.store_high(bus, mnemonic, mode, m.addr);
+                } else {
+                    let value = self.store_op(mnemonic);
+                    self.write(bus, m.addr, value);
+                }
+                true
+            },
+            (Access::Modify, Mode::Accumulator, 1) => {
+                self.read(bus, self.pc);
+                self.a = self.modify_op(mnemonic, self.a);
+                true
+            },
+            (Access::Modify, _, 1) => {
+                m.data = self.read(bus, m.addr);
+                false
+            },
+            (Access::Modify, _, 2) => {
+                // NMOS writes the unmodified value back while the ALU works, the 65C02 reads it again
+                if self.variant==Variant::Cmos {
+                    self.read(bus, m.addr);
+                } else {
+                    self.write(bus, m.addr, m.data);
+                }
+                m.data = self.modify_op(mnemonic, m.data);
+                false
+            },
+            (Access::Modify, _, 3) => {
+                self.write(bus, m.addr, m.data);
+                true
+            },
+            (access, mode, k) => unreachable!("{mnemonic} {mode:?} has no {access:?} cycle {k}"),
+        }
     }
-    
-    fn bit_zp(&mut self, mem: &Memory){
-        let addr = self.zp(mem);
-        let m = self.read(mem, addr);
-        self.setz(if self.a&m==0{Z}else{0});
-        self.setv(m&V);
-        self.setn(m&N);
+
+    // Relative branch tail, k is 0 on the offset fetch
+    fn branch<B: Bus>(&mut self, bus: &mut B, m: &mut Micro, k: u8, taken: bool) -> bool{
+        match k {
+            0 => {
+                m.data = self.fetch(bus);
+                !taken
+            },
+            1 => {
+                self.read(bus, self.pc);
+                let target = self.pc.wrapping_add(m.data as i8 as u16);
+                if target&0xFF00 == self.pc&0xFF00 {
+                    self.pc = target;
+                    return true;
+                }
+                // PCL is updated first, the carry into PCH costs another cycle
+                m.addr = target;
+                self.pc = self.pc&0xFF00 | target&0x00FF;
+                self.page_crossed = true;
+                false
+            },
+            _ => {
+                self.read(bus, self.pc);
+                self.pc = m.addr;
+                true
+            },
+        }
     }
-    fn bit_abs(&mut self, mem: &Memory){
-        let addr = self.abs(mem);
-        let m = self.read(mem, addr);
-        self.setz(if self.a&m==0{Z}else{0});
-        self.setv(m&V);
-        self.setn(m&N);
+
+    fn control<B: Bus>(&mut self, bus: &mut B, m: &mut Micro) -> bool{
+        use Mnemonic::*;
+        let mnemonic = m.op.mnemonic;
+        match (mnemonic, m.op.mode, m.t) {
+            (Jmp, _, 2) => {
+                m.data = self.fetch(bus);
+                false
+            },
+            (Jmp, Mode::Absolute, 3) => {
+                self.pc = (self.read(bus, self.pc) as u16) << 8 | m.data as u16;
+                true
+            },
+            (Jmp, _, 3) => {
+                m.addr = (self.fetch(bus) as u16) << 8 | m.data as u16;
+                false
+            },
+            // The 65C02 spends a cycle on the fixed indirection and on adding X
+            (Jmp, Mode::AbsoluteIndexedIndirect, 4) => {
+                self.read(bus, self.pc.wrapping_sub(1));
+                m.addr = m.addr.wrapping_add(self.x as u16);
+                false
+            },
+            (Jmp, Mode::Indirect, 4) if self.variant==Variant::Cmos => {
+                self.read(bus, self.pc.wrapping_sub(1));
+                false
+            },
+            (Jmp, Mode::Indirect, 4) => {
+                m.data = self.read(bus, m.addr);
+                false
+            },
+            (Jmp, Mode::Indirect, 5) if self.variant!=Variant::Cmos => {
+                // The NMOS pointer does not carry into its high byte
+                let high = self.read(bus, m.addr&0xFF00 | m.addr.wrapping_add(1)&0x00FF);
+                self.pc = (high as u16) << 8 | m.data as u16;
+                true
+            },
+            (Jmp, _, 5) => {
+                m.data = self.read(bus, m.addr);
+                false
+            },
+            (Jmp, _, 6) => {
+                let high = self.read(bus, m.addr.wrapping_add(1));
+                self.pc = (high as u16) << 8 | m.data as u16;
+                true
+            },
+
+            (Jsr, _, 2) => {
+                m.data = self.fetch(bus);
+                false
+            },
+            (Jsr, _, 3) => {
+                self.read(bus, 0x0100|self.s as u16);
+                false
+            },
+            (Jsr, _, 4) => {
+                self.push(bus, (self.pc >> 8) as u8);
+                false
+            },
+            (Jsr, _, 5) => {
+                self.push(bus, self.pc as u8);
+                false
+            },
+            (Jsr, _, 6) => {
+                self.pc = (self.read(bus, self.pc) as u16) << 8 | m.data as u16;
+                true
+            },
+
+            (Brk, _, 2) => {
+                // The byte after BRK is padding and is skipped by the return address
+                self.fetch(bus);
+                false
+            },
+            (Brk, _, 3) => {
+                self.push(bus, (self.pc >> 8) as u8);
+                false
+            },
+            (Brk, _, 4) => {
+                self.push(bus, self.pc as u8);
+                false
+            },
+            (Brk, _, 5) => {
+                self.push(bus, self.p|B|U);
+                self.p |= I;
+                self.irq_inhibit = true;
+                if self.variant==Variant::Cmos {
+                    self.p &= !D;
+                }
+                m.addr = self.hijack(IRQ_VECTOR);
+                false
+            },
+            (Brk, _, 6) => {
+                m.data = self.read(bus, m.addr);
+                false
+            },
+            (Brk, _, 7) => {
+                self.pc = (self.read(bus, m.addr.wrapping_add(1)) as u16) << 8 | m.data as u16;
+                true
+            },
+
+            (Rts | Rti | Pla | Plp | Plx | Ply | Pha | Php | Phx | Phy | Wai | Stp, _, 2) => {
+                self.read(bus, self.pc);
+                false
+            },
+            (Pha, _, 3) => {
+                self.push(bus, self.a);
+                true
+            },
+            (Php, _, 3) => {
+                self.push(bus, self.p | U | B);
+                true
+            },
+            (Phx, _, 3) => {
+                self.push(bus, self.x);
+                true
+            },
+            (Phy, _, 3) => {
+                self.push(bus, self.y);
+                true
+            },
+            (Rts | Rti | Pla | Plp | Plx | Ply, _, 3) => {
+                self.read(bus, 0x0100|self.s as u16);
+                false
+            },
+            (Pla, _, 4) => {
+                self.a = self.pull(bus);
+                self.setzn(self.a);
+                true
+            },
+            (Plx, _, 4) => {
+                self.x = self.pull(bus);
+                self.setzn(self.x);
+                true
+            },
+            (Ply, _, 4) => {
+                self.y = self.pull(bus);
+                self.setzn(self.y);
+                true
+            },
+            (Plp, _, 4) => {
+                self.p = self.pull(bus)&!B|U;
+                true
+            },
+            (Rts, _, 4) | (Rti, _, 5) => {
+                m.data = self.pull(bus);
+                false
+            },
+            (Rts, _, 5) => {
+                self.pc = (self.pull(bus) as u16) << 8 | m.data as u16;
+                false
+            },
+            (Rts, _, 6) => {
+                self.fetch(bus);
+                true
+            },
+            (Rti, _, 4) => {
+                self.p = (self.pull(bus) & !B) | U;
+                false
+            },
+            (Rti, _, 6) => {
+                self.pc = (self.pull(bus) as u16) << 8 | m.data as u16;
+                true
+            },
+
+            (Bpl, _, t) => self.branch(bus, m, t - 2, self.p&N==0),
+            (Bmi, _, t) => self.branch(bus, m, t - 2, self.p&N!=0),
+            (Bvc, _, t) => self.branch(bus, m, t - 2, self.p&V==0),
+            (Bvs, _, t) => self.branch(bus, m, t - 2, self.p&V!=0),
+            (Bcc, _, t) => self.branch(bus, m, t - 2, self.p&C==0),
+            (Bcs, _, t) => self.branch(bus, m, t - 2, self.p&C!=0),
+            (Bne, _, t) => self.branch(bus, m, t - 2, self.p&Z==0),
+            (Beq, _, t) => self.branch(bus, m, t - 2, self.p&Z!=0),
+            (Bra, _, t) => self.branch(bus, m, t - 2, true),
+
+            // BBRn/BBSn test the zero page operand, read it twice, then fetch the branch offset
+            (Bbr(_) | Bbs(_), _, 2) => {
+                m.addr = self.fetch(bus) as u16;
+                false
+            },
+            (Bbr(_) | Bbs(_), _, 3) => {
+                m.ptr = self.read(bus, m.addr);
+                false
+            },
+            (Bbr(_) | Bbs(_), _, 4) => {
+                self.read(bus, m.addr);
+                false
+            },
+            (Bbr(n) | Bbs(n), _, t) => {
+                let taken = (m.ptr&(1<<n)!=0) == matches!(mnemonic, Bbs(_));
+                self.branch(bus, m, t - 5, taken)
+            },
+
+            (Wai, _, 3) => {
+                self.read(bus, self.pc);
+                self.state = RunState::Waiting;
+                true
+            },
+            (Stp, _, 3) => {
+                self.read(bus, self.pc);
+                self.state = RunState::Stopped;
+                true
+            },
+            (Jam, _, _) => {
+                // Leave PC on the opcode so the lock up is visible
+                self.read(bus, self.pc);
+                self.pc = self.pc.wrapping_sub(1);
+                self.state = RunState::Jammed;
+                true
+            },
+
+            (_, _, 2) => {
+                self.read(bus, self.pc);
+                self.implied(mnemonic);
+                true
+            },
+            (mnemonic, mode, t) => unreachable!("{mnemonic} {mode:?} has no cycle {t}"),
+        }
     }
-    
-    fn adc_imm(&mut self, mem: &Memory){
-        let addr = self.imm();
-        let m = self.read(mem, addr);
-        let c = self.p&C;
-        let sum = self.a as u16 + m as u16 + c as u16;
-        self.setc(if sum&0x100!=0{C}else{0});
-        let overflow= !(self.a^m) & (self.a^sum as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = sum as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+
+    // NMI and IRQ share the BRK sequence but fetch the opcode twice without advancing PC
+    fn interrupt<B: Bus>(&mut self, bus: &mut B, m: &mut Micro) -> bool{
+        match m.t {
+            2 => {
+                self.read(bus, self.pc);
+                false
+            },
+            3 => {
+                self.push(bus, (self.pc >> 8) as u8);
+                false
+            },
+            4 => {
+                self.push(bus, self.pc as u8);
+                false
+            },
+            5 => {
+                self.push(bus, (self.p|U)&!B);
+                self.p |= I;
+                self.irq_inhibit = true;
+                if self.variant==Variant::Cmos {
+                    self.p &= !D;
+                }
+                m.addr = if m.event==Event::Nmi { NMI_VECTOR } else { self.hijack(IRQ_VECTOR) };
+                false
+            },
+            6 => {
+                m.data = self.read(bus, m.addr);
+                false
+            },
+            _ => {
+                self.pc = (self.read(bus, m.addr.wrapping_add(1)) as u16) << 8 | m.data as u16;
+                true
+            },
+        }
     }
-    fn adc_zp(&mut self, mem: &Memory){
-        let addr = self.zp(mem);
-        let m = self.read(mem, addr);
-        let c = self.p&C;
-        let sum = self.a as u16 + m as u16 + c as u16;
-        self.setc(if sum&0x100!=0{C}else{0});
-        let overflow= !(self.a^m) & (self.a^sum as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = sum as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+
+    fn cycle<B: Bus>(&mut self, bus: &mut B, m: &mut Micro) -> bool{
+        if m.extra > 0 {
+            self.read(bus, m.addr);
+            m.extra -= 1;
+            return m.extra == 0;
+        }
+        if !matches!(m.event, Event::Instruction(_)) {
+            return self.interrupt(bus, m);
+        }
+        match m.op.mnemonic.access() {
+            Access::Other => self.control(bus, m),
+            _ if m.ready > 0 => self.access(bus, m, m.t - m.ready),
+            _ => {
+                if self.address(bus, m) {
+                    m.ready = m.t;
+                }
+                false
+            },
+        }
     }
-    fn adc_zpx(&mut self, mem: &Memory){
-        let addr = self.zpx(mem);
-        let m = self.read(mem, addr);
-        let c = self.p&C;
-        let sum = self.a as u16 + m as u16 + c as u16;
-        self.setc(if sum&0x100!=0{C}else{0});
-        let overflow= !(self.a^m) & (self.a^sum as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = sum as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+
+    // Starts an instruction or interrupt sequence with its first cycle
+    fn begin<B: Bus>(&mut self, bus: &mut B) -> Result<Micro, CpuError>{
+        let pc = self.pc;
+        self.page_crossed = false;
+        let (event, opcode) = if self.poll_nmi {
+            self.nmi_pending = false;
+            (Event::Nmi, 0x00)
+        } else if self.poll_irq {
+            (Event::Irq, 0x00)
+        } else {
+            let opcode = bus.peek(pc);
+            if !self.undocumented && opcodes(self.variant)[opcode as usize].legality != Legality::Legal {
+                self.cycles = 0;
+                return Err(CpuError::UnknownOpcode{ opcode, pc });
+            }
+            (Event::Instruction(opcode), opcode)
+        };
+        let mut m = Micro{
+            op: opcodes(self.variant)[opcode as usize],
+            event,
+            pc,
+            t: 1,
+            ready: 0,
+            addr: 0,
+            ptr: 0,
+            data: 0,
+            fixup: false,
+            fixed: false,
+            extra: 0,
+        };
+        if let Event::Instruction(_) = event {
+            self.fetch(bus);
+            match m.op.mode {
+                Mode::Immediate => {
+                    m.addr = self.pc;
+                    self.pc = self.pc.wrapping_add(1);
+                    m.ready = 1;
+                },
+                Mode::Implied | Mode::Accumulator => m.ready = 1,
+                _ => (),
+            }
+        } else {
+            self.read(bus, pc);
+            self.poll_nmi = false;
+            self.poll_irq = false;
+        }
+        Ok(m)
     }
-    fn adc_abs(&mut self, mem: &Memory){
-        let addr = self.abs(mem);
-        let m = self.read(mem, addr);
-        let c = self.p&C;
-        let sum = self.a as u16 + m as u16 + c as u16;
-        self.setc(if sum&0x100!=0{C}else{0});
-        let overflow= !(self.a^m) & (self.a^sum as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = sum as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+
+    // Advances the CPU by one clock cycle, every bus access of the instruction happens on its own cycle.
+    // Returns the instruction or interrupt sequence that finished on this cycle
+    pub fn tick<B: Bus>(&mut self, bus: &mut B) -> Result<Option<StepInfo>, CpuError>{
+        match self.state {
+            RunState::Jammed => {
+                self.cycles = 1;
+                return Err(CpuError::Jammed{ pc: self.pc });
+            },
+            RunState::Stopped => {
+                self.cycles = 1;
+                return Ok(Some(StepInfo{ pc: self.pc, cycles: 1, event: Event::Idle }));
+            },
+            RunState::Waiting => {
+                if !self.nmi_pending && !self.irq_line {
+                    self.cycles = 1;
+                    return Ok(Some(StepInfo{ pc: self.pc, cycles: 1, event: Event::Idle }));
+                }
+                // A masked IRQ still ends the wait, execution just continues after WAI
+                self.state = RunState::Running;
+                self.poll();
+            },
+            RunState::Running => ()
+        }
+        let m = match self.micro.take() {
+            Some(mut m) => {
+                m.t += 1;
+                if self.cycle(bus, &mut m) {
+                    return self.finish(m);
+                }
+                m
+            },
+            None => {
+                let m = self.begin(bus)?;
+                if m.op.cycles == 1 && matches!(m.event, Event::Instruction(_)) {
+                    return self.finish(m);
+                }
+                m
+            },
+        };
+        self.cycles = m.t as u32;
+        // A taken branch keeps the sample from before its offset fetch, one that crosses a page samples
+        // again on the PCL cycle and takes an interrupt seen by either. Like interrupts BRK does not
+        // poll at all so the first instruction of the handler always runs
+        let offset_fetch = match m.op.mode {
+            Mode::Relative => 2,
+            Mode::ZeroPageRelative => 5,
+            _ => 0,
+        };
+        if matches!(m.event, Event::Instruction(_)) && m.op.mnemonic != Mnemonic::Brk {
+            if offset_fetch == 0 || m.t < offset_fetch {
+                self.poll();
+            } else if m.t == offset_fetch + 1 {
+                self.poll_again();
+            }
+        }
+        self.micro = Some(m);
+        Ok(None)
     }
-    fn adc_absx(&mut self, mem: &Memory){
-        let addr = self.absx(mem);
-        let m = self.read(mem, addr);
-        let c = self.p&C;
-        let sum = self.a as u16 + m as u16 + c as u16;
-        self.setc(if sum&0x100!=0{C}else{0});
-        let overflow= !(self.a^m) & (self.a^sum as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = sum as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+
+    fn finish(&mut self, m: Micro) -> Result<Option<StepInfo>, CpuError>{
+        self.cycles = m.t as u32;
+        if self.state == RunState::Jammed {
+            return Err(CpuError::Jammed{ pc: m.pc });
+        }
+        Ok(Some(StepInfo{ pc: m.pc, cycles: m.t as u32, event: m.event }))
     }
-    fn adc_absy(&mut self, mem: &Memory){
-        let addr = self.absy(mem);
-        let m = self.read(mem, addr);
-        let c = self.p&C;
-        let sum = self.a as u16 + m as u16 + c as u16;
-        self.setc(if sum&0x100!=0{C}else{0});
-        let overflow= !(self.a^m) & (self.a^sum as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = sum as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+
+    // Runs a whole instruction or interrupt sequence, interrupts latched since the last step are taken first
+    pub fn step<B: Bus>(&mut self, bus: &mut B) -> Result<StepInfo, CpuError>{
+        if self.micro.is_none() {
+            self.poll_nmi = self.nmi_pending;
+            self.poll_irq = self.irq_line && !self.irq_inhibit;
+        }
+        loop {
+            if let Some(info) = self.tick(bus)? {
+                return Ok(info);
+            }
+        }
     }
-    fn adc_indx(&mut self, mem: &Memory){
-        let addr = self.indx(mem);
-        let m = self.read(mem, addr);
-        let c = self.p&C;
-        let sum = self.a as u16 + m as u16 + c as u16;
-        self.setc(if sum&0x100!=0{C}else{0});
-        let overflow= !(self.a^m) & (self.a^sum as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = sum as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+}
+
+impl Display for Processor{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:X}        A: {:X} X: {:X} Y: {:X} P: {:X} SP: {:X}", self.pc, self.a, self.x, self.y, self.p, self.s)
     }
-    fn adc_indy(&mut self, mem: &Memory){
-        let addr = self.indy(mem);
-        let m = self.read(mem, addr);
-        let c = self.p&C;
-        let sum = self.a as u16 + m as u16 + c as u16;
-        self.setc(if sum&0x100!=0{C}else{0});
-        let overflow= !(self.a^m) & (self.a^sum as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = sum as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
+}
+
+impl Snapshot for Processor{
+    fn save(&self, w: &mut StateWriter){
+        w.u8(self.a);
+        w.u8(self.x);
+        w.u8(self.y);
+        w.u8(self.s);
+        w.u16(self.pc);
+        w.u8(self.p);
+        w.u32(self.cycles);
+        w.u8(self.variant as u8);
+        w.u8(self.xaa_magic);
+        w.u8(self.lxa_magic);
+        w.bool(self.undocumented);
+        w.u8(self.state as u8);
+        w.bool(self.page_crossed);
+        w.bool(self.nmi_pending);
+        w.bool(self.irq_line);
+        w.bool(self.irq_inhibit);
+        w.bool(self.poll_nmi);
+        w.bool(self.poll_irq);
+        // The opcode table entry is looked up again from the opcode
+        w.bool(self.micro.is_some());
+        if let Some(m) = self.micro {
+            let (event, opcode) = match m.event {
+                Event::Instruction(opcode) => (0, opcode),
+                Event::Nmi => (1, 0),
+                Event::Irq => (2, 0),
+                Event::Idle => (3, 0),
+            };
+            w.u8(event);
+            w.u8(opcode);
+            w.u16(m.pc);
+            w.u8(m.t);
+            w.u8(m.ready);
+            w.u16(m.addr);
+            w.u8(m.ptr);
+            w.u8(m.data);
+            w.bool(m.fixup);
+            w.bool(m.fixed);
+            w.u8(m.extra);
+        }
     }
-    
-    
-    fn sbc_imm(&mut self, mem: &Memory){
-        let addr = self.imm();
-        let m = self.read(mem, addr);
-        let m_inverse = m ^ 0xFF;
-        let c = self.p&C;
-        let diff = self.a as u16 + m_inverse as u16 + c as u16;
-        self.setc(if diff&0x100!=0{C}else{0});
-        let overflow= ((diff as u8)^m_inverse) & (self.a^diff as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = diff as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn sbc_zp(&mut self, mem: &Memory){
-        let addr = self.zp(mem);
-        let m = self.read(mem, addr);
-        let m_inverse = m ^ 0xFF;
-        let c = self.p&C;
-        let diff = self.a as u16 + m_inverse as u16 + c as u16;
-        self.setc(if diff&0x100!=0{C}else{0});
-        let overflow= ((diff as u8)^m_inverse) & (self.a^diff as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = diff as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn sbc_zpx(&mut self, mem: &Memory){
-        let addr = self.zpx(mem);
-        let m = self.read(mem, addr);
-        let m_inverse = m ^ 0xFF;
-        let c = self.p&C;
-        let diff = self.a as u16 + m_inverse as u16 + c as u16;
-        self.setc(if diff&0x100!=0{C}else{0});
-        let overflow= ((diff as u8)^m_inverse) & (self.a^diff as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = diff as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn sbc_abs(&mut self, mem: &Memory){
-        let addr = self.abs(mem);
-        let m = self.read(mem, addr);
-        let m_inverse = m ^ 0xFF;
-        let c = self.p&C;
-        let diff = self.a as u16 + m_inverse as u16 + c as u16;
-        self.setc(if diff&0x100!=0{C}else{0});
-        let overflow= ((diff as u8)^m_inverse) & (self.a^diff as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = diff as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn sbc_absx(&mut self, mem: &Memory){
-        let addr = self.absx(mem);
-        let m = self.read(mem, addr);
-        let m_inverse = m ^ 0xFF;
-        let c = self.p&C;
-        let diff = self.a as u16 + m_inverse as u16 + c as u16;
-        self.setc(if diff&0x100!=0{C}else{0});
-        let overflow= ((diff as u8)^m_inverse) & (self.a^diff as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = diff as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn sbc_absy(&mut self, mem: &Memory){
-        let addr = self.absy(mem);
-        let m = self.read(mem, addr);
-        let m_inverse = m ^ 0xFF;
-        let c = self.p&C;
-        let diff = self.a as u16 + m_inverse as u16 + c as u16;
-        self.setc(if diff&0x100!=0{C}else{0});
-        let overflow= ((diff as u8)^m_inverse) & (self.a^diff as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = diff as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn sbc_indx(&mut self, mem: &Memory){
-        let addr = self.indx(mem);
-        let m = self.read(mem, addr);
-        let m_inverse = m ^ 0xFF;
-        let c = self.p&C;
-        let diff = self.a as u16 + m_inverse as u16 + c as u16;
-        self.setc(if diff&0x100!=0{C}else{0});
-        let overflow= ((diff as u8)^m_inverse) & (self.a^diff as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = diff as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn sbc_indy(&mut self, mem: &Memory){
-        let addr = self.indy(mem);
-        let m = self.read(mem, addr);
-        let m_inverse = m ^ 0xFF;
-        let c = self.p&C;
-        let diff = self.a as u16 + m_inverse as u16 + c as u16;
-        self.setc(if diff&0x100!=0{C}else{0});
-        let overflow= ((diff as u8)^m_inverse) & (self.a^diff as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = diff as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    
-    fn cmp_imm(&mut self, mem: &Memory){
-        let addr = self.imm();
-        let m = self.read(mem, addr);
-        self.setc(if self.a >= m {C} else {0});
-        self.setz(if self.a == m {Z} else {0});
-        let diff = self.a.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    fn cmp_zp(&mut self, mem: &Memory){
-        let addr = self.zp(mem);
-        let m = self.read(mem, addr);
-        self.setc(if self.a >= m {C} else {0});
-        self.setz(if self.a == m {Z} else {0});
-        let diff = self.a.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    fn cmp_zpx(&mut self, mem: &Memory){
-        let addr = self.zpx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if self.a >= m {C} else {0});
-        self.setz(if self.a == m {Z} else {0});
-        let diff = self.a.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    fn cmp_abs(&mut self, mem: &Memory){
-        let addr = self.abs(mem);
-        let m = self.read(mem, addr);
-        self.setc(if self.a >= m {C} else {0});
-        self.setz(if self.a == m {Z} else {0});
-        let diff = self.a.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    fn cmp_absx(&mut self, mem: &Memory){
-        let addr = self.absx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if self.a >= m {C} else {0});
-        self.setz(if self.a == m {Z} else {0});
-        let diff = self.a.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    fn cmp_absy(&mut self, mem: &Memory){
-        let addr = self.absy(mem);
-        let m = self.read(mem, addr);
-        self.setc(if self.a >= m {C} else {0});
-        self.setz(if self.a == m {Z} else {0});
-        let diff = self.a.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    fn cmp_indx(&mut self, mem: &Memory){
-        let addr = self.indx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if self.a >= m {C} else {0});
-        self.setz(if self.a == m {Z} else {0});
-        let diff = self.a.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    fn cmp_indy(&mut self, mem: &Memory){
-        let addr = self.indy(mem);
-        let m = self.read(mem, addr);
-        self.setc(if self.a >= m {C} else {0});
-        self.setz(if self.a == m {Z} else {0});
-        let diff = self.a.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    
-    fn cpx_imm(&mut self, mem: &Memory){
-        let addr = self.imm();
-        let m = self.read(mem, addr);
-        self.setc(if self.x >= m {C} else {0});
-        self.setz(if self.x == m {Z} else {0});
-        let diff = self.x.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    fn cpx_zp(&mut self, mem: &Memory){
-        let addr = self.zp(mem);
-        let m = self.read(mem, addr);
-        self.setc(if self.x >= m {C} else {0});
-        self.setz(if self.x == m {Z} else {0});
-        let diff = self.x.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    fn cpx_abs(&mut self, mem: &Memory){
-        let addr = self.abs(mem);
-        let m = self.read(mem, addr);
-        self.setc(if self.x >= m {C} else {0});
-        self.setz(if self.x == m {Z} else {0});
-        let diff = self.x.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    
-    fn cpy_imm(&mut self, mem: &Memory){
-        let addr = self.imm();
-        let m = self.read(mem, addr);
-        self.setc(if self.y >= m {C} else {0});
-        self.setz(if self.y == m {Z} else {0});
-        let diff = self.y.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    fn cpy_zp(&mut self, mem: &Memory){
-        let addr = self.zp(mem);
-        let m = self.read(mem, addr);
-        self.setc(if self.y >= m {C} else {0});
-        self.setz(if self.y == m {Z} else {0});
-        let diff = self.y.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    fn cpy_abs(&mut self, mem: &Memory){
-        let addr = self.abs(mem);
-        let m = self.read(mem, addr);
-        self.setc(if self.y >= m {C} else {0});
-        self.setz(if self.y == m {Z} else {0});
-        let diff = self.y.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-
-    fn inc_zp(&mut self, mem: &mut Memory){
-        let addr = self.zp(mem);
-        let value = self.read(mem, addr).wrapping_add(1);
-        self.write(mem, addr, value);
-        self.setz(if value==0{Z}else{0});
-        self.setn(if value&N!=0{N}else{0});
-    }
-    fn inc_zpx(&mut self, mem: &mut Memory){
-        let addr = self.zpx(mem);
-        let value = self.read(mem, addr).wrapping_add(1);
-        self.write(mem, addr, value);
-        self.setz(if value==0{Z}else{0});
-        self.setn(if value&N!=0{N}else{0});
-    }
-    fn inc_abs(&mut self, mem: &mut Memory){
-        let addr = self.abs(mem);
-        let value = self.read(mem, addr).wrapping_add(1);
-        self.write(mem, addr, value);
-        self.setz(if value==0{Z}else{0});
-        self.setn(if value&N!=0{N}else{0});
-    }
-    fn inc_absx(&mut self, mem: &mut Memory){
-        let addr = self.absx_ro(mem);
-        let value = self.read(mem, addr).wrapping_add(1);
-        self.write(mem, addr, value);
-        self.setz(if value==0{Z}else{0});
-        self.setn(if value&N!=0{N}else{0});
-    }
-    
-    fn inx(&mut self){
-        self.x = self.x.wrapping_add(1);
-        self.setz(if self.x==0{Z}else{0});
-        self.setn(if self.x&N!=0{N}else{0});
-    }
-    fn iny(&mut self){
-        self.y = self.y.wrapping_add(1);
-        self.setz(if self.y==0{Z}else{0});
-        self.setn(if self.y&N!=0{N}else{0});
-    }
-
-    fn dec_zp(&mut self, mem: &mut Memory){
-        let addr = self.zp(mem);
-        let value = self.read(mem, addr).wrapping_sub(1);
-        self.write(mem, addr, value);
-        self.setz(if value==0{Z}else{0});
-        self.setn(if value&N!=0{N}else{0});
-    }
-    fn dec_zpx(&mut self, mem: &mut Memory){
-        let addr = self.zpx(mem);
-        let value = self.read(mem, addr).wrapping_sub(1);
-        self.write(mem, addr, value);
-        self.setz(if value==0{Z}else{0});
-        self.setn(if value&N!=0{N}else{0});
-    }
-    fn dec_abs(&mut self, mem: &mut Memory){
-        let addr = self.abs(mem);
-        let value = self.read(mem, addr).wrapping_sub(1);
-        self.write(mem, addr, value);
-        self.setz(if value==0{Z}else{0});
-        self.setn(if value&N!=0{N}else{0});
-    }
-    fn dec_absx(&mut self, mem: &mut Memory){
-        let addr = self.absx(mem);
-        let value = self.read(mem, addr).wrapping_sub(1);
-        self.write(mem, addr, value);
-        self.setz(if value==0{Z}else{0});
-        self.setn(if value&N!=0{N}else{0});
-    }
-    
-    fn dex(&mut self){
-        self.x = self.x.wrapping_sub(1);
-        self.setz(if self.x==0{Z}else{0});
-        self.setn(if self.x&N!=0{N}else{0});
-    }
-    fn dey(&mut self){
-        self.y = self.y.wrapping_sub(1);
-        self.setz(if self.y==0{Z}else{0});
-        self.setn(if self.y&N!=0{N}else{0});
-    }
-    
-    fn asl(&mut self){
-        self.setc(if self.a&0x80==0x80 {C} else {0});
-        self.a = self.a.wrapping_shl(1);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn asl_zp(&mut self, mem: &mut Memory){
-        let addr = self.zp(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m = m.wrapping_shl(1);
-        self.write(mem, addr, m);
-        self.setz(if m==0{Z}else{0});
-        self.setn(if m&N!=0{N}else{0});
-    }
-    fn asl_zpx(&mut self, mem: &mut Memory){
-        let addr = self.zpx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m = m.wrapping_shl(1);
-        self.write(mem, addr, m);
-        self.setz(if m==0{Z}else{0});
-        self.setn(if m&N!=0{N}else{0});
-    }
-    fn asl_abs(&mut self, mem: &mut Memory){
-        let addr = self.abs(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m = m.wrapping_shl(1);
-        self.write(mem, addr, m);
-        self.setz(if m==0{Z}else{0});
-        self.setn(if m&N!=0{N}else{0});
-    }
-    fn asl_absx(&mut self, mem: &mut Memory){
-        let addr = self.absx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m = m.wrapping_shl(1);
-        self.write(mem, addr, m);
-        self.setz(if m==0{Z}else{0});
-        self.setn(if m&N!=0{N}else{0});
-    }
-
-    fn lsr(&mut self){
-        self.setc(if self.a&C==C {C} else {0});
-        self.a = self.a.wrapping_shr(1);
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn lsr_zp(&mut self, mem: &mut Memory){
-        let addr = self.zp(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&C==C {C} else {0});
-        let m = m.wrapping_shr(1);
-        self.write(mem, addr, m);
-        self.setz(if m==0{Z}else{0});
-        self.setn(if m&N!=0{N}else{0});
-    }
-    fn lsr_zpx(&mut self, mem: &mut Memory){
-        let addr = self.zpx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&C==C {C} else {0});
-        let m = m.wrapping_shr(1);
-        self.write(mem, addr, m);
-        self.setz(if m==0{Z}else{0});
-        self.setn(if (m)&N!=0{N}else{0});
-    }
-    fn lsr_abs(&mut self, mem: &mut Memory){
-        let addr = self.abs(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&C==C {C} else {0});
-        let m = m.wrapping_shr(1);
-        self.write(mem, addr, m);
-        self.setz(if m==0{Z}else{0});
-        self.setn(if (m)&N!=0{N}else{0});
-    }
-    fn lsr_absx(&mut self, mem: &mut Memory){
-        let addr = self.absx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&C==C {C} else {0});
-        let m = m.wrapping_shr(1);
-        self.write(mem, addr, m);
-        self.setz(if m==0{Z}else{0});
-        self.setn(if m&N!=0{N}else{0});
-    }
-    
-    fn rol(&mut self){
-        let c = self.p&C;
-        self.setc(if self.a&0x80==0x80 {C} else {0});
-        self.a = self.a.wrapping_shl(1)|c;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn rol_zp(&mut self, mem: &mut Memory){
-        let c = self.p&C;
-        let addr = self.zp(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m1 = m.wrapping_shl(1)|c;
-        self.write(mem, addr, m1);
-        self.setz(if m1==0{Z}else{0});
-        self.setn(if m1&N!=0{N}else{0});
-    }
-    fn rol_zpx(&mut self, mem: &mut Memory){
-        let c = self.p&C;
-        let addr = self.zpx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m1 = m.wrapping_shl(1)|c;
-        self.write(mem, addr, m1);
-        self.setz(if m1==0{Z}else{0});
-        self.setn(if m1&N!=0{N}else{0});
-    }
-    fn rol_abs(&mut self, mem: &mut Memory){
-        let c = self.p&C;
-        let addr = self.abs(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m1 = m.wrapping_shl(1)|c;
-        self.write(mem, addr, m1);
-        self.setz(if m1==0{Z}else{0});
-        self.setn(if m1&N!=0{N}else{0});
-    }
-    fn rol_absx(&mut self, mem: &mut Memory){
-        let c = self.p&C;
-        let addr = self.absx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m1 = m.wrapping_shl(1)|c;
-        self.write(mem, addr, m1);
-        self.setz(if m1==0{Z}else{0});
-        self.setn(if m1&N!=0{N}else{0});
-    }
-
-    fn ror(&mut self){
-        let c = (self.p&C)<<7;
-        self.setc(if self.a&1==1 {C} else {0});
-        self.a = self.a.wrapping_shr(1)|c;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn ror_zp(&mut self, mem: &mut Memory){
-        let c = (self.p&C)<<7;
-        let addr = self.zp(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x1==1 {C} else {0});
-        let m1 = self.a.wrapping_shr(1)|c;
-        self.write(mem, addr, m1);
-        self.setz(if m1==0{Z}else{0});
-        self.setn(if m1&N!=0{N}else{0});
-    }
-    fn ror_zpx(&mut self, mem: &mut Memory){
-        let c = (self.p&C)<<7;
-        let addr = self.zpx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&C==C {C} else {0});
-        let m1 = self.a.wrapping_shr(1)|c;
-        self.write(mem, addr, m1);
-        self.setz(if m1==0{Z}else{0});
-        self.setn(if m1&N!=0{N}else{0});
-    }
-    fn ror_abs(&mut self, mem: &mut Memory){
-        let c = (self.p&C)<<7;
-        let addr = self.abs(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&C==C {C} else {0});
-        let m1 = self.a.wrapping_shr(1)|c;
-        self.write(mem, addr, m1);
-        self.setz(if m1==0{Z}else{0});
-        self.setn(if m1&N!=0{N}else{0});
-    }
-    fn ror_absx(&mut self, mem: &mut Memory){
-        let c = (self.p&C)<<7;
-        let addr = self.absx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&C==C {C} else {0});
-        let m1 = self.a.wrapping_shr(1)|c;
-        self.write(mem, addr, m1);
-        self.setz(if m1==0{Z}else{0});
-        self.setn(if m1&N!=0{N}else{0});
-    }
-
-    fn jmp_abs(&mut self, mem: &Memory){
-        self.pc = self.abs(mem);
-    }
-    fn jmp_ind(&mut self, mem: &Memory){
-        self.pc = self.ind(mem);
-    }
-
-    fn jsr(&mut self, mem: &mut Memory){
-        let addr = self.abs(mem);
-        let bytes = (self.pc-1).to_be_bytes();
-        self.push(mem, bytes[0]);
-        self.push(mem, bytes[1]);
-        self.pc = addr;
-    }
-    
-    fn rts(&mut self, mem: &Memory){
-        let lo = self.pull(mem) as u16;
-        let hi = self.pull(mem) as u16;
-        self.pc = hi <<8 | lo;
-        self.pc = self.pc.wrapping_add(1);
-    }
- 
-    fn bcs(&mut self, mem: &Memory){
-        let old_pc = self.pc + 2;
-        let pc = self.rel(mem);
-        if self.p&C==C{
-            self.pc  = pc;
-            self.cycles += 1;
-            if (old_pc & 0xFF00) != (pc & 0xFF00) {
-                self.cycles += 1;
-                self.page_crossed = true;
-            }
-        }
-    }
-    fn bcc(&mut self, mem: &Memory){
-        let old_pc = self.pc + 2;
-        let pc = self.rel(mem);
-        if self.p&C!=C{
-            self.pc  = pc;
-            self.cycles += 1;
-            if (old_pc & 0xFF00) != (pc & 0xFF00) {
-                self.cycles += 1;
-                self.page_crossed = true;
-            }
-        }
-    }
-    fn beq(&mut self, mem: &Memory){
-        let old_pc = self.pc + 2;
-        let pc = self.rel(mem);
-        if self.p&Z==Z{
-            self.pc  = pc;
-            self.cycles += 1;
-            if (old_pc & 0xFF00) != (pc & 0xFF00) {
-                self.cycles += 1;
-                self.page_crossed = true;
-            }
-        }
-    }
-    fn bne(&mut self, mem: &Memory){
-        let old_pc = self.pc + 2;
-        let pc = self.rel(mem);
-        if self.p&Z!=Z{
-            self.pc  = pc;
-            self.cycles += 1;
-            if (old_pc & 0xFF00) != (pc & 0xFF00) {
-                self.cycles += 1;
-                self.page_crossed = true;
-            }
-        }
-    }
-    fn bmi(&mut self, mem: &Memory){
-        let old_pc = self.pc + 2;
-        let pc = self.rel(mem);
-        if self.p&N==N{
-            self.pc  = pc;
-            self.cycles += 1;
-            if (old_pc & 0xFF00) != (pc & 0xFF00) {
-                self.cycles += 1;
-                self.page_crossed = true;
-            }
-        }
-    }
-    fn bpl(&mut self, mem: &Memory){
-        let old_pc = self.pc + 2;
-        let pc = self.rel(mem);
-        if self.p&N!=N{
-            self.pc  = pc;
-            self.cycles += 1;
-            if (old_pc & 0xFF00) != (pc & 0xFF00) {
-                self.cycles += 1;
-                self.page_crossed = true;
-            }
-        }
-    }
-    fn bvs(&mut self, mem: &Memory){
-        let old_pc = self.pc + 2;
-        let pc = self.rel(mem);
-        if self.p&V==V{
-            self.pc  = pc;
-            self.cycles += 1;
-            if (old_pc & 0xFF00) != (pc & 0xFF00) {
-                self.cycles += 1;
-                self.page_crossed = true;
-            }
-        }
-    }
-    fn bvc(&mut self, mem: &Memory){
-        let old_pc = self.pc + 2;
-        let pc = self.rel(mem);
-        if self.p&V!=V{
-            self.pc  = pc;
-            self.cycles += 1;
-            if (old_pc & 0xFF00) != (pc & 0xFF00) {
-                self.cycles += 1;
-                self.page_crossed = true;
-            }
-        }
-    }
-    
-    fn clc(&mut self){
-        self.p = self.p&!C;
-    }
-    fn cld(&mut self){
-        self.p = self.p&!D;
-    }
-    fn cli(&mut self){
-        self.p = self.p&!I;
-    }
-    fn clv(&mut self){
-        self.p = self.p&!V;
-    }
-    
-    fn sec(&mut self){
-        self.p = self.p|C;
-    }
-    fn sed(&mut self){
-        self.p = self.p|D;
-    }
-    fn sei(&mut self){
-        self.p = self.p|I;
-    }
-    
-    fn brk(&mut self, mem: &mut Memory){
-        self.pc = self.pc.wrapping_add(1);
-        let bytes = self.pc.to_le_bytes();
-        self.push(mem, bytes[0]);
-        self.push(mem, bytes[1]);
-        self.push(mem, self.p|B|U);
-        self.p |= I;
-    }
-    
-    fn rti(&mut self, mem: &Memory) {
-        self.p = (self.pull(mem) & !B) | U;
-        let lo = self.pull(mem) as u16;
-        let hi = self.pull(mem) as u16;
-        self.pc = (hi << 8) | lo
-    }
-    
-    fn lax_indx(&mut self, mem: &Memory){
-        let addr = self.indx(mem);
-        self.a = self.read(mem, addr);
-        self.x = self.a;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn lax_indy(&mut self, mem: &Memory){
-        let addr = self.indy(mem);
-        self.a = self.read(mem, addr);
-        self.x = self.a;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn lax_zp(&mut self, mem: &Memory){
-        let addr = self.zp(mem);
-        self.a = self.read(mem, addr);
-        self.x = self.a;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn lax_zpy(&mut self, mem: &Memory){
-        let addr = self.zpy(mem);
-        self.a = self.read(mem, addr);
-        self.x = self.a;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn lax_abs(&mut self, mem: &Memory){
-        let addr = self.abs(mem);
-        self.a = self.read(mem, addr);
-        self.x = self.a;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn lax_absy(&mut self, mem: &Memory){
-        let addr = self.absy(mem);
-        self.a = self.read(mem, addr);
-        self.x = self.a;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-
-    fn sax_indx(&mut self, mem: &mut Memory){
-        let addr = self.indx(mem);
-        self.write(mem, addr, self.a&self.x);
-    }
-    fn sax_zp(&mut self, mem: &mut Memory){
-        let addr = self.zp(mem);
-        self.write(mem, addr, self.a&self.x);
-    }
-    fn sax_zpy(&mut self, mem: &mut Memory){
-        let addr = self.zpy(mem);
-        self.write(mem, addr, self.a&self.x);
-    }
-    fn sax_abs(&mut self, mem: &mut Memory){
-        let addr = self.abs(mem);
-        self.write(mem, addr, self.a&self.x);
-    }
-
-    fn dcp_zp(&mut self, mem: &mut Memory){
-        let addr = self.zp(mem);
-        let m = self.read(mem, addr).wrapping_sub(1);
-        self.write(mem, addr, m);
-        self.setc(if self.a >= m {C} else {0});
-        self.setz(if self.a == m {Z} else {0});
-        let diff = self.a.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    fn dcp_zpx(&mut self, mem: &mut Memory){
-        let addr = self.zpx(mem);
-        let m = self.read(mem, addr).wrapping_sub(1);
-        self.write(mem, addr, m);
-        self.setc(if self.a >= m {C} else {0});
-        self.setz(if self.a == m {Z} else {0});
-        let diff = self.a.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    fn dcp_abs(&mut self, mem: &mut Memory){
-        let addr = self.abs(mem);
-        let m = self.read(mem, addr).wrapping_sub(1);
-        self.write(mem, addr, m);
-        self.setc(if self.a >= m {C} else {0});
-        self.setz(if self.a == m {Z} else {0});
-        let diff = self.a.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    fn dcp_absx(&mut self, mem: &mut Memory){
-        let addr = self.absx_ro(mem);
-        let m = self.read(mem, addr).wrapping_sub(1);
-        self.write(mem, addr, m);
-        self.setc(if self.a >= m {C} else {0});
-        self.setz(if self.a == m {Z} else {0});
-        let diff = self.a.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    fn dcp_absy(&mut self, mem: &mut Memory){
-        let addr = self.absy_ro(mem);
-        let m = self.read(mem, addr).wrapping_sub(1);
-        self.write(mem, addr, m);
-        self.setc(if self.a >= m {C} else {0});
-        self.setz(if self.a == m {Z} else {0});
-        let diff = self.a.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    fn dcp_indx(&mut self, mem: &mut Memory){
-        let addr = self.indx(mem);
-        let m = self.read(mem, addr).wrapping_sub(1);
-        self.write(mem, addr, m);
-        self.setc(if self.a >= m {C} else {0});
-        self.setz(if self.a == m {Z} else {0});
-        let diff = self.a.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-    fn dcp_indy(&mut self, mem: &mut Memory){
-        let addr = self.indy_ro(mem);
-        let m = self.read(mem, addr).wrapping_sub(1);
-        self.write(mem, addr, m);
-        self.setc(if self.a >= m {C} else {0});
-        self.setz(if self.a == m {Z} else {0});
-        let diff = self.a.wrapping_sub(m);
-        self.setn(if diff&N!=0{N}else{0});
-    }
-
-    fn isc_zp(&mut self, mem: &mut Memory){
-        let addr = self.zp(mem);
-        let m = self.read(mem, addr).wrapping_add(1);
-        self.write(mem, addr, m);
-        let m_inverse = m ^ 0xFF;
-        let c = self.p&C;
-        let diff = self.a as u16 + m_inverse as u16 + c as u16;
-        self.setc(if diff&0x100!=0{C}else{0});
-        let overflow= ((diff as u8)^m_inverse) & (self.a^diff as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = diff as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn isc_zpx(&mut self, mem: &mut Memory){
-        let addr = self.zpx(mem);
-        let m = self.read(mem, addr).wrapping_add(1);
-        self.write(mem, addr, m);
-        let m_inverse = m ^ 0xFF;
-        let c = self.p&C;
-        let diff = self.a as u16 + m_inverse as u16 + c as u16;
-        self.setc(if diff&0x100!=0{C}else{0});
-        let overflow= ((diff as u8)^m_inverse) & (self.a^diff as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = diff as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn isc_abs(&mut self, mem: &mut Memory){
-        let addr = self.abs(mem);
-        let m = self.read(mem, addr).wrapping_add(1);
-        self.write(mem, addr, m);
-        let m_inverse = m ^ 0xFF;
-        let c = self.p&C;
-        let diff = self.a as u16 + m_inverse as u16 + c as u16;
-        self.setc(if diff&0x100!=0{C}else{0});
-        let overflow= ((diff as u8)^m_inverse) & (self.a^diff as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = diff as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn isc_absx(&mut self, mem: &mut Memory){
-        let addr = self.absx_ro(mem);
-        let m = self.read(mem, addr).wrapping_add(1);
-        self.write(mem, addr, m);
-        let m_inverse = m ^ 0xFF;
-        let c = self.p&C;
-        let diff = self.a as u16 + m_inverse as u16 + c as u16;
-        self.setc(if diff&0x100!=0{C}else{0});
-        let overflow= ((diff as u8)^m_inverse) & (self.a^diff as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = diff as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn isc_absy(&mut self, mem: &mut Memory){
-        let addr = self.absy_ro(mem);
-        let m = self.read(mem, addr).wrapping_add(1);
-        self.write(mem, addr, m);
-        let m_inverse = m ^ 0xFF;
-        let c = self.p&C;
-        let diff = self.a as u16 + m_inverse as u16 + c as u16;
-        self.setc(if diff&0x100!=0{C}else{0});
-        let overflow= ((diff as u8)^m_inverse) & (self.a^diff as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = diff as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn isc_indx(&mut self, mem: &mut Memory){
-        let addr = self.indx(mem);
-        let m = self.read(mem, addr).wrapping_add(1);
-        self.write(mem, addr, m);
-        let m_inverse = m ^ 0xFF;
-        let c = self.p&C;
-        let diff = self.a as u16 + m_inverse as u16 + c as u16;
-        self.setc(if diff&0x100!=0{C}else{0});
-        let overflow= ((diff as u8)^m_inverse) & (self.a^diff as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = diff as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn isc_indy(&mut self, mem: &mut Memory){
-        let addr = self.indy_ro(mem);
-        let m = self.read(mem, addr).wrapping_add(1);
-        self.write(mem, addr, m);
-        let m_inverse = m ^ 0xFF;
-        let c = self.p&C;
-        let diff = self.a as u16 + m_inverse as u16 + c as u16;
-        self.setc(if diff&0x100!=0{C}else{0});
-        let overflow= ((diff as u8)^m_inverse) & (self.a^diff as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = diff as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-
-    fn slo_zp(&mut self, mem: &mut Memory){
-        let addr = self.zp(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m = m.wrapping_shl(1);
-        self.write(mem, addr, m);
-        self.a |= m;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn slo_zpx(&mut self, mem: &mut Memory){
-        let addr = self.zpx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m = m.wrapping_shl(1);
-        self.write(mem, addr, m);
-        self.a |= m;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn slo_abs(&mut self, mem: &mut Memory){
-        let addr = self.abs(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m = m.wrapping_shl(1);
-        self.write(mem, addr, m);
-        self.a |= m;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn slo_absx(&mut self, mem: &mut Memory){
-        let addr = self.absx_ro(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m = m.wrapping_shl(1);
-        self.write(mem, addr, m);
-        self.a |= m;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn slo_absy(&mut self, mem: &mut Memory){
-        let addr = self.absy_ro(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m = m.wrapping_shl(1);
-        self.write(mem, addr, m);
-        self.a |= m;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn slo_indx(&mut self, mem: &mut Memory){
-        let addr = self.indx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m = m.wrapping_shl(1);
-        self.write(mem, addr, m);
-        self.a |= m;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn slo_indy(&mut self, mem: &mut Memory){
-        let addr = self.indy_ro(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m = m.wrapping_shl(1);
-        self.write(mem, addr, m);
-        self.a |= m;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-
-    fn rla_zp(&mut self, mem: &mut Memory){
-        let c = self.p&C;
-        let addr = self.zp(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m1 = m.wrapping_shl(1)|c;
-        self.write(mem, addr, m1);
-        self.a &= m1;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn rla_zpx(&mut self, mem: &mut Memory){
-        let c = self.p&C;
-        let addr = self.zpx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m1 = m.wrapping_shl(1)|c;
-        self.write(mem, addr, m1);
-        self.a &= m1;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn rla_abs(&mut self, mem: &mut Memory){
-        let c = self.p&C;
-        let addr = self.abs(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m1 = m.wrapping_shl(1)|c;
-        self.write(mem, addr, m1);
-        self.a &= m1;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn rla_absx(&mut self, mem: &mut Memory){
-        let c = self.p&C;
-        let addr = self.absx_ro(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m1 = m.wrapping_shl(1)|c;
-        self.write(mem, addr, m1);
-        self.a &= m1;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn rla_absy(&mut self, mem: &mut Memory){
-        let c = self.p&C;
-        let addr = self.absy_ro(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m1 = m.wrapping_shl(1)|c;
-        self.write(mem, addr, m1);
-        self.a &= m1;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn rla_indx(&mut self, mem: &mut Memory){
-        let c = self.p&C;
-        let addr = self.indx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m1 = m.wrapping_shl(1)|c;
-        self.write(mem, addr, m1);
-        self.a &= m1;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn rla_indy(&mut self, mem: &mut Memory){
-        let c = self.p&C;
-        let addr = self.indy_ro(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x80==0x80 {C} else {0});
-        let m1 = m.wrapping_shl(1)|c;
-        self.write(mem, addr, m1);
-        self.a &= m1;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    
-    fn sre_zp(&mut self, mem: &mut Memory){
-        let addr = self.zp(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&C==C {C} else {0});
-        let m = m.wrapping_shr(1);
-        self.write(mem, addr, m);
-        self.a ^= m;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn sre_zpx(&mut self, mem: &mut Memory){
-        let addr = self.zpx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&C==C {C} else {0});
-        let m = m.wrapping_shr(1);
-        self.write(mem, addr, m);
-        self.a ^= m;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn sre_abs(&mut self, mem: &mut Memory){
-        let addr = self.abs(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&C==C {C} else {0});
-        let m = m.wrapping_shr(1);
-        self.write(mem, addr, m);
-        self.a ^= m;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn sre_absx(&mut self, mem: &mut Memory){
-        let addr = self.absx_ro(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&C==C {C} else {0});
-        let m = m.wrapping_shr(1);
-        self.write(mem, addr, m);
-        self.a ^= m;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn sre_absy(&mut self, mem: &mut Memory){
-        let addr = self.absy_ro(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&C==C {C} else {0});
-        let m = m.wrapping_shr(1);
-        self.write(mem, addr, m);
-        self.a ^= m;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn sre_indx(&mut self, mem: &mut Memory){
-        let addr = self.indx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&C==C {C} else {0});
-        let m = m.wrapping_shr(1);
-        self.write(mem, addr, m);
-        self.a ^= m;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn sre_indy(&mut self, mem: &mut Memory){
-        let addr = self.indy_ro(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&C==C {C} else {0});
-        let m = m.wrapping_shr(1);
-        self.write(mem, addr, m);
-        self.a ^= m;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-
-    fn rra_zp(&mut self, mem: &mut Memory){
-        let c = (self.p&C)<<7; // old c
-        let addr = self.zp(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x1==1 {C} else {0});
-        let m = m.wrapping_shr(1)|c;
-        self.write(mem, addr, m);
-        let c = self.p&C; // new c set by ror
-        let sum = self.a as u16 + m as u16 + c as u16;
-        self.setc(if sum&0x100!=0{C}else{0});
-        let overflow= !(self.a^m) & (self.a^sum as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = sum as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn rra_zpx(&mut self, mem: &mut Memory){
-        let c = (self.p&C)<<7; // old c
-        let addr = self.zpx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x1==1 {C} else {0});
-        let m = m.wrapping_shr(1)|c;
-        self.write(mem, addr, m);
-        let c = self.p&C; // new c set by ror
-        let sum = self.a as u16 + m as u16 + c as u16;
-        self.setc(if sum&0x100!=0{C}else{0});
-        let overflow= !(self.a^m) & (self.a^sum as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = sum as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn rra_abs(&mut self, mem: &mut Memory){
-        let c = (self.p&C)<<7; // old c
-        let addr = self.abs(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x1==1 {C} else {0});
-        let m = m.wrapping_shr(1)|c;
-        self.write(mem, addr, m);
-        let c = self.p&C; // new c set by ror
-        let sum = self.a as u16 + m as u16 + c as u16;
-        self.setc(if sum&0x100!=0{C}else{0});
-        let overflow= !(self.a^m) & (self.a^sum as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = sum as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn rra_absx(&mut self, mem: &mut Memory){
-        let c = (self.p&C)<<7; // old c
-        let addr = self.absx_ro(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x1==1 {C} else {0});
-        let m = m.wrapping_shr(1)|c;
-        self.write(mem, addr, m);
-        let c = self.p&C; // new c set by ror
-        let sum = self.a as u16 + m as u16 + c as u16;
-        self.setc(if sum&0x100!=0{C}else{0});
-        let overflow= !(self.a^m) & (self.a^sum as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = sum as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn rra_absy(&mut self, mem: &mut Memory){
-        let c = (self.p&C)<<7; // old c
-        let addr = self.absy_ro(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x1==1 {C} else {0});
-        let m = m.wrapping_shr(1)|c;
-        self.write(mem, addr, m);
-        let c = self.p&C; // new c set by ror
-        let sum = self.a as u16 + m as u16 + c as u16;
-        self.setc(if sum&0x100!=0{C}else{0});
-        let overflow= !(self.a^m) & (self.a^sum as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = sum as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn rra_indx(&mut self, mem: &mut Memory){
-        let c = (self.p&C)<<7; // old c
-        let addr = self.indx(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x1==1 {C} else {0});
-        let m = m.wrapping_shr(1)|c;
-        self.write(mem, addr, m);
-        let c = self.p&C; // new c set by ror
-        let sum = self.a as u16 + m as u16 + c as u16;
-        self.setc(if sum&0x100!=0{C}else{0});
-        let overflow= !(self.a^m) & (self.a^sum as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = sum as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }
-    fn rra_indy(&mut self, mem: &mut Memory){
-        let c = (self.p&C)<<7; // old c
-        let addr = self.indy_ro(mem);
-        let m = self.read(mem, addr);
-        self.setc(if m&0x1==1 {C} else {0});
-        let m = m.wrapping_shr(1)|c;
-        self.write(mem, addr, m);
-        let c = self.p&C; // new c set by ror
-        let sum = self.a as u16 + m as u16 + c as u16;
-        self.setc(if sum&0x100!=0{C}else{0});
-        let overflow= !(self.a^m) & (self.a^sum as u8) & N !=0;
-        self.setv(if overflow{V}else{0});
-        self.a = sum as u8;
-        self.setz(if self.a==0{Z}else{0});
-        self.setn(if self.a&N!=0{N}else{0});
-    }    
-
-    pub fn step(&mut self, mem: &mut Memory){
-        let opcode = mem.read(self.pc);
-        self.page_crossed = false;
-        self.cycles = BASE_CYCLES[opcode as usize] as u32;
-        self.pc = self.pc.wrapping_add(1);
-        match opcode {
-            LDA_IMM => self.lda_imm(mem),
-            LDA_ZP => self.lda_zp(mem),
-            LDA_ZPX => self.lda_zpx(mem),
-            LDA_ABS => self.lda_abs(mem),
-            LDA_ABSX => self.lda_absx(mem),
-            LDA_ABSY => self.lda_absy(mem),
-            LDA_INDX => self.lda_indx(mem),
-            LDA_INDY => self.lda_indy(mem),
-            
-            LDX_IMM => self.ldx_imm(mem),
-            LDX_ZP => self.ldx_zp(mem),
-            LDX_ZPY => self.ldx_zpy(mem),
-            LDX_ABS => self.ldx_abs(mem),
-            LDX_ABSY => self.ldx_absy(mem),
-            
-            LDY_IMM => self.ldy_imm(mem),
-            LDY_ZP => self.ldy_zp(mem),
-            LDY_ZPX => self.ldy_zpx(mem),
-            LDY_ABS => self.ldy_abs(mem),
-            LDY_ABSX => self.ldy_absx(mem),
-            
-            STA_ZP => self.sta_zp(mem),
-            STA_ZPX => self.sta_zpx(mem),
-            STA_ABS => self.sta_abs(mem),
-            STA_ABSX => self.sta_absx(mem),
-            STA_ABSY => self.sta_absy(mem),
-            STA_INDX => self.sta_indx(mem),
-            STA_INDY => self.sta_indy(mem),
-            
-            STX_ZP => self.stx_zp(mem),
-            STX_ZPY => self.stx_zpy(mem),
-            STX_ABS => self.stx_abs(mem),
-
-            STY_ZP => self.sty_zp(mem),
-            STY_ZPX => self.sty_zpx(mem),
-            STY_ABS => self.sty_abs(mem),
-            
-            TAX => self.tax(),
-            TXA => self.txa(),
-            TAY => self.tay(),
-            TYA => self.tya(),
-            TSX => self.tsx(),
-            TXS => self.txs(),
-            
-            PHA => self.pha(mem),
-            PHP => self.php(mem),
-            PLA => self.pla(mem),
-            PLP => self.plp(mem),
-
-            ORA_IMM => self.ora_imm(mem),
-            ORA_ZP => self.ora_zp(mem),
-            ORA_ZPX => self.ora_zpx(mem),
-            ORA_ABS => self.ora_abs(mem),
-            ORA_ABSX => self.ora_absx(mem),
-            ORA_ABSY => self.ora_absy(mem),
-            ORA_INDX => self.ora_indx(mem),
-            ORA_INDY => self.ora_indy(mem),
-
-            AND_IMM => self.and_imm(mem),
-            AND_ZP => self.and_zp(mem),
-            AND_ZPX => self.and_zpx(mem),
-            AND_ABS => self.and_abs(mem),
-            AND_ABSX => self.and_absx(mem),
-            AND_ABSY => self.and_absy(mem),
-            AND_INDX => self.and_indx(mem),
-            AND_INDY => self.and_indy(mem),
-
-            EOR_IMM => self.eor_imm(mem),
-            EOR_ZP => self.eor_zp(mem),
-            EOR_ZPX => self.eor_zpx(mem),
-            EOR_ABS => self.eor_abs(mem),
-            EOR_ABSX => self.eor_absx(mem),
-            EOR_ABSY => self.eor_absy(mem),
-            EOR_INDX => self.eor_indx(mem),
-            EOR_INDY => self.eor_indy(mem),
-            
-            BIT_ZP => self.bit_zp(mem),
-            BIT_ABS => self.bit_abs(mem),
-            
-            ADC_IMM => self.adc_imm(mem),
-            ADC_ZP => self.adc_zp(mem),
-            ADC_ZPX => self.adc_zpx(mem),
-            ADC_ABS => self.adc_abs(mem),
-            ADC_ABSX => self.adc_absx(mem),
-            ADC_ABSY => self.adc_absy(mem),
-            ADC_INDX => self.adc_indx(mem),
-            ADC_INDY => self.adc_indy(mem),
-
-            SBC_IMM | 0xEB => self.sbc_imm(mem),
-            SBC_ZP => self.sbc_zp(mem),
-            SBC_ZPX => self.sbc_zpx(mem),
-            SBC_ABS => self.sbc_abs(mem),
-            SBC_ABSX => self.sbc_absx(mem),
-            SBC_ABSY => self.sbc_absy(mem),
-            SBC_INDX => self.sbc_indx(mem),
-            SBC_INDY => self.sbc_indy(mem),
-
-            CMP_IMM => self.cmp_imm(mem),
-            CMP_ZP => self.cmp_zp(mem),
-            CMP_ZPX => self.cmp_zpx(mem),
-            CMP_ABS => self.cmp_abs(mem),
-            CMP_ABSX => self.cmp_absx(mem),
-            CMP_ABSY => self.cmp_absy(mem),
-            CMP_INDX => self.cmp_indx(mem),
-            CMP_INDY => self.cmp_indy(mem),
-            
-            CPX_IMM => self.cpx_imm(mem),
-            CPX_ZP => self.cpx_zp(mem),
-            CPX_ABS => self.cpx_abs(mem),
-
-            CPY_IMM => self.cpy_imm(mem),
-            CPY_ZP => self.cpy_zp(mem),
-            CPY_ABS => self.cpy_abs(mem),
-            
-            INC_ZP => self.inc_zp(mem),
-            INC_ZPX => self.inc_zpx(mem),
-            INC_ABS => self.inc_abs(mem),
-            INC_ABSX => self.inc_absx(mem),
-            INX => self.inx(),
-            INY => self.iny(),
-
-            DEC_ZP => self.dec_zp(mem),
-            DEC_ZPX => self.dec_zpx(mem),
-            DEC_ABS => self.dec_abs(mem),
-            DEC_ABSX => self.dec_absx(mem),
-            DEX => self.dex(),
-            DEY => self.dey(),
-            
-            ASL => self.asl(),
-            ASL_ZP => self.asl_zp(mem),
-            ASL_ZPX => self.asl_zpx(mem),
-            ASL_ABS => self.asl_abs(mem),
-            ASL_ABSX => self.asl_absx(mem),
-
-            LSR => self.lsr(),
-            LSR_ZP => self.lsr_zp(mem),
-            LSR_ZPX => self.lsr_zpx(mem),
-            LSR_ABS => self.lsr_abs(mem),
-            LSR_ABSX => self.lsr_absx(mem),
-
-            ROL => self.rol(),
-            ROL_ZP => self.rol_zp(mem),
-            ROL_ZPX => self.rol_zpx(mem),
-            ROL_ABS => self.rol_abs(mem),
-            ROL_ABSX => self.rol_absx(mem),
-
-            ROR => self.ror(),
-            ROR_ZP => self.ror_zp(mem),
-            ROR_ZPX => self.ror_zpx(mem),
-            ROR_ABS => self.ror_abs(mem),
-            ROR_ABSX => self.ror_absx(mem),
-            
-            JSR => self.jsr(mem),
-            RTS => self.rts(mem),
-            JMP_ABS => self.jmp_abs(mem),
-            JMP_IND => self.jmp_ind(mem),
-            
-            BPL => self.bpl(mem),
-            BMI => self.bmi(mem),
-            BEQ => self.beq(mem),
-            BNE => self.bne(mem),
-            BCC => self.bcc(mem),
-            BCS => self.bcs(mem),
-            BVC => self.bvc(mem),
-            BVS => self.bvs(mem),
-            
-            CLC => self.clc(),
-            CLD => self.cld(),
-            CLI => self.cli(),
-            CLV => self.clv(),
-            
-            SEC => self.sec(),
-            SED => self.sed(),
-            SEI => self.sei(),
-            
-            BRK => self.brk(mem),
-            RTI => self.rti(mem),
-            NOP => (),
-            
-            // Illegal opcodes:
-            0x04 | 0x44 | 0x64 => {self.zp(mem);}, // NOP ZP
-            0x0C => {self.abs(mem);}, //NOP ABS
-            0x14 | 0x34 | 0x54 | 0x74 | 0xD4 | 0xF4 => {self.zpx(mem);}, //NOP ZPX
-            0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC => {self.absx(mem);}, //NOP ABSX
-            0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => (), // NOP ACC
-            0x80 => {self.imm();}, //NOP IMM
-            
-            LAX_ZP => self.lax_zp(mem),
-            LAX_ZPY => self.lax_zpy(mem),
-            LAX_ABS => self.lax_abs(mem),
-            LAX_ABSY => self.lax_absy(mem),
-            LAX_INDX => self.lax_indx(mem),
-            LAX_INDY => self.lax_indy(mem),
-
-            SAX_ZP => self.sax_zp(mem),
-            SAX_ZPY => self.sax_zpy(mem),
-            SAX_ABS => self.sax_abs(mem),
-            SAX_INDX => self.sax_indx(mem),
-            
-            DCP_ZP => self.dcp_zp(mem),
-            DCP_ZPX => self.dcp_zpx(mem),
-            DCP_ABS => self.dcp_abs(mem),
-            DCP_ABSX => self.dcp_absx(mem),
-            DCP_ABSY => self.dcp_absy(mem),
-            DCP_INDX => self.dcp_indx(mem),
-            DCP_INDY => self.dcp_indy(mem),
-            
-            ISC_ZP => self.isc_zp(mem),
-            ISC_ZPX => self.isc_zpx(mem),
-            ISC_ABS => self.isc_abs(mem),
-            ISC_ABSX => self.isc_absx(mem),
-            ISC_ABSY => self.isc_absy(mem),
-            ISC_INDX => self.isc_indx(mem),
-            ISC_INDY => self.isc_indy(mem),
-            
-            SLO_ZP => self.slo_zp(mem),
-            SLO_ZPX => self.slo_zpx(mem),
-            SLO_ABS => self.slo_abs(mem),
-            SLO_ABSX => self.slo_absx(mem),
-            SLO_ABSY => self.slo_absy(mem),
-            SLO_INDX => self.slo_indx(mem),
-            SLO_INDY => self.slo_indy(mem),
-            
-            RLA_ZP => self.rla_zp(mem),
-            RLA_ZPX => self.rla_zpx(mem),
-            RLA_ABS => self.rla_abs(mem),
-            RLA_ABSX => self.rla_absx(mem),
-            RLA_ABSY => self.rla_absy(mem),
-            RLA_INDX => self.rla_indx(mem),
-            RLA_INDY => self.rla_indy(mem),
-
-            SRE_ZP => self.sre_zp(mem),
-            SRE_ZPX => self.sre_zpx(mem),
-            SRE_ABS => self.sre_abs(mem),
-            SRE_ABSX => self.sre_absx(mem),
-            SRE_ABSY => self.sre_absy(mem),
-            SRE_INDX => self.sre_indx(mem),
-            SRE_INDY => self.sre_indy(mem),
-            
-            RRA_ZP => self.rra_zp(mem),
-            RRA_ZPX => self.rra_zpx(mem),
-            RRA_ABS => self.rra_abs(mem),
-            RRA_ABSX => self.rra_absx(mem),
-            RRA_ABSY => self.rra_absy(mem),
-            RRA_INDX => self.rra_indx(mem),
-            RRA_INDY => self.rra_indy(mem),
-            _ => {panic!("Unknown opcode {:02X}", opcode)}
-        }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>{
+        self.a = r.u8()?;
+        self.x = r.u8()?;
+        self.y = r.u8()?;
+        self.s = r.u8()?;
+        self.pc = r.u16()?;
+        self.p = r.u8()?;
+        self.cycles = r.u32()?;
+        self.variant = match r.u8()? {
+            0 => Variant::Nmos,
+            1 => Variant::Nes,
+            2 => Variant::Cmos,
+            _ => return Err(StateError::Invalid("CPU variant")),
+        };
+        self.xaa_magic = r.u8()?;
+        self.lxa_magic = r.u8()?;
+        self.undocumented = r.bool()?;
+        self.state = match r.u8()? {
+            0 => RunState::Running,
+            1 => RunState::Waiting,
+            2 => RunState::Stopped,
+            3 => RunState::Jammed,
+            _ => return Err(StateError::Invalid("CPU run state")),
+        };
+        self.page_crossed = r.bool()?;
+        self.nmi_pending = r.bool()?;
+        self.irq_line = r.bool()?;
+        self.irq_inhibit = r.bool()?;
+        self.poll_nmi = r.bool()?;
+        self.poll_irq = r.bool()?;
+        self.micro = None;
+        if r.bool()? {
+            let kind = r.u8()?;
+            let opcode = r.u8()?;
+            let event = match kind {
+                0 => Event::Instruction(opcode),
+                1 => Event::Nmi,
+                2 => Event::Irq,
+                3 => Event::Idle,
+                _ => return Err(StateError::Invalid("CPU event")),
+            };
+            self.micro = Some(Micro{
+                op: opcodes(self.variant)[opcode as usize],
+                event,
+                pc: r.u16()?,
+                t: r.u8()?,
+                ready: r.u8()?,
+                addr: r.u16()?,
+                ptr: r.u8()?,
+                data: r.u8()?,
+                fixup: r.bool()?,
+                fixed: r.bool()?,
+                extra: r.u8()?,
+            });
+        }
+        Ok(())
     }
 }
-
-impl Display for Processor{
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:X}        A: {:X} X: {:X} Y: {:X} P: {:X} SP: {:X}", self.pc, self.a, self.x, self.y, self.p, self.s)
-    }
-}
\ No newline at end of file
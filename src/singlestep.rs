@@ -0,0 +1,191 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use emulator_6502::bus::Bus;
+use emulator_6502::json::{self, Json};
+use emulator_6502::memory::Memory;
+use emulator_6502::op::{Mnemonic, opcodes};
+use emulator_6502::processor::{Processor, Variant};
+
+// Cases listed for each failing opcode
+const SHOWN_FAILURES: usize = 3;
+
+struct State{
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cycle{
+    addr: u16,
+    value: u8,
+    write: bool,
+}
+
+struct Case{
+    name: String,
+    initial: State,
+    expected: State,
+    cycles: Vec<Cycle>,
+}
+
+fn number<T: TryFrom<u64>>(json: &Json, what: &str) -> Result<T, String>{
+    json.as_u64().and_then(|value| T::try_from(value).ok()).ok_or_else(|| format!("{} is not a valid number", what))
+}
+
+fn field<'a>(json: &'a Json, key: &str) -> Result<&'a Json, String>{
+    json.get(key).ok_or_else(|| format!("missing {}", key))
+}
+
+fn state(json: &Json) -> Result<State, String>{
+    let byte = |key: &str| number::<u8>(field(json, key)?, key);
+    let ram = field(json, "ram")?.as_array().ok_or("ram is not an array")?.iter().map(|entry| match entry.as_array() {
+        Some([addr, value]) => Ok((number(addr, "ram address")?, number(value, "ram value")?)),
+        _ => Err("ram entries are [address, value]".to_string()),
+    }).collect::<Result<_, String>>()?;
+    Ok(State{ pc: number(field(json, "pc")?, "pc")?, s: byte("s")?, a: byte("a")?, x: byte("x")?, y: byte("y")?, p: byte("p")?, ram })
+}
+
+fn case(json: &Json) -> Result<Case, String>{
+    let cycles = field(json, "cycles")?.as_array().ok_or("cycles is not an array")?.iter().map(|entry| match entry.as_array() {
+        Some([addr, value, kind]) => Ok(Cycle{
+            addr: number(addr, "cycle address")?,
+            value: number(value, "cycle value")?,
+            write: match kind.as_str() {
+                Some("read") => false,
+                Some("write") => true,
+                _ => return Err("cycle kind is read or write".to_string()),
+            },
+        }),
+        _ => Err("cycles are [address, value, kind]".to_string()),
+    }).collect::<Result<_, String>>()?;
+    Ok(Case{
+        name: field(json, "name")?.as_str().unwrap_or_default().to_string(),
+        initial: state(field(json, "initial")?)?,
+        expected: state(field(json, "final")?)?,
+        cycles,
+    })
+}
+
+// Flat memory recording every access in order
+struct Recorder{
+    mem: Memory,
+    cycles: Vec<Cycle>,
+}
+
+impl Bus for Recorder{
+    fn read(&mut self, addr: u16) -> u8{
+        let value = self.mem.read(addr);
+        self.cycles.push(Cycle{ addr, value, write: false });
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8){
+        self.cycles.push(Cycle{ addr, value, write: true });
+        self.mem.write(addr, value);
+    }
+
+    fn peek(&self, addr: u16) -> u8{
+        self.mem.read(addr)
+    }
+}
+
+// First difference from the expected outcome, None when the case passed
+fn check(test: &Case, bus: &mut Recorder, variant: Variant, cycles: bool) -> Option<String>{
+    let initial = &test.initial;
+    for &(addr, value) in &initial.ram {
+        bus.mem.write(addr, value);
+    }
+    bus.cycles.clear();
+    let mut cpu = Processor::with_variant(variant);
+    cpu.pc = initial.pc;
+    cpu.s = initial.s;
+    cpu.a = initial.a;
+    cpu.x = initial.x;
+    cpu.y = initial.y;
+    cpu.p = initial.p;
+    let result = cpu.step(bus);
+
+    let expected = &test.expected;
+    let registers = [("pc", cpu.pc, expected.pc), ("s", cpu.s as u16, expected.s as u16), ("a", cpu.a as u16, expected.a as u16),
+        ("x", cpu.x as u16, expected.x as u16), ("y", cpu.y as u16, expected.y as u16), ("p", cpu.p as u16, expected.p as u16)];
+    let mut failure = match result {
+        Err(err) => Some(err.to_string()),
+        Ok(_) => registers.iter().find(|(_, actual, wanted)| actual != wanted)
+            .map(|(name, actual, wanted)| format!("{} is ${:02X}, expected ${:02X}", name, actual, wanted)),
+    };
+    failure = failure.or_else(|| expected.ram.iter().find(|&&(addr, value)| bus.mem.read(addr) != value)
+        .map(|&(addr, value)| format!("${:04X} is ${:02X}, expected ${:02X}", addr, bus.mem.read(addr), value)));
+    if cycles && failure.is_none() && bus.cycles != test.cycles {
+        let at = bus.cycles.iter().zip(&test.cycles).position(|(actual, wanted)| actual != wanted)
+            .unwrap_or(bus.cycles.len().min(test.cycles.len()));
+        let show = |cycle: Option<&Cycle>| cycle.map_or("nothing".to_string(), |cycle|
+            format!("{} ${:04X} = ${:02X}", if cycle.write { "write" } else { "read" }, cycle.addr, cycle.value));
+        failure = Some(format!("cycle {} is {}, expected {}", at + 1, show(bus.cycles.get(at)), show(test.cycles.get(at))));
+    }
+
+    // Only the touched bytes are cleared so the memory can be reused for the next case
+    let touched: Vec<u16> = initial.ram.iter().map(|&(addr, _)| addr).chain(bus.cycles.iter().map(|cycle| cycle.addr)).collect();
+    for addr in touched {
+        bus.mem.write(addr, 0);
+    }
+    failure
+}
+
+// Runs <dir>/00.json to ff.json against a fresh CPU each, bus cycles are compared one by one when asked.
+// Prints a line per failing opcode and a total, missing files are skipped. JAM is skipped too since the
+// core stops with CpuError::Jammed instead of running the bus cycles of a locked up chip.
+pub fn singlestep(dir: &Path, variant: Variant, cycles: bool, only: Option<u8>) -> Result<(), Box<dyn Error>>{
+    if !dir.is_dir() {
+        println!("single step tests skipped, {} not found", dir.display());
+        return Ok(());
+    }
+    let mut bus = Recorder{ mem: Memory::new(), cycles: Vec::new() };
+    let (mut files, mut passed, mut failed, mut failed_opcodes) = (0, 0, 0, 0);
+    let mut skipped = Vec::new();
+    for opcode in (0..=255u8).filter(|&opcode| only.is_none_or(|only| only == opcode)) {
+        let path = dir.join(format!("{:02x}.json", opcode));
+        if !path.is_file() {
+            continue;
+        }
+        if opcodes(variant)[opcode as usize].mnemonic == Mnemonic::Jam {
+            skipped.push(format!("${:02X}", opcode));
+            continue;
+        }
+        let text = fs::read_to_string(&path)?;
+        let json = json::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let tests = json.as_array().ok_or_else(|| format!("{}: expected an array of tests", path.display()))?;
+        files += 1;
+
+        let mut failures = Vec::new();
+        for test in tests {
+            let test = case(test).map_err(|err| format!("{}: {}", path.display(), err))?;
+            match check(&test, &mut bus, variant, cycles) {
+                None => passed += 1,
+                Some(failure) => failures.push((test.name, failure)),
+            }
+        }
+        if !failures.is_empty() {
+            failed += failures.len();
+            failed_opcodes += 1;
+            println!("${:02X} {}: {} of {} failed", opcode, opcodes(variant)[opcode as usize].mnemonic, failures.len(), tests.len());
+            for (name, failure) in failures.iter().take(SHOWN_FAILURES) {
+                println!("    {}: {}", name, failure);
+            }
+        }
+    }
+    if !skipped.is_empty() {
+        println!("skipped JAM {}", skipped.join(" "));
+    }
+    println!("single step tests: {} opcode files, {} cases passed, {} failed in {} opcodes", files, passed, failed, failed_opcodes);
+    if failed > 0 {
+        return Err(format!("{} single step cases failed", failed).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,187 @@
+use std::error::Error;
+use std::fmt::Display;
+
+use crate::crc::crc32;
+
+const MAGIC: [u8; 4] = *b"NSAV";
+// Bumped whenever the layout of any component changes
+pub const VERSION: u16 = 1;
+// Magic, version, ROM hash and payload length
+const HEADER: usize = 14;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError{
+    BadMagic([u8; 4]),
+    UnsupportedVersion(u16),
+    // The state was saved with a different cartridge
+    RomMismatch{ expected: u32, actual: u32 },
+    BadChecksum,
+    Truncated,
+    // A field holds a value the component cannot take
+    Invalid(&'static str),
+}
+
+impl Display for StateError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::BadMagic(magic) => write!(f, "not a save state, magic {:02X?}", magic),
+            StateError::UnsupportedVersion(version) => write!(f, "save state version {} is not supported, expected {}", version, VERSION),
+            StateError::RomMismatch{ expected, actual } => write!(f, "save state is for ROM {:08X}, loaded ROM is {:08X}", actual, expected),
+            StateError::BadChecksum => write!(f, "save state checksum mismatch"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(what) => write!(f, "save state has an invalid {}", what),
+        }
+    }
+}
+
+impl Error for StateError{}
+
+// Little endian field writer, the layout is the order of the calls
+#[derive(Debug, Default)]
+pub struct StateWriter{
+    data: Vec<u8>,
+}
+
+impl StateWriter{
+
+    pub fn new() -> StateWriter{
+        StateWriter::default()
+    }
+
+    pub fn u8(&mut self, value: u8){
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool){
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16){
+        self.data.extend(value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32){
+        self.data.extend(value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64){
+        self.data.extend(value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, value: &[u8]){
+        self.data.extend(value);
+    }
+
+    // Length prefixed, for buffers whose size depends on the cartridge
+    pub fn vec(&mut self, value: &[u8]){
+        self.u32(value.len() as u32);
+        self.bytes(value);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8>{
+        self.data
+    }
+}
+
+pub struct StateReader<'a>{
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a>{
+
+    pub fn new(data: &'a [u8]) -> StateReader<'a>{
+        StateReader{ data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError>{
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.data.len()).ok_or(StateError::Truncated)?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError>{
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError>{
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("flag")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError>{
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError>{
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError>{
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn bytes(&mut self, out: &mut [u8]) -> Result<(), StateError>{
+        out.copy_from_slice(self.take(out.len())?);
+        Ok(())
+    }
+
+    // Length prefixed buffer, its length must match the one it replaces
+    pub fn vec(&mut self, out: &mut [u8]) -> Result<(), StateError>{
+        if self.u32()? as usize != out.len() {
+            return Err(StateError::Invalid("buffer length"));
+        }
+        self.bytes(out)
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.pos == self.data.len()
+    }
+}
+
+// Component whose whole state can be written out and read back
+pub trait Snapshot{
+    fn save(&self, w: &mut StateWriter);
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>;
+}
+
+// Wraps a payload with the header and a CRC-32 of everything before it
+pub fn encode(rom_hash: u32, payload: &[u8]) -> Vec<u8>{
+    let mut out = Vec::with_capacity(HEADER + payload.len() + 4);
+    out.extend(MAGIC);
+    out.extend(VERSION.to_le_bytes());
+    out.extend(rom_hash.to_le_bytes());
+    out.extend((payload.len() as u32).to_le_bytes());
+    out.extend(payload);
+    out.extend(crc32(&out).to_le_bytes());
+    out
+}
+
+// Checks the header and checksum and returns the payload
+pub fn decode(data: &[u8], rom_hash: u32) -> Result<&[u8], StateError>{
+    let mut r = StateReader::new(data);
+    let mut magic = [0; 4];
+    r.bytes(&mut magic)?;
+    if magic != MAGIC {
+        return Err(StateError::BadMagic(magic));
+    }
+    let version = r.u16()?;
+    if version != VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+    let actual = r.u32()?;
+    let len = r.u32()? as usize;
+    let body = HEADER.checked_add(len).filter(|&end| end + 4 <= data.len()).ok_or(StateError::Truncated)?;
+    let crc = u32::from_le_bytes(data[body..body + 4].try_into().unwrap());
+    if crc != crc32(&data[..body]) {
+        return Err(StateError::BadChecksum);
+    }
+    if actual != rom_hash {
+        return Err(StateError::RomMismatch{ expected: rom_hash, actual });
+    }
+    Ok(&data[HEADER..body])
+}
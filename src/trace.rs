@@ -0,0 +1,63 @@
+use crate::bus::Bus;
+use crate::disasm::{Instruction, disassemble_variant};
+use crate::op::{Legality, Mnemonic, Mode};
+use crate::processor::Processor;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat{
+    // Current nestest.log: PPU:scanline,dot and the CPU cycle count
+    Nintendulator,
+    // Older nestest.log: the PPU dot as CYC and the scanline as SL
+    Nestest,
+}
+
+// Where the rest of the machine is when the instruction starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceClock{
+    pub scanline: u16,
+    pub dot: u16,
+    pub cycles: u64,
+}
+
+// Disassembly with the values nestest shows next to the operand, e.g. LDA ($89),Y = 0300 @ 0300 = 89
+fn annotated<B: Bus>(instruction: &Instruction, cpu: &Processor, bus: &B) -> String{
+    // nestest spells ISC as ISB
+    let mnemonic = match instruction.mnemonic {
+        Mnemonic::Isc => "ISB".to_string(),
+        mnemonic => mnemonic.to_string(),
+    };
+    let operand = instruction.operand();
+    // Like Nintendulator, memory mapped registers are not looked at and show as FF
+    let value = |addr: u16| if (0x2000..0x4020).contains(&addr) { 0xFF } else { bus.peek(addr) };
+    let ptr = instruction.bytes[1];
+    let text = match (instruction.mode, instruction.effective(cpu, bus)) {
+        (Mode::Absolute, _) if matches!(instruction.mnemonic, Mnemonic::Jmp | Mnemonic::Jsr) => operand,
+        (Mode::ZeroPage | Mode::Absolute, Some(addr)) => format!("{} = {:02X}", operand, value(addr)),
+        (Mode::ZeroPageX | Mode::ZeroPageY, Some(addr)) => format!("{} @ {:02X} = {:02X}", operand, addr, value(addr)),
+        (Mode::AbsoluteX | Mode::AbsoluteY, Some(addr)) => format!("{} @ {:04X} = {:02X}", operand, addr, value(addr)),
+        // nestest reads the pointer across the page even though the NMOS jump wraps within it
+        (Mode::Indirect, _) => format!("{} = {:04X}", operand, bus.peek_u16(instruction.target().unwrap_or(0))),
+        (Mode::IndirectX, Some(addr)) => format!("{} @ {:02X} = {:04X} = {:02X}", operand, ptr.wrapping_add(cpu.x), addr, value(addr)),
+        (Mode::IndirectY, Some(addr)) => {
+            let base = addr.wrapping_sub(cpu.y as u16);
+            format!("{} = {:04X} @ {:04X} = {:02X}", operand, base, addr, value(addr))
+        },
+        _ => operand,
+    };
+    format!("{} {}", mnemonic, text).trim_end().to_string()
+}
+
+// Trace line for the instruction at PC, before it runs, column for column as in nestest.log
+pub fn trace_line<B: Bus>(cpu: &Processor, bus: &B, clock: TraceClock, format: TraceFormat) -> String{
+    let instruction = disassemble_variant(bus, cpu.pc, cpu.variant);
+    let bytes: Vec<String> = instruction.bytes().iter().map(|byte| format!("{:02X}", byte)).collect();
+    let marker = if instruction.legality == Legality::Legal { ' ' } else { '*' };
+    let registers = format!("A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}", cpu.a, cpu.x, cpu.y, cpu.p, cpu.s);
+    // The pre-render line shows as -1
+    let scanline = if clock.scanline == 261 { -1 } else { clock.scanline as i32 };
+    let timing = match format {
+        TraceFormat::Nintendulator => format!("PPU:{:>3},{:>3} CYC:{}", scanline, clock.dot, clock.cycles),
+        TraceFormat::Nestest => format!("CYC:{:>3} SL:{}", clock.dot, scanline),
+    };
+    format!("{:04X}  {:<8} {}{:<31} {} {}", cpu.pc, bytes.join(" "), marker, annotated(&instruction, cpu, bus), registers, timing)
+}
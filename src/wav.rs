@@ -0,0 +1,37 @@
+use std::io;
+use std::path::Path;
+
+// Mono 16-bit PCM, samples are clamped to -1.0..=1.0
+pub fn encode(sample_rate: u32, samples: &[f32]) -> io::Result<Vec<u8>>{
+    let invalid = |message| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let byte_rate = sample_rate.checked_mul(2).filter(|_| sample_rate > 0).ok_or_else(|| invalid("sample rate does not fit a WAV header"))?;
+    // The RIFF size counts 36 header bytes besides the data
+    let data_len = samples.len().checked_mul(2).and_then(|len| u32::try_from(len).ok()).filter(|len| len.checked_add(36).is_some())
+        .ok_or_else(|| invalid("too many samples for a WAV file"))?;
+    let mut out = Vec::with_capacity(44 + data_len as usize);
+    out.extend(b"RIFF");
+    out.extend((36 + data_len).to_le_bytes());
+    out.extend(b"WAVE");
+
+    out.extend(b"fmt ");
+    out.extend(16u32.to_le_bytes());
+    // PCM, one channel
+    out.extend(1u16.to_le_bytes());
+    out.extend(1u16.to_le_bytes());
+    out.extend(sample_rate.to_le_bytes());
+    // Byte rate and block align
+    out.extend(byte_rate.to_le_bytes());
+    out.extend(2u16.to_le_bytes());
+    out.extend(16u16.to_le_bytes());
+
+    out.extend(b"data");
+    out.extend(data_len.to_le_bytes());
+    for &sample in samples {
+        out.extend(((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes());
+    }
+    Ok(out)
+}
+
+pub fn write<P: AsRef<Path>>(path: P, sample_rate: u32, samples: &[f32]) -> io::Result<()>{
+    std::fs::write(path, encode(sample_rate, samples)?)
+}